@@ -0,0 +1,122 @@
+use std::net::IpAddr;
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use hyper::{Method, StatusCode, Uri, Version};
+use log::info;
+use serde_json::json;
+
+/// Format of the line logged for every request by the access log
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AccessLogFormat {
+    /// One JSON object per line, for log aggregators. It includes the latency of the request
+    Json,
+    /// Apache's combined log format, so existing log tooling can parse it. It has no latency, and
+    /// like the JSON format it logs the path without the query, which may carry credentials
+    Combined,
+}
+
+/// Subject of an authenticated request, kept in the extensions of its response so the access log
+/// can read it once the response is finalized
+#[derive(Debug, Clone)]
+pub(crate) struct AuthenticatedSubject(pub String);
+
+/// What is logged of a request once its response is finalized
+pub(crate) struct AccessLogEntry<'a> {
+    pub time: DateTime<Utc>,
+    pub client_ip: IpAddr,
+    pub method: &'a Method,
+    pub uri: &'a Uri,
+    pub version: Version,
+    pub status: StatusCode,
+    pub latency: Duration,
+    pub subject: Option<&'a str>,
+    /// Size of the response body, unknown for streamed bodies
+    pub response_size: Option<u64>,
+    pub referer: Option<&'a str>,
+    pub user_agent: Option<&'a str>,
+}
+
+impl AccessLogFormat {
+    pub(crate) fn log(&self, entry: &AccessLogEntry) {
+        info!("{}", self.format(entry));
+    }
+
+    fn format(&self, entry: &AccessLogEntry) -> String {
+        match self {
+            AccessLogFormat::Json => json!({
+                "time": entry.time.to_rfc3339(),
+                "client_ip": entry.client_ip.to_string(),
+                "method": entry.method.as_str(),
+                "path": entry.uri.path(),
+                "status": entry.status.as_u16(),
+                "latency_ms": entry.latency.as_secs_f64() * 1000.0,
+                "subject": entry.subject,
+                "response_size": entry.response_size,
+            })
+            .to_string(),
+            // %h %l %u %t "%r" %>s %b "%{Referer}i" "%{User-agent}i"
+            AccessLogFormat::Combined => format!(
+                "{} - {} [{}] \"{} {} {:?}\" {} {} \"{}\" \"{}\"",
+                entry.client_ip,
+                entry.subject.unwrap_or("-"),
+                entry.time.format("%d/%b/%Y:%H:%M:%S %z"),
+                entry.method,
+                escape_quoted(entry.uri.path()),
+                entry.version,
+                entry.status.as_u16(),
+                entry
+                    .response_size
+                    .map_or("-".to_string(), |size| size.to_string()),
+                escape_quoted(entry.referer.unwrap_or("-")),
+                escape_quoted(entry.user_agent.unwrap_or("-")),
+            ),
+        }
+    }
+}
+
+/// Escapes the quotes and backslashes of a quoted field of the combined format, like Apache does
+fn escape_quoted(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+    use serde_json::Value;
+
+    use super::*;
+
+    #[test]
+    fn access_log_format_test() {
+        let uri = Uri::from_static("/api/orders?page=2&access_token=s3cr3t");
+        let entry = AccessLogEntry {
+            time: Utc.with_ymd_and_hms(2024, 10, 10, 13, 55, 36).unwrap(),
+            client_ip: IpAddr::from([10, 0, 0, 7]),
+            method: &Method::GET,
+            uri: &uri,
+            version: Version::HTTP_11,
+            status: StatusCode::OK,
+            latency: Duration::from_millis(42),
+            subject: Some("alice"),
+            response_size: Some(2326),
+            referer: None,
+            user_agent: Some("curl/8.4.0 \"test\""),
+        };
+
+        assert_eq!(
+            AccessLogFormat::Combined.format(&entry),
+            r#"10.0.0.7 - alice [10/Oct/2024:13:55:36 +0000] "GET /api/orders HTTP/1.1" 200 2326 "-" "curl/8.4.0 \"test\"""#
+        );
+
+        let line: Value = serde_json::from_str(&AccessLogFormat::Json.format(&entry)).unwrap();
+        assert_eq!(line["method"], "GET");
+        assert_eq!(line["path"], "/api/orders");
+        assert_eq!(line["status"], 200);
+        assert_eq!(line["latency_ms"], 42.0);
+        assert_eq!(line["client_ip"], "10.0.0.7");
+        assert_eq!(line["subject"], "alice");
+        assert!(!AccessLogFormat::Combined.format(&entry).contains("s3cr3t"));
+        assert!(!AccessLogFormat::Json.format(&entry).contains("s3cr3t"));
+    }
+}
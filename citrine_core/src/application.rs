@@ -1,31 +1,72 @@
-use log::info;
+#[cfg(feature = "templates")]
+use std::{collections::HashMap, path::Path};
+use std::{
+    future::Future,
+    net::Ipv4Addr,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use hyper::{HeaderMap, Method, StatusCode, Uri};
+use log::{debug, info};
+use serde_json::{json, Value};
+#[cfg(feature = "templates")]
 use tera::Tera;
 
 use crate::{
+    access_log::AccessLogFormat,
     configuration,
-    error::ServerError,
-    middleware::RequestMiddleware,
-    request::Request,
+    cors::CorsConfiguration,
+    drain::DEFAULT_DRAIN_TIMEOUT,
+    error::{ErrorFormat, ErrorHandler, ServerError},
+    middleware::{RequestMiddleware, ResponseMiddleware},
+    multipart::MultipartLimits,
+    request::{FormLimits, Request, RequestMetadata},
+    resolution::ResolutionOrder,
     response::Response,
-    router::{InternalRouter, Router},
-    security::security_configuration::SecurityConfiguration,
-    server::RequestPipelineConfiguration,
-    static_file_server::StaticFileServer,
-    templates,
+    router::{Accepts, GetBodyPolicy, InternalRouter, Router},
+    security::security_configuration::{
+        AuthResult, SecurityConfiguration, SecurityConfigurationHandle,
+    },
+    serializers::ResponseSerializers,
+    server::{self, ReadinessGate, RequestPipelineConfiguration},
+    slow_request_log::SlowRequestLog,
+    tls::TlsConfiguration,
 };
 
+#[cfg(feature = "static-files")]
+use crate::static_file_server::StaticFileServer;
+#[cfg(feature = "templates")]
+use crate::templates;
+
 pub struct Application<T: Send + Sync + 'static> {
     name: String,
     version: String,
     port: u16,
     context: T,
     request_middleware: RequestMiddleware,
+    response_middleware: ResponseMiddleware,
     response_interceptor: fn(&Request, &Response),
     router: InternalRouter<T>,
+    #[cfg(feature = "templates")]
     load_templates: bool,
+    #[cfg(feature = "templates")]
     configure_tera: fn(Tera) -> Tera,
-    security_configuration: SecurityConfiguration,
+    #[cfg(feature = "templates")]
+    error_templates: HashMap<StatusCode, String>,
+    security_configuration: SecurityConfigurationHandle,
+    #[cfg(feature = "static-files")]
     static_file_server: StaticFileServer,
+    resolution_order: ResolutionOrder,
+    readiness_gate: Option<ReadinessGate>,
+    tls: Option<TlsConfiguration>,
+    error_format: ErrorFormat,
+    error_handler: Option<ErrorHandler>,
+    slow_request_log: Option<SlowRequestLog>,
+    access_log: Option<AccessLogFormat>,
+    default_headers: HeaderMap,
+    cors: Option<CorsConfiguration>,
+    drain_timeout: Duration,
 }
 
 impl<T> Application<T>
@@ -40,6 +81,12 @@ where
     }
 
     pub async fn start(self) -> Result<(), ServerError> {
+        let tls_acceptor = match self.tls.as_ref() {
+            Some(tls) => Some(tls.acceptor()?),
+            None => None,
+        };
+
+        #[cfg(feature = "templates")]
         if self.load_templates {
             if let Err(e) = templates::init_templates(self.configure_tera) {
                 panic!("Error loading templates: {}", e);
@@ -53,17 +100,39 @@ where
             "Started application {} v{} (via Citrine)",
             self.name, self.version
         );
+        debug!(
+            "Running with {} runtime worker threads",
+            tokio::runtime::Handle::current().metrics().num_workers()
+        );
+
+        let pipeline_configuration = RequestPipelineConfiguration::new(
+            self.response_interceptor,
+            self.router,
+            self.security_configuration,
+            self.request_middleware,
+            self.response_middleware,
+            self.context,
+        )
+        .with_cors(self.cors)
+        .with_error_handler(self.error_handler)
+        .with_error_format(self.error_format)
+        .with_slow_request_log(self.slow_request_log)
+        .with_access_log(self.access_log)
+        .with_default_headers(self.default_headers)
+        .with_resolution_order(self.resolution_order)
+        .with_drain_timeout(self.drain_timeout);
+        #[cfg(feature = "static-files")]
+        let pipeline_configuration =
+            pipeline_configuration.with_static_file_server(self.static_file_server);
+        #[cfg(feature = "templates")]
+        let pipeline_configuration =
+            pipeline_configuration.with_error_templates(self.error_templates);
 
         crate::server::start(
             self.port,
-            RequestPipelineConfiguration::new(
-                self.response_interceptor,
-                self.router,
-                self.security_configuration,
-                self.static_file_server,
-                self.request_middleware,
-                self.context,
-            ),
+            pipeline_configuration,
+            self.readiness_gate,
+            tls_acceptor,
         )
         .await;
 
@@ -71,18 +140,50 @@ where
     }
 }
 
+/// Tells whether the dependencies of the application, like the database, are reachable, from the
+/// context that holds them
+pub type ReadinessCheck<T> = fn(&T) -> bool;
+
 pub struct ApplicationBuilder<T: Send + Sync + 'static> {
     name: String,
     version: String,
     port: u16,
     context: T,
     request_middleware: RequestMiddleware,
+    response_middleware: ResponseMiddleware,
     response_interceptor: fn(&Request, &Response),
     router: Router<T>,
+    #[cfg(feature = "templates")]
     load_templates: bool,
+    #[cfg(feature = "templates")]
     configure_tera: fn(Tera) -> Tera,
-    security_configuration: SecurityConfiguration,
+    #[cfg(feature = "templates")]
+    error_templates: HashMap<StatusCode, String>,
+    security_configuration: SecurityConfigurationHandle,
+    #[cfg(feature = "static-files")]
     static_file_server: StaticFileServer,
+    resolution_order: ResolutionOrder,
+    worker_threads: Option<usize>,
+    max_body_size: usize,
+    default_accepts: Option<Accepts>,
+    get_body_policy: GetBodyPolicy,
+    allow_trace_and_connect: bool,
+    trim_strings: bool,
+    form_limits: FormLimits,
+    multipart_limits: MultipartLimits,
+    readiness_gate: Option<ReadinessGate>,
+    tls: Option<TlsConfiguration>,
+    response_serializers: Option<ResponseSerializers>,
+    error_format: ErrorFormat,
+    error_handler: Option<ErrorHandler>,
+    slow_request_log: Option<SlowRequestLog>,
+    access_log: Option<AccessLogFormat>,
+    default_headers: HeaderMap,
+    cors: Option<CorsConfiguration>,
+    drain_timeout: Duration,
+    configuration_endpoint: Option<String>,
+    health_check: Option<String>,
+    readiness_check: Option<(String, ReadinessCheck<T>)>,
 }
 
 impl<T> ApplicationBuilder<T>
@@ -118,13 +219,20 @@ where
     }
 
     pub fn security_configuration(
-        mut self,
+        self,
         security_configuration: SecurityConfiguration,
     ) -> ApplicationBuilder<T> {
-        self.security_configuration = security_configuration;
+        self.security_configuration.set(security_configuration);
         self
     }
 
+    /*
+     * Handle to replace the security configuration while the application is running
+     */
+    pub fn security_configuration_handle(&self) -> SecurityConfigurationHandle {
+        self.security_configuration.clone()
+    }
+
     pub fn router(mut self, router: Router<T>) -> ApplicationBuilder<T> {
         self.router = router;
         self
@@ -136,6 +244,7 @@ where
      * when running with debug_assertions to reflect changes in template code, but this will not
      * be the case when running in production mode
      */
+    #[cfg(feature = "templates")]
     pub fn configure_tera(mut self, configuration: fn(Tera) -> Tera) -> Self {
         self.configure_tera = configuration;
         // doesn't make sense to configure tera and not enable it
@@ -143,11 +252,216 @@ where
         self
     }
 
+    #[cfg(feature = "static-files")]
     pub fn serve_static_files(mut self, static_file_server: StaticFileServer) -> Self {
         self.static_file_server = static_file_server;
         self
     }
 
+    /*
+     * Order in which static files, the router and a single page application index are tried to
+     * answer requests, for the whole application or per path prefix. Defaults to static files
+     * first and then the router
+     */
+    pub fn resolution_order(mut self, resolution_order: ResolutionOrder) -> Self {
+        self.resolution_order = resolution_order;
+        self
+    }
+
+    /*
+     * Maximum size in bytes of request bodies, for the routes that don't set their own limit.
+     * Bigger requests are rejected with a 413 Payload Too Large. Defaults to 2 MiB
+     */
+    pub fn max_body_size(mut self, max_body_size: usize) -> Self {
+        self.max_body_size = max_body_size;
+        self
+    }
+
+    /*
+     * Content types accepted by the routes added with the post, put, patch and delete helpers,
+     * instead of their defaults. Routes that set their accepted types themselves keep them
+     */
+    pub fn default_accepts(mut self, accepts: Accepts) -> Self {
+        self.default_accepts = Some(accepts);
+        self
+    }
+
+    /*
+     * Whether bodies sent in GET and HEAD requests to routes that don't accept any are ignored,
+     * the default, or rejected with a 400 Bad Request
+     */
+    pub fn get_body_policy(mut self, get_body_policy: GetBodyPolicy) -> Self {
+        self.get_body_policy = get_body_policy;
+        self
+    }
+
+    /*
+     * TRACE and CONNECT requests are answered with a 501 Not Implemented by default, as TRACE
+     * enables cross-site tracing attacks. This lets them reach the routes defined for them
+     */
+    pub fn allow_trace_and_connect(mut self) -> Self {
+        self.allow_trace_and_connect = true;
+        self
+    }
+
+    /*
+     * Leading and trailing whitespace is removed from the string fields of JSON and form bodies
+     * before they are deserialized with get_body and get_body_validated, so values like padded
+     * emails don't fail validation or end up stored with the whitespace
+     */
+    pub fn trim_strings(mut self) -> Self {
+        self.trim_strings = true;
+        self
+    }
+
+    /*
+     * Limits on the number and size of the fields of form-urlencoded bodies, checked before they
+     * are deserialized. Forms that exceed them are rejected with a 413 Payload Too Large.
+     * Defaults to 1000 fields
+     */
+    pub fn form_limits(mut self, form_limits: FormLimits) -> Self {
+        self.form_limits = form_limits;
+        self
+    }
+
+    /*
+     * Limits on the number of parts, the size of each part and the total size of multipart
+     * bodies, checked while they are parsed with get_multipart. Bodies that exceed them are
+     * rejected with a 413 Payload Too Large. Defaults to 100 parts
+     */
+    pub fn multipart_limits(mut self, multipart_limits: MultipartLimits) -> Self {
+        self.multipart_limits = multipart_limits;
+        self
+    }
+
+    /*
+     * Custom JSON serializers for specific types, used by Response::json_registered instead of
+     * their Serialize implementation
+     */
+    pub fn response_serializers(mut self, response_serializers: ResponseSerializers) -> Self {
+        self.response_serializers = Some(response_serializers);
+        self
+    }
+
+    /*
+     * Shape of the body of error responses. ErrorFormat::ProblemJson sends RFC 7807 problem
+     * details as application/problem+json instead of the default body
+     */
+    pub fn error_format(mut self, error_format: ErrorFormat) -> Self {
+        self.error_format = error_format;
+        self
+    }
+
+    /*
+     * Renders the errors of the framework, like 404, 405, 415 or 401, instead of the default
+     * body. It receives the method, URI and headers of the request, so it can answer with HTML
+     * pages to browsers and JSON to API clients
+     */
+    pub fn error_handler(mut self, error_handler: ErrorHandler) -> Self {
+        self.error_handler = Some(error_handler);
+        self
+    }
+
+    /*
+     * HTML page for the errors with the status, rendered from the template with the status, its
+     * reason and the cause of the error when the client accepts HTML. Other clients, or a
+     * template that fails to render, get the JSON body. An error handler takes precedence
+     */
+    #[cfg(feature = "templates")]
+    pub fn error_template(mut self, status: StatusCode, template_name: &str) -> Self {
+        self.error_templates
+            .insert(status, template_name.to_string());
+        // error templates are rendered with tera
+        self.load_templates = true;
+        self
+    }
+
+    /*
+     * Logs a warning for the requests that go over any of the thresholds, like a latency of more
+     * than a second or a response body of more than 10 MiB, to find the problematic endpoints
+     */
+    pub fn slow_request_log(mut self, slow_request_log: SlowRequestLog) -> Self {
+        self.slow_request_log = Some(slow_request_log);
+        self
+    }
+
+    /*
+     * Logs one line per request with its method, path, status, latency, client IP and
+     * authenticated subject, as JSON or in Apache's combined log format
+     */
+    pub fn access_log(mut self, access_log: AccessLogFormat) -> Self {
+        self.access_log = Some(access_log);
+        self
+    }
+
+    /*
+     * Headers added to every response, like a version header or security headers. Headers set
+     * by the handlers, the middlewares or CORS win over these
+     */
+    pub fn default_headers(mut self, default_headers: HeaderMap) -> Self {
+        self.default_headers = default_headers;
+        self
+    }
+
+    /*
+     * Answers CORS preflight requests and adds the Access-Control-Allow-* headers to the
+     * responses to the allowed origins
+     */
+    pub fn cors(mut self, cors: CorsConfiguration) -> Self {
+        self.cors = Some(cors);
+        self
+    }
+
+    /*
+     * Time in-flight connections get to finish after a shutdown signal before the server stops
+     * waiting for them. Defaults to 10 seconds
+     */
+    pub fn drain_timeout(mut self, drain_timeout: Duration) -> Self {
+        self.drain_timeout = drain_timeout;
+        self
+    }
+
+    /*
+     * Adds a GET endpoint at the path that returns the effective configuration of the
+     * application, for troubleshooting deployed environments. Secrets are never included. The
+     * path must be protected by a security rule that authenticates requests, ideally requiring an
+     * admin role, or the application will not start. Unauthenticated requests get a 404
+     */
+    pub fn configuration_endpoint(mut self, path: &str) -> Self {
+        self.configuration_endpoint = Some(path.to_string());
+        self
+    }
+
+    /*
+     * Adds a GET endpoint at the path for liveness probes, like /health, that answers with a 200
+     * and the uptime of the application. It skips the security rules, as probes don't carry
+     * credentials
+     */
+    pub fn health_check(mut self, path: &str) -> Self {
+        self.health_check = Some(path.to_string());
+        self
+    }
+
+    /*
+     * Adds a GET endpoint at the path for readiness probes, like /readyz. It answers with a 200
+     * when the check passes and a 503 when it doesn't, so the check can ping the database pool
+     * or other dependencies of the context instead of relying on a static flag. Like the health
+     * check, it skips the security rules
+     */
+    pub fn readiness_check(mut self, path: &str, readiness_check: ReadinessCheck<T>) -> Self {
+        self.readiness_check = Some((path.to_string(), readiness_check));
+        self
+    }
+
+    /*
+     * Serves the application over HTTPS with the given certificate and key instead of plain HTTP
+     */
+    pub fn tls(mut self, tls: TlsConfiguration) -> Self {
+        self.tls = Some(tls);
+        self
+    }
+
+    #[cfg(feature = "templates")]
     pub fn load_templates(mut self) -> Self {
         self.load_templates = true;
         self
@@ -158,23 +472,216 @@ where
         self
     }
 
+    pub fn response_middleware(mut self, response_middleware: ResponseMiddleware) -> Self {
+        self.response_middleware = response_middleware;
+        self
+    }
+
+    /*
+     * The server will start listening right away, but it will respond to every request with a
+     * 503 until this future resolves. Useful for warming caches or running migrations on startup
+     */
+    pub fn readiness_gate(
+        mut self,
+        readiness_gate: impl Future<Output = ()> + Send + 'static,
+    ) -> Self {
+        self.readiness_gate = Some(Box::pin(readiness_gate));
+        self
+    }
+
+    /*
+     * Number of worker threads of the runtime built by run. It has no effect when the application
+     * is started with start, as it will use the runtime it is awaited on
+     */
+    pub fn worker_threads(mut self, worker_threads: usize) -> Self {
+        self.worker_threads = Some(worker_threads);
+        self
+    }
+
+    pub fn run(self) -> Result<(), ServerError> {
+        let runtime = self.build_runtime()?;
+        runtime.block_on(self.start())
+    }
+
+    fn build_runtime(&self) -> Result<tokio::runtime::Runtime, ServerError> {
+        let mut runtime_builder = tokio::runtime::Builder::new_multi_thread();
+        runtime_builder.enable_all();
+        if let Some(worker_threads) = self.worker_threads {
+            if worker_threads == 0 {
+                return Err(ServerError::from(
+                    "Worker thread count must be greater than 0",
+                ));
+            }
+            runtime_builder.worker_threads(worker_threads);
+        }
+
+        Ok(runtime_builder.build()?)
+    }
+
+    /*
+     * Checks the whole configuration and returns an error listing every problem found, so
+     * misconfigurations are reported on startup instead of when the first request arrives
+     */
+    pub fn validate(&self) -> Result<(), ServerError> {
+        let mut problems = vec![];
+
+        if let Err(e) = InternalRouter::from(self.router.clone()) {
+            problems.push(e.to_string());
+        }
+        problems.extend(self.security_configuration.current().validate());
+        problems.extend(self.request_middleware.validate());
+        problems.extend(self.response_middleware.validate());
+        #[cfg(feature = "static-files")]
+        problems.extend(self.static_file_server.validate());
+        if let Some(Err(e)) = self.tls.as_ref().map(TlsConfiguration::acceptor) {
+            problems.push(e.to_string());
+        }
+        if let Some(path) = self.configuration_endpoint.as_ref() {
+            let protected = path.parse::<Uri>().is_ok_and(|uri| {
+                self.security_configuration
+                    .current()
+                    .requires_authentication(&RequestMetadata::new(
+                        Method::GET,
+                        uri,
+                        HeaderMap::new(),
+                    ))
+            });
+            if !protected {
+                problems.push(format!(
+                    "The configuration endpoint {} is not protected by a security rule that authenticates requests",
+                    path
+                ));
+            }
+        }
+        #[cfg(feature = "templates")]
+        if self.load_templates {
+            problems.extend(templates_folder_problem(
+                &configuration::templates_folder_or_default(),
+            ));
+        }
+
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(ServerError::from(format!(
+                "Invalid application configuration:\n - {}",
+                problems.join("\n - ")
+            )))
+        }
+    }
+
+    /*
+     * Configuration the application will run with, as returned by the configuration endpoint
+     */
+    fn effective_configuration(&self) -> Value {
+        let routes = self
+            .router
+            .routes
+            .iter()
+            .map(|route| format!("{} {}", route.method, route.path))
+            .collect::<Vec<String>>();
+        #[allow(unused_mut)]
+        let mut effective_configuration = json!({
+            "name": self.name,
+            "version": self.version,
+            "host": Ipv4Addr::from(server::HOST).to_string(),
+            "port": self.port,
+            "tls": self.tls.is_some(),
+            "worker_threads": self.worker_threads,
+            "max_body_size": self.max_body_size,
+            "get_body_policy": format!("{:?}", self.get_body_policy),
+            "allow_trace_and_connect": self.allow_trace_and_connect,
+            "trim_strings": self.trim_strings,
+            "form_limits": format!("{:?}", self.form_limits),
+            "multipart_limits": format!("{:?}", self.multipart_limits),
+            "error_format": format!("{:?}", self.error_format),
+            "cors": self.cors.is_some(),
+            "slow_request_log": self.slow_request_log.is_some(),
+            "access_log": self.access_log.map(|format| format!("{:?}", format)),
+            "default_headers": self
+                .default_headers
+                .keys()
+                .map(|name| name.as_str())
+                .collect::<Vec<&str>>(),
+            "drain_timeout_seconds": self.drain_timeout.as_secs(),
+            "features": enabled_features(),
+            "routes": routes,
+            "security_rules": self.security_configuration.current().summary(),
+        });
+        #[cfg(feature = "templates")]
+        {
+            effective_configuration["templates_enabled"] = json!(self.load_templates);
+            effective_configuration["templates_folder"] =
+                json!(configuration::templates_folder_or_default());
+        }
+        effective_configuration
+    }
+
     pub async fn start(self) -> Result<(), ServerError> {
-        let internal_router_res = InternalRouter::from(self.router);
+        self.validate()?;
+
+        let mut router = match self.configuration_endpoint.as_ref() {
+            Some(path) => {
+                let effective_configuration = self.effective_configuration();
+                self.router
+                    .get(path, move |_, request| match request.auth_result {
+                        AuthResult::Authenticated(_) | AuthResult::CustomAuthenticated(_) => {
+                            Response::new(StatusCode::OK).json(effective_configuration.clone())
+                        }
+                        _ => Response::new(StatusCode::NOT_FOUND),
+                    })
+            }
+            None => self.router,
+        };
+        router = add_probes(router, self.health_check.as_deref(), self.readiness_check);
+        if let Some(default_accepts) = self.default_accepts.as_ref() {
+            router.apply_default_accepts(default_accepts);
+        }
+        let internal_router_res = InternalRouter::from(router);
         if let Err(e) = internal_router_res {
             return Err(ServerError::from(e));
         }
+        let mut router = internal_router_res.unwrap();
+        router.set_default_max_body_size(self.max_body_size);
+        router.set_get_body_policy(self.get_body_policy);
+        router.set_allow_trace_and_connect(self.allow_trace_and_connect);
+        router.set_trim_strings(self.trim_strings);
+        router.set_form_limits(self.form_limits);
+        router.set_multipart_limits(self.multipart_limits);
+        router.set_error_handler(self.error_handler);
+        router.set_error_format(self.error_format);
+        #[cfg(feature = "templates")]
+        router.set_error_templates(self.error_templates.clone());
+        router.set_response_serializers(self.response_serializers);
+
         Application {
             name: self.name,
             version: self.version,
             port: self.port,
             context: self.context,
             request_middleware: self.request_middleware,
+            response_middleware: self.response_middleware,
             response_interceptor: self.response_interceptor,
-            router: internal_router_res.unwrap(),
+            router,
+            #[cfg(feature = "templates")]
             load_templates: self.load_templates,
+            #[cfg(feature = "templates")]
             configure_tera: self.configure_tera,
+            #[cfg(feature = "templates")]
+            error_templates: self.error_templates,
             security_configuration: self.security_configuration,
+            #[cfg(feature = "static-files")]
             static_file_server: self.static_file_server,
+            resolution_order: self.resolution_order,
+            readiness_gate: self.readiness_gate,
+            tls: self.tls,
+            error_format: self.error_format,
+            error_handler: self.error_handler,
+            slow_request_log: self.slow_request_log,
+            access_log: self.access_log,
+            default_headers: self.default_headers,
+            cors: self.cors,
+            drain_timeout: self.drain_timeout,
         }
         .start()
         .await
@@ -192,12 +699,297 @@ where
             port: configuration::port_or_default(),
             context: T::default(),
             request_middleware: RequestMiddleware::default(),
+            response_middleware: ResponseMiddleware::default(),
             response_interceptor: |_, _| {},
             router: Router::new(),
+            #[cfg(feature = "templates")]
             load_templates: configuration::templates_enabled_or_default(),
+            #[cfg(feature = "templates")]
             configure_tera: |t| t,
-            security_configuration: SecurityConfiguration::new(),
+            #[cfg(feature = "templates")]
+            error_templates: HashMap::new(),
+            security_configuration: SecurityConfigurationHandle::default(),
+            #[cfg(feature = "static-files")]
             static_file_server: StaticFileServer::default(),
+            resolution_order: ResolutionOrder::default(),
+            worker_threads: None,
+            max_body_size: configuration::max_body_size_or_default(),
+            default_accepts: None,
+            get_body_policy: GetBodyPolicy::default(),
+            allow_trace_and_connect: false,
+            trim_strings: false,
+            form_limits: FormLimits::default(),
+            multipart_limits: MultipartLimits::default(),
+            readiness_gate: None,
+            tls: None,
+            response_serializers: None,
+            error_format: ErrorFormat::default(),
+            error_handler: None,
+            slow_request_log: None,
+            access_log: None,
+            default_headers: HeaderMap::new(),
+            cors: None,
+            drain_timeout: DEFAULT_DRAIN_TIMEOUT,
+            configuration_endpoint: None,
+            health_check: None,
+            readiness_check: None,
+        }
+    }
+}
+
+fn health_handler<T: Send + Sync + 'static>(
+    started: Instant,
+) -> impl Fn(Arc<T>, Request) -> Response + Send + Sync + 'static {
+    move |_, _| {
+        Response::new(StatusCode::OK).json(json!({
+            "status": "up",
+            "uptime_seconds": started.elapsed().as_secs(),
+        }))
+    }
+}
+
+/// Adds the endpoints of the health and readiness probes. They skip the security rules, as probes
+/// don't carry credentials
+pub(crate) fn add_probes<T: Send + Sync + 'static>(
+    mut router: Router<T>,
+    health_check: Option<&str>,
+    readiness_check: Option<(String, ReadinessCheck<T>)>,
+) -> Router<T> {
+    if let Some(path) = health_check {
+        router = router.get(path, health_handler(Instant::now())).public();
+    }
+    if let Some((path, readiness_check)) = readiness_check {
+        router = router
+            .get(&path, readiness_handler(readiness_check))
+            .public();
+    }
+    router
+}
+
+fn readiness_handler<T: Send + Sync + 'static>(
+    readiness_check: ReadinessCheck<T>,
+) -> impl Fn(Arc<T>, Request) -> Response + Send + Sync + 'static {
+    move |context, _| {
+        if readiness_check(&context) {
+            Response::new(StatusCode::OK).json(json!({ "status": "ready" }))
+        } else {
+            Response::new(StatusCode::SERVICE_UNAVAILABLE).json(json!({ "status": "unavailable" }))
+        }
+    }
+}
+
+fn enabled_features() -> Vec<&'static str> {
+    [
+        ("templates", cfg!(feature = "templates")),
+        ("static-files", cfg!(feature = "static-files")),
+        ("jwt", cfg!(feature = "jwt")),
+        ("oidc", cfg!(feature = "oidc")),
+        ("xml", cfg!(feature = "xml")),
+    ]
+    .into_iter()
+    .filter(|(_, enabled)| *enabled)
+    .map(|(feature, _)| feature)
+    .collect()
+}
+
+/// Problem found in the templates folder, which has to exist when templates are enabled
+#[cfg(feature = "templates")]
+fn templates_folder_problem(templates_folder: &str) -> Option<String> {
+    if Path::new(templates_folder).is_dir() {
+        None
+    } else {
+        Some(format!(
+            "Templates are enabled but the templates folder {} does not exist",
+            templates_folder
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[cfg(feature = "static-files")]
+    use std::path::PathBuf;
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    use crate::{
+        request_matcher::MethodMatcher,
+        response::ResponseBody,
+        security::{
+            api_key::ApiKeyConfiguration,
+            security_configuration::{Authenticator, SecurityAction, SecurityRule},
+        },
+    };
+
+    use super::*;
+
+    fn validation_error(builder: ApplicationBuilder<()>) -> String {
+        builder.validate().unwrap_err().to_string()
+    }
+
+    #[test]
+    fn valid_configuration_test() {
+        let builder = Application::<()>::builder()
+            .router(Router::new().get("/hello", |_, _| Response::new(StatusCode::OK)));
+
+        assert!(builder.validate().is_ok());
+    }
+
+    #[test]
+    fn duplicated_route_test() {
+        let builder = Application::<()>::builder().router(
+            Router::new()
+                .get("/hello", |_, _| Response::new(StatusCode::OK))
+                .get("/hello", |_, _| Response::new(StatusCode::OK)),
+        );
+
+        assert!(validation_error(builder).contains("GET /hello"));
+    }
+
+    #[test]
+    fn malformed_security_matcher_test() {
+        let builder = Application::<()>::builder().security_configuration(
+            SecurityConfiguration::new().add_rule(
+                SecurityRule::new()
+                    .add_matcher(MethodMatcher::All, "/api/(*")
+                    .execute_action(SecurityAction::Deny),
+            ),
+        );
+
+        assert!(validation_error(builder).contains("Malformed request matcher /api/(*"));
+    }
+
+    #[test]
+    fn configuration_endpoint_test() {
+        let builder = Application::<()>::builder()
+            .port(9090)
+            .max_body_size(1024)
+            .security_configuration(
+                SecurityConfiguration::new().add_rule(
+                    SecurityRule::new()
+                        .add_matcher(MethodMatcher::All, "/admin/*")
+                        .execute_action(SecurityAction::Authenticate(Authenticator::ApiKey(
+                            Box::new(ApiKeyConfiguration::new().add_key("ops", "sup3r-s3cr3t")),
+                        )))
+                        .require_roles(vec!["admin".to_string()]),
+                ),
+            )
+            .router(Router::new().get("/hello", |_, _| Response::new(StatusCode::OK)))
+            .configuration_endpoint("/admin/configuration");
+        assert!(builder.validate().is_ok());
+
+        let configuration = builder.effective_configuration();
+        assert_eq!(configuration["port"], 9090);
+        assert_eq!(configuration["host"], "127.0.0.1");
+        assert_eq!(configuration["max_body_size"], 1024);
+        assert_eq!(configuration["routes"], json!(["GET /hello"]));
+        assert_eq!(
+            configuration["security_rules"],
+            json!(["All HTTP methods /admin/* | Authenticate with API key from header x-api-key | roles admin"])
+        );
+        assert!(!configuration.to_string().contains("sup3r-s3cr3t"));
+
+        // Without a rule that authenticates it the endpoint would be public
+        let builder = Application::<()>::builder().configuration_endpoint("/configuration");
+        assert!(validation_error(builder).contains("configuration endpoint /configuration"));
+    }
+
+    #[test]
+    fn worker_threads_test() {
+        let runtime = Application::<()>::builder()
+            .worker_threads(3)
+            .build_runtime()
+            .unwrap();
+        assert_eq!(runtime.metrics().num_workers(), 3);
+
+        let builder = Application::<()>::builder().worker_threads(0);
+        assert!(builder.build_runtime().is_err());
+    }
+
+    #[test]
+    fn readiness_check_test() {
+        #[derive(Default)]
+        struct DatabaseContext {
+            database_reachable: AtomicBool,
         }
+
+        let handler = readiness_handler::<DatabaseContext>(|context| {
+            context.database_reachable.load(Ordering::Acquire)
+        });
+        let context = Arc::new(DatabaseContext::default());
+        let request = || {
+            Request::new(
+                Method::GET,
+                Uri::from_static("/readyz"),
+                "",
+                HeaderMap::new(),
+                AuthResult::Allowed,
+            )
+        };
+
+        let response = handler(context.clone(), request());
+        assert_eq!(response.get_status(), StatusCode::SERVICE_UNAVAILABLE);
+
+        context.database_reachable.store(true, Ordering::Release);
+        let response = handler(context, request());
+        assert_eq!(response.get_status(), StatusCode::OK);
+    }
+
+    #[test]
+    fn health_check_test() {
+        let handler = health_handler::<()>(Instant::now() - Duration::from_secs(90));
+        let request = Request::new(
+            Method::GET,
+            Uri::from_static("/health"),
+            "",
+            HeaderMap::new(),
+            AuthResult::Allowed,
+        );
+
+        let response = handler(Arc::new(()), request);
+        assert_eq!(response.get_status(), StatusCode::OK);
+        let Some(ResponseBody::Full(body)) = response.get_body() else {
+            panic!("health check body is not a full body");
+        };
+        let body: Value = serde_json::from_slice(body).unwrap();
+        assert_eq!(body["status"], "up");
+        assert_eq!(body["uptime_seconds"], 90);
+    }
+
+    #[test]
+    #[cfg(feature = "static-files")]
+    fn missing_static_folder_test() {
+        let builder = Application::<()>::builder().serve_static_files(
+            StaticFileServer::new().serve_folder("/", PathBuf::from("./missing_static_folder")),
+        );
+
+        assert!(validation_error(builder).contains("./missing_static_folder"));
+    }
+
+    #[test]
+    #[cfg(feature = "templates")]
+    fn missing_templates_folder_test() {
+        let problem = templates_folder_problem("./missing_templates_folder").unwrap();
+        assert!(problem.contains("templates folder ./missing_templates_folder"));
+
+        // The crate has a templates folder for the tests that render templates
+        assert!(templates_folder_problem("templates").is_none());
+    }
+
+    #[test]
+    #[cfg(feature = "static-files")]
+    fn multiple_problems_test() {
+        let builder = Application::<()>::builder()
+            .router(
+                Router::new()
+                    .get("/hello", |_, _| Response::new(StatusCode::OK))
+                    .get("/hello", |_, _| Response::new(StatusCode::OK)),
+            )
+            .serve_static_files(
+                StaticFileServer::new().serve_folder("/", PathBuf::from("./missing_static_folder")),
+            );
+
+        let error = validation_error(builder);
+        assert!(error.contains("GET /hello"));
+        assert!(error.contains("./missing_static_folder"));
     }
 }
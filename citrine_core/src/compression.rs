@@ -0,0 +1,123 @@
+use std::io::Write;
+
+use flate2::{write::GzEncoder, Compression};
+use hyper::{header::ACCEPT_ENCODING, HeaderMap};
+
+/// Bodies smaller than this are sent uncompressed, as the encoding overhead outweighs the savings
+pub(crate) const MIN_COMPRESSED_SIZE: usize = 256;
+
+/// Brotli quality used for responses compressed on every request. The maximum of 11 is too slow
+/// for that
+const BROTLI_QUALITY: u32 = 5;
+const BROTLI_WINDOW_SIZE: u32 = 22;
+const BROTLI_BUFFER_SIZE: usize = 4096;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Encoding {
+    Brotli,
+    Gzip,
+}
+
+impl Encoding {
+    /// Value of the Content-Encoding header
+    pub(crate) fn as_str(&self) -> &'static str {
+        match self {
+            Encoding::Brotli => "br",
+            Encoding::Gzip => "gzip",
+        }
+    }
+
+    /// Encoding with the highest quality in the Accept-Encoding headers. Brotli is chosen over
+    /// gzip when both have the same quality, as it compresses HTML better
+    pub(crate) fn negotiate(request_headers: &HeaderMap) -> Option<Encoding> {
+        let mut best: Option<(Encoding, u16)> = None;
+        let codings = request_headers
+            .get_all(ACCEPT_ENCODING)
+            .iter()
+            .filter_map(|header| header.to_str().ok())
+            .flat_map(|header| header.split(','));
+        for coding in codings {
+            let mut parts = coding.split(';');
+            let encoding = match parts.next().unwrap_or_default().trim() {
+                name if name.eq_ignore_ascii_case("br") => Encoding::Brotli,
+                name if name.eq_ignore_ascii_case("gzip") => Encoding::Gzip,
+                _ => continue,
+            };
+            // Quality in thousandths, the precision allowed by RFC 9110
+            let quality = parts
+                .find_map(|parameter| parameter.trim().strip_prefix("q="))
+                .and_then(|quality| quality.parse::<f32>().ok())
+                .map(|quality| (quality.clamp(0.0, 1.0) * 1000.0) as u16)
+                .unwrap_or(1000);
+            if quality == 0 {
+                continue;
+            }
+            let is_better = match best {
+                None => true,
+                Some((_, best_quality)) => {
+                    quality > best_quality
+                        || (quality == best_quality && encoding == Encoding::Brotli)
+                }
+            };
+            if is_better {
+                best = Some((encoding, quality));
+            }
+        }
+        best.map(|(encoding, _)| encoding)
+    }
+
+    pub(crate) fn compress(&self, body: &[u8]) -> std::io::Result<Vec<u8>> {
+        match self {
+            Encoding::Brotli => {
+                let mut compressed = Vec::new();
+                let mut writer = brotli::CompressorWriter::new(
+                    &mut compressed,
+                    BROTLI_BUFFER_SIZE,
+                    BROTLI_QUALITY,
+                    BROTLI_WINDOW_SIZE,
+                );
+                writer.write_all(body)?;
+                writer.flush()?;
+                // Dropping the writer finishes the stream
+                drop(writer);
+                Ok(compressed)
+            }
+            Encoding::Gzip => {
+                let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+                encoder.write_all(body)?;
+                encoder.finish()
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use hyper::header::HeaderValue;
+
+    use super::*;
+
+    fn accept_encoding(value: &'static str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(ACCEPT_ENCODING, HeaderValue::from_static(value));
+        headers
+    }
+
+    #[test]
+    fn negotiate_test() {
+        assert_eq!(
+            Encoding::negotiate(&accept_encoding("gzip, deflate, br")),
+            Some(Encoding::Brotli)
+        );
+        assert_eq!(
+            Encoding::negotiate(&accept_encoding("br;q=0.5, gzip")),
+            Some(Encoding::Gzip)
+        );
+        assert_eq!(
+            Encoding::negotiate(&accept_encoding("GZIP, br;q=0")),
+            Some(Encoding::Gzip)
+        );
+        assert_eq!(Encoding::negotiate(&accept_encoding("identity")), None);
+        assert_eq!(Encoding::negotiate(&HeaderMap::new()), None);
+    }
+}
@@ -39,6 +39,21 @@ pub fn templates_folder_or_default() -> String {
     env::var("CITRINE_TEMPLATES_FOLDER").unwrap_or("templates".to_string())
 }
 
+/// Default maximum size of request bodies, 2 MiB
+pub const DEFAULT_MAX_BODY_SIZE: usize = 2 * 1024 * 1024;
+
+pub fn max_body_size_or_default() -> usize {
+    if let Ok(var) = env::var("CITRINE_MAX_BODY_SIZE") {
+        let parse_res = var.parse::<usize>();
+        if let Err(e) = parse_res {
+            panic!("Invalid value for CITRINE_MAX_BODY_SIZE: {}", e);
+        }
+        parse_res.unwrap()
+    } else {
+        DEFAULT_MAX_BODY_SIZE
+    }
+}
+
 pub fn banner_enabled() -> bool {
     if let Ok(var) = env::var("CITRINE_BANNER_ENABLED") {
         match var.to_lowercase().as_str() {
@@ -0,0 +1,183 @@
+use std::fmt::Display;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SameSite {
+    Strict,
+    Lax,
+    None,
+}
+
+impl Display for SameSite {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SameSite::Strict => write!(f, "Strict"),
+            SameSite::Lax => write!(f, "Lax"),
+            SameSite::None => write!(f, "None"),
+        }
+    }
+}
+
+/// Cookie sent to the client with Response::set_cookie. Its Display implementation renders the
+/// value of the Set-Cookie header
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cookie {
+    name: String,
+    value: String,
+    max_age: Option<u64>,
+    path: Option<String>,
+    http_only: bool,
+    secure: bool,
+    same_site: Option<SameSite>,
+}
+
+impl Cookie {
+    pub fn new(name: &str, value: &str) -> Self {
+        Cookie {
+            name: name.to_string(),
+            value: value.to_string(),
+            max_age: None,
+            path: None,
+            http_only: false,
+            secure: false,
+            same_site: None,
+        }
+    }
+
+    /// Seconds until the cookie expires. A Max-Age of 0 removes the cookie from the client
+    pub fn max_age(mut self, seconds: u64) -> Self {
+        self.max_age = Some(seconds);
+        self
+    }
+
+    pub fn path(mut self, path: &str) -> Self {
+        self.path = Some(path.to_string());
+        self
+    }
+
+    pub fn http_only(mut self) -> Self {
+        self.http_only = true;
+        self
+    }
+
+    pub fn secure(mut self) -> Self {
+        self.secure = true;
+        self
+    }
+
+    pub fn same_site(mut self, same_site: SameSite) -> Self {
+        self.same_site = Some(same_site);
+        self
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn value(&self) -> &str {
+        &self.value
+    }
+
+    /// Checks the name, value and path against RFC 6265, so a value with a `;` can't add
+    /// attributes to the Set-Cookie header
+    pub(crate) fn validate(&self) -> Result<(), String> {
+        let is_token = |c: char| c.is_ascii_graphic() && !"()<>@,;:\\\"/[]?={}".contains(c);
+        if self.name.is_empty() || !self.name.chars().all(is_token) {
+            return Err(format!("Invalid cookie name {:?}", self.name));
+        }
+        let value = self
+            .value
+            .strip_prefix('"')
+            .and_then(|value| value.strip_suffix('"'))
+            .unwrap_or(&self.value);
+        let is_cookie_octet = |c: char| c.is_ascii_graphic() && !"\",;\\".contains(c);
+        if !value.chars().all(is_cookie_octet) {
+            return Err(format!("Invalid value for cookie {}", self.name));
+        }
+        if let Some(path) = self.path.as_ref() {
+            if path.chars().any(|c| c.is_ascii_control() || c == ';') {
+                return Err(format!("Invalid path for cookie {}", self.name));
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Display for Cookie {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}={}", self.name, self.value)?;
+        if let Some(max_age) = self.max_age {
+            write!(f, "; Max-Age={}", max_age)?;
+        }
+        if let Some(path) = self.path.as_ref() {
+            write!(f, "; Path={}", path)?;
+        }
+        if self.http_only {
+            write!(f, "; HttpOnly")?;
+        }
+        if self.secure {
+            write!(f, "; Secure")?;
+        }
+        if let Some(same_site) = self.same_site {
+            write!(f, "; SameSite={}", same_site)?;
+        }
+        Ok(())
+    }
+}
+
+/// Value of the cookie with the given name in a Cookie request header
+pub(crate) fn find_cookie(header: &str, name: &str) -> Option<String> {
+    header
+        .split(';')
+        .filter_map(|pair| pair.trim().split_once('='))
+        .find(|(cookie_name, _)| *cookie_name == name)
+        .map(|(_, value)| value.trim_matches('"').to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn set_cookie_header_test() {
+        let cookie = Cookie::new("session", "abc123")
+            .max_age(3600)
+            .path("/")
+            .http_only()
+            .secure()
+            .same_site(SameSite::Lax);
+
+        assert_eq!(
+            cookie.to_string(),
+            "session=abc123; Max-Age=3600; Path=/; HttpOnly; Secure; SameSite=Lax"
+        );
+        assert_eq!(Cookie::new("theme", "dark").to_string(), "theme=dark");
+    }
+
+    #[test]
+    fn find_cookie_test() {
+        let header = "theme=dark; session=\"abc123\";lang=en";
+
+        assert_eq!(find_cookie(header, "session"), Some("abc123".to_string()));
+        assert_eq!(find_cookie(header, "lang"), Some("en".to_string()));
+        assert_eq!(find_cookie(header, "missing"), None);
+    }
+
+    #[test]
+    fn validate_cookie_test() {
+        assert!(Cookie::new("session", "abc123").validate().is_ok());
+        assert!(Cookie::new("session", "\"abc 123\"").validate().is_err());
+        assert!(Cookie::new("session", "\"abc123\"").validate().is_ok());
+        assert!(Cookie::new("session", "abc; Domain=evil.com")
+            .validate()
+            .is_err());
+        assert!(Cookie::new("session", "abc\r\nX-Injected: 1")
+            .validate()
+            .is_err());
+        assert!(Cookie::new("my session", "abc").validate().is_err());
+        assert!(Cookie::new("", "abc").validate().is_err());
+        assert!(Cookie::new("session", "abc")
+            .path("/; Secure")
+            .validate()
+            .is_err());
+    }
+}
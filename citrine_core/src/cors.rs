@@ -0,0 +1,241 @@
+use hyper::{
+    header::{
+        HeaderName, HeaderValue, ACCESS_CONTROL_ALLOW_CREDENTIALS, ACCESS_CONTROL_ALLOW_HEADERS,
+        ACCESS_CONTROL_ALLOW_METHODS, ACCESS_CONTROL_ALLOW_ORIGIN, ACCESS_CONTROL_MAX_AGE,
+        ACCESS_CONTROL_REQUEST_HEADERS, ACCESS_CONTROL_REQUEST_METHOD, AUTHORIZATION, CONTENT_TYPE,
+        ORIGIN, VARY,
+    },
+    HeaderMap, Method, StatusCode,
+};
+
+use crate::response::Response;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum AllowedOrigins {
+    Any,
+    List(Vec<String>),
+}
+
+/// Cross-Origin Resource Sharing configuration. Preflight requests are answered automatically
+/// and the Access-Control-Allow-* headers are added to the responses to allowed origins
+#[derive(Clone, Debug)]
+pub struct CorsConfiguration {
+    allowed_origins: AllowedOrigins,
+    allowed_methods: Vec<Method>,
+    allowed_headers: Vec<HeaderName>,
+    allow_credentials: bool,
+    max_age: Option<u64>,
+}
+
+impl Default for CorsConfiguration {
+    fn default() -> Self {
+        CorsConfiguration {
+            allowed_origins: AllowedOrigins::List(Vec::new()),
+            allowed_methods: vec![
+                Method::GET,
+                Method::POST,
+                Method::PUT,
+                Method::PATCH,
+                Method::DELETE,
+            ],
+            allowed_headers: vec![CONTENT_TYPE, AUTHORIZATION],
+            allow_credentials: false,
+            max_age: None,
+        }
+    }
+}
+
+impl CorsConfiguration {
+    /// No origin is allowed until one is added. GET, POST, PUT, PATCH and DELETE requests with
+    /// the Content-Type and Authorization headers are allowed
+    pub fn new() -> Self {
+        CorsConfiguration::default()
+    }
+
+    pub fn allow_any_origin(mut self) -> Self {
+        self.allowed_origins = AllowedOrigins::Any;
+        self
+    }
+
+    pub fn allow_origin(mut self, origin: &str) -> Self {
+        match &mut self.allowed_origins {
+            AllowedOrigins::List(origins) => origins.push(origin.to_string()),
+            AllowedOrigins::Any => {}
+        }
+        self
+    }
+
+    pub fn allow_methods(mut self, methods: Vec<Method>) -> Self {
+        self.allowed_methods = methods;
+        self
+    }
+
+    pub fn allow_headers(mut self, headers: Vec<HeaderName>) -> Self {
+        self.allowed_headers = headers;
+        self
+    }
+
+    /// Allows cookies and credentials in cross origin requests. The origin of the request is sent
+    /// back instead of *, as browsers don't accept credentials with a wildcard origin
+    pub fn allow_credentials(mut self) -> Self {
+        self.allow_credentials = true;
+        self
+    }
+
+    /// Seconds browsers can cache the result of a preflight request
+    pub fn max_age(mut self, seconds: u64) -> Self {
+        self.max_age = Some(seconds);
+        self
+    }
+
+    /// Value of the Access-Control-Allow-Origin header for the origin, if it is allowed
+    fn allowed_origin(&self, origin: &HeaderValue) -> Option<HeaderValue> {
+        match &self.allowed_origins {
+            AllowedOrigins::Any if !self.allow_credentials => Some(HeaderValue::from_static("*")),
+            AllowedOrigins::Any => Some(origin.clone()),
+            AllowedOrigins::List(origins) => origins
+                .iter()
+                .any(|allowed| origin == allowed.as_str())
+                .then(|| origin.clone()),
+        }
+    }
+
+    pub(crate) fn is_preflight(&self, method: &Method, headers: &HeaderMap) -> bool {
+        method == Method::OPTIONS
+            && headers.contains_key(ORIGIN)
+            && headers.contains_key(ACCESS_CONTROL_REQUEST_METHOD)
+    }
+
+    /// Answer to a preflight request. Requests from origins that aren't allowed get no CORS
+    /// headers, so the browser blocks the actual request
+    pub(crate) fn preflight_response(&self, headers: &HeaderMap) -> Response {
+        let mut response = Response::empty(StatusCode::NO_CONTENT);
+        let origin = headers.get(ORIGIN).unwrap();
+        if self.allowed_origin(origin).is_none() {
+            return response;
+        }
+
+        let methods = self
+            .allowed_methods
+            .iter()
+            .map(|method| method.as_str())
+            .collect::<Vec<&str>>()
+            .join(", ");
+        response = response.add_header(ACCESS_CONTROL_ALLOW_METHODS, &methods);
+
+        // Headers are always allowed when requested, as browsers only ask for the non safelisted
+        // ones, so the configured list only limits them if it is not empty
+        let headers_list = if self.allowed_headers.is_empty() {
+            headers
+                .get(ACCESS_CONTROL_REQUEST_HEADERS)
+                .and_then(|requested| requested.to_str().ok())
+                .unwrap_or_default()
+                .to_string()
+        } else {
+            self.allowed_headers
+                .iter()
+                .map(|header| header.as_str())
+                .collect::<Vec<&str>>()
+                .join(", ")
+        };
+        if !headers_list.is_empty() {
+            response = response.add_header(ACCESS_CONTROL_ALLOW_HEADERS, &headers_list);
+        }
+        if let Some(max_age) = self.max_age {
+            response = response.add_header(ACCESS_CONTROL_MAX_AGE, &max_age.to_string());
+        }
+
+        self.add_response_headers(origin, response.headers_mut());
+        response
+    }
+
+    /// Adds the CORS headers of an actual response for the origin of the request
+    pub(crate) fn add_response_headers(&self, origin: &HeaderValue, headers: &mut HeaderMap) {
+        let allowed_origin = self.allowed_origin(origin);
+        if allowed_origin.as_ref().is_some_and(|value| value != "*") {
+            // The header depends on the origin of the request, so caches must take it into account
+            headers.append(VARY, HeaderValue::from_static("Origin"));
+        }
+        if let Some(allowed_origin) = allowed_origin {
+            headers.insert(ACCESS_CONTROL_ALLOW_ORIGIN, allowed_origin);
+            if self.allow_credentials {
+                headers.insert(
+                    ACCESS_CONTROL_ALLOW_CREDENTIALS,
+                    HeaderValue::from_static("true"),
+                );
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn preflight_headers(origin: &'static str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(ORIGIN, HeaderValue::from_static(origin));
+        headers.insert(
+            ACCESS_CONTROL_REQUEST_METHOD,
+            HeaderValue::from_static("PUT"),
+        );
+        headers
+    }
+
+    #[test]
+    fn preflight_test() {
+        let cors = CorsConfiguration::new()
+            .allow_origin("https://app.example.com")
+            .allow_methods(vec![Method::GET, Method::PUT])
+            .max_age(600);
+        let headers = preflight_headers("https://app.example.com");
+        assert!(cors.is_preflight(&Method::OPTIONS, &headers));
+
+        let response = cors.preflight_response(&headers);
+        assert_eq!(response.get_status(), StatusCode::NO_CONTENT);
+        let response_headers = response.get_headers();
+        assert_eq!(
+            response_headers.get(ACCESS_CONTROL_ALLOW_ORIGIN).unwrap(),
+            "https://app.example.com"
+        );
+        assert_eq!(
+            response_headers.get(ACCESS_CONTROL_ALLOW_METHODS).unwrap(),
+            "GET, PUT"
+        );
+        assert_eq!(response_headers.get(ACCESS_CONTROL_MAX_AGE).unwrap(), "600");
+
+        let response = cors.preflight_response(&preflight_headers("https://evil.example.com"));
+        assert!(response
+            .get_headers()
+            .get(ACCESS_CONTROL_ALLOW_ORIGIN)
+            .is_none());
+    }
+
+    #[test]
+    fn wildcard_origin_test() {
+        let origin = HeaderValue::from_static("https://app.example.com");
+
+        let mut headers = HeaderMap::new();
+        CorsConfiguration::new()
+            .allow_any_origin()
+            .add_response_headers(&origin, &mut headers);
+        assert_eq!(headers.get(ACCESS_CONTROL_ALLOW_ORIGIN).unwrap(), "*");
+        assert!(headers.get(ACCESS_CONTROL_ALLOW_CREDENTIALS).is_none());
+
+        // With credentials the origin of the request is reflected
+        let mut headers = HeaderMap::new();
+        CorsConfiguration::new()
+            .allow_any_origin()
+            .allow_credentials()
+            .add_response_headers(&origin, &mut headers);
+        assert_eq!(
+            headers.get(ACCESS_CONTROL_ALLOW_ORIGIN).unwrap(),
+            "https://app.example.com"
+        );
+        assert_eq!(
+            headers.get(ACCESS_CONTROL_ALLOW_CREDENTIALS).unwrap(),
+            "true"
+        );
+        assert_eq!(headers.get(VARY).unwrap(), "Origin");
+    }
+}
@@ -0,0 +1,106 @@
+use std::{future::Future, time::Duration};
+
+use log::{info, warn};
+use tokio::time::{self, Instant};
+
+use crate::request::ShutdownState;
+
+/// Time in-flight connections get to finish once the shutdown begins
+pub const DEFAULT_DRAIN_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How often the remaining connections are logged while draining
+pub(crate) const DRAIN_REPORT_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Debug, PartialEq)]
+pub(crate) enum DrainOutcome {
+    Drained,
+    TimedOut { remaining: usize },
+}
+
+/// Waits for the in-flight connections to finish, logging how many remain and the time left
+/// until the timeout
+pub(crate) async fn drain(
+    connections_closed: impl Future<Output = ()>,
+    shutdown_state: &ShutdownState,
+    timeout: Duration,
+    report_interval: Duration,
+) -> DrainOutcome {
+    info!(
+        "Draining {} connections, waiting up to {}s for them to finish",
+        shutdown_state.in_flight_connections(),
+        timeout.as_secs()
+    );
+
+    let deadline = Instant::now() + timeout;
+    let mut reports = time::interval_at(Instant::now() + report_interval, report_interval);
+    let mut connections_closed = std::pin::pin!(connections_closed);
+    loop {
+        tokio::select! {
+            _ = &mut connections_closed => {
+                info!("All connections drained");
+                return DrainOutcome::Drained;
+            }
+            _ = time::sleep_until(deadline) => {
+                let remaining = shutdown_state.in_flight_connections();
+                warn!("Timed out draining connections, {} still open", remaining);
+                return DrainOutcome::TimedOut { remaining };
+            }
+            _ = reports.tick() => {
+                info!(
+                    "Draining: {} connections remaining, {}s left",
+                    shutdown_state.in_flight_connections(),
+                    deadline.saturating_duration_since(Instant::now()).as_secs()
+                );
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn all_closed(shutdown_state: ShutdownState) {
+        while shutdown_state.in_flight_connections() > 0 {
+            time::sleep(Duration::from_millis(5)).await;
+        }
+    }
+
+    #[tokio::test]
+    async fn drain_test() {
+        let shutdown_state = ShutdownState::default();
+        for delay in [20, 40, 60] {
+            let connection = shutdown_state.track_connection();
+            tokio::spawn(async move {
+                time::sleep(Duration::from_millis(delay)).await;
+                drop(connection);
+            });
+        }
+        assert_eq!(shutdown_state.in_flight_connections(), 3);
+
+        let outcome = drain(
+            all_closed(shutdown_state.clone()),
+            &shutdown_state,
+            Duration::from_secs(5),
+            Duration::from_millis(10),
+        )
+        .await;
+        assert_eq!(outcome, DrainOutcome::Drained);
+        assert_eq!(shutdown_state.in_flight_connections(), 0);
+    }
+
+    #[tokio::test]
+    async fn drain_timeout_test() {
+        let shutdown_state = ShutdownState::default();
+        let _stuck_connection = shutdown_state.track_connection();
+
+        let outcome = drain(
+            all_closed(shutdown_state.clone()),
+            &shutdown_state,
+            Duration::from_millis(50),
+            Duration::from_millis(10),
+        )
+        .await;
+        assert_eq!(outcome, DrainOutcome::TimedOut { remaining: 1 });
+    }
+}
@@ -1,19 +1,107 @@
-use std::fmt::Debug;
+use std::{collections::HashMap, fmt::Debug};
 
 use chrono::{NaiveDateTime, Utc};
 use derive_more::derive::{Display, Error};
-use hyper::StatusCode;
+#[cfg(feature = "templates")]
+use hyper::HeaderMap;
+use hyper::{header::CONTENT_TYPE, StatusCode};
 use log::error;
 use serde::{Deserialize, Serialize};
-use validator::ValidationErrors;
+#[cfg(feature = "templates")]
+use std::sync::Arc;
+use validator::{ValidationErrors, ValidationErrorsKind};
 
-use crate::response::Response;
+#[cfg(feature = "xml")]
+use crate::request::ContentType;
+use crate::request::{Request, RequestMetadata};
+use crate::response::{Response, RetryAfter};
+#[cfg(feature = "templates")]
+use crate::util;
 
 pub type ServerError = Box<dyn std::error::Error + Send + Sync>;
 
+/// Retry-After sent with 503 and 429 errors that don't set their own
+pub const DEFAULT_RETRY_AFTER_SECONDS: u64 = 5;
+
+/// Validation messages by field. Nested fields are keyed by their path, like address.city or
+/// items[0].name
+pub type FieldErrors = HashMap<String, Vec<String>>;
+
+/// Renders the errors produced by the framework, like 404, 415 or 401, instead of the default body.
+/// It receives the method, URI and headers of the request to choose the format, like HTML pages for
+/// browsers and JSON for API clients
+pub type ErrorHandler = fn(&RequestError, &RequestMetadata) -> Response;
+
+/// How framework errors are answered, by the error handler of the application or, if it doesn't
+/// have one, with its error format and templates. Routes get the one of the application in their
+/// request extensions
+#[derive(Clone, Default)]
+pub(crate) struct ErrorConfiguration {
+    pub(crate) handler: Option<ErrorHandler>,
+    pub(crate) format: ErrorFormat,
+    // Templates of the HTML error pages by status
+    #[cfg(feature = "templates")]
+    pub(crate) templates: Arc<HashMap<StatusCode, String>>,
+}
+
+/// Response for a framework error, rendered by the error handler of the application if it has one
+/// or with the error configuration of the application if it doesn't
+pub(crate) fn error_response(
+    error_configuration: &ErrorConfiguration,
+    error: RequestError,
+    request_metadata: &RequestMetadata,
+) -> Response {
+    match error_configuration.handler {
+        Some(error_handler) => error_handler(&error, request_metadata),
+        None => {
+            #[cfg(feature = "templates")]
+            if let Some(response) =
+                error.template_response(&error_configuration.templates, &request_metadata.headers)
+            {
+                return response;
+            }
+            error.into_response_with(ErrorBody::for_format(
+                error_configuration.format,
+                Some(request_metadata.uri.path()),
+            ))
+        }
+    }
+}
+
+/// Response for a framework error raised inside a route, like a body that fails validation, with
+/// the error configuration the router left in the request extensions
+pub(crate) fn route_error_response(error: RequestError, request: &Request) -> Response {
+    let default_configuration = ErrorConfiguration::default();
+    let error_configuration = request
+        .extensions()
+        .get::<ErrorConfiguration>()
+        .unwrap_or(&default_configuration);
+    error_response(error_configuration, error, &request.metadata())
+}
+
+/// Context of error templates
+#[cfg(feature = "templates")]
+#[derive(Serialize)]
+struct ErrorTemplateContext<'a> {
+    status: u16,
+    reason: &'a str,
+    cause: &'a str,
+}
+
+/// Shape of the body of error responses
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum ErrorFormat {
+    /// DefaultErrorResponseBody
+    #[default]
+    Default,
+    /// RFC 7807 problem details, sent as application/problem+json
+    ProblemJson,
+}
+
 #[derive(Debug, Clone, Display)]
 pub enum ErrorType {
     RequestBodyUnreadable,
+    RequestQueryUnreadable,
     NotFound,
     MethodNotAllowed,
     Internal,
@@ -21,6 +109,17 @@ pub enum ErrorType {
     FailedValidation(ValidationErrors),
     Unauthorized,
     UnsupportedMediaType,
+    ServiceUnavailable,
+    PayloadTooLarge,
+    InvalidPatch,
+    TooManyRequests,
+    UnexpectedBody,
+    NotImplemented,
+    Forbidden,
+    /// The body was already read as a stream, so it can't be read again
+    BodyAlreadyConsumed,
+    /// None of the media types in the Accept header of the request can be produced
+    NotAcceptable,
 }
 
 impl ErrorType {
@@ -29,11 +128,21 @@ impl ErrorType {
             ErrorType::NotFound => "Request not found",
             ErrorType::MethodNotAllowed => "Method not allowed",
             ErrorType::RequestBodyUnreadable => "Could not parse request body",
+            ErrorType::RequestQueryUnreadable => "Could not parse request query",
             ErrorType::Internal => "There was an error handling the request",
             ErrorType::MissingBody => "Request body is missing",
             ErrorType::FailedValidation(_) => "Request body failed validation",
             ErrorType::Unauthorized => "Unauthorized",
             ErrorType::UnsupportedMediaType => "Unsupported Media Type",
+            ErrorType::ServiceUnavailable => "Service unavailable",
+            ErrorType::PayloadTooLarge => "Payload too large",
+            ErrorType::InvalidPatch => "Could not apply patch",
+            ErrorType::TooManyRequests => "Too many requests",
+            ErrorType::UnexpectedBody => "Request body is not allowed",
+            ErrorType::NotImplemented => "Not implemented",
+            ErrorType::Forbidden => "Forbidden",
+            ErrorType::BodyAlreadyConsumed => "Request body was already consumed",
+            ErrorType::NotAcceptable => "Not acceptable",
         }
     }
 }
@@ -43,6 +152,8 @@ impl ErrorType {
 pub struct RequestError {
     error_type: ErrorType,
     cause: Option<String>,
+    retry_after: Option<RetryAfter>,
+    supported_media_types: Vec<String>,
 }
 
 impl RequestError {
@@ -50,6 +161,8 @@ impl RequestError {
         RequestError {
             error_type,
             cause: Some(cause.to_string()),
+            retry_after: None,
+            supported_media_types: vec![],
         }
     }
 
@@ -57,25 +170,128 @@ impl RequestError {
         RequestError {
             error_type,
             cause: None,
+            retry_after: None,
+            supported_media_types: vec![],
         }
     }
-}
 
-impl From<RequestError> for Response {
-    fn from(error: RequestError) -> Self {
-        let status_code = match error.error_type {
+    /// Validation messages by field when the error is a failed validation, in a form that can be
+    /// passed directly to a template to render them next to each form field
+    pub fn field_errors(&self) -> Option<FieldErrors> {
+        if let ErrorType::FailedValidation(validation_errors) = &self.error_type {
+            let mut field_errors = FieldErrors::new();
+            collect_field_errors(validation_errors, "", &mut field_errors);
+            Some(field_errors)
+        } else {
+            None
+        }
+    }
+
+    /// Overrides the Retry-After sent with 503 Service Unavailable and 429 Too Many Requests
+    /// errors
+    pub fn retry_after(mut self, retry_after: impl Into<RetryAfter>) -> Self {
+        self.retry_after = Some(retry_after.into());
+        self
+    }
+
+    /// Media types that are accepted instead, listed in the body of 415 Unsupported Media Type
+    /// errors so clients can discover them
+    pub fn supported_media_types(mut self, media_types: Vec<String>) -> Self {
+        self.supported_media_types = media_types;
+        self
+    }
+
+    /// Status of the response the error is answered with
+    pub fn status(&self) -> StatusCode {
+        match self.error_type {
             ErrorType::NotFound => StatusCode::NOT_FOUND,
             ErrorType::MethodNotAllowed => StatusCode::METHOD_NOT_ALLOWED,
-            ErrorType::Internal => StatusCode::INTERNAL_SERVER_ERROR,
+            ErrorType::Internal | ErrorType::BodyAlreadyConsumed => {
+                StatusCode::INTERNAL_SERVER_ERROR
+            }
             ErrorType::Unauthorized => StatusCode::UNAUTHORIZED,
+            ErrorType::Forbidden => StatusCode::FORBIDDEN,
             ErrorType::UnsupportedMediaType => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            ErrorType::NotAcceptable => StatusCode::NOT_ACCEPTABLE,
+            ErrorType::ServiceUnavailable => StatusCode::SERVICE_UNAVAILABLE,
+            ErrorType::PayloadTooLarge => StatusCode::PAYLOAD_TOO_LARGE,
+            ErrorType::InvalidPatch => StatusCode::UNPROCESSABLE_ENTITY,
+            ErrorType::TooManyRequests => StatusCode::TOO_MANY_REQUESTS,
+            ErrorType::NotImplemented => StatusCode::NOT_IMPLEMENTED,
             ErrorType::RequestBodyUnreadable
+            | ErrorType::RequestQueryUnreadable
             | ErrorType::MissingBody
+            | ErrorType::UnexpectedBody
             | ErrorType::FailedValidation(_) => StatusCode::BAD_REQUEST,
+        }
+    }
+
+    /// Response for the error with the body in the format of the request body, so clients that
+    /// send XML get their errors as XML too. Other requests get the JSON body of the configured
+    /// error format, with the path of the request as the instance of problem details
+    pub fn into_response_for(self, request: &Request) -> Response {
+        let default_configuration = ErrorConfiguration::default();
+        let error_configuration = request
+            .extensions()
+            .get::<ErrorConfiguration>()
+            .unwrap_or(&default_configuration);
+        #[cfg(feature = "templates")]
+        if let Some(response) =
+            self.template_response(&error_configuration.templates, request.headers())
+        {
+            return response;
+        }
+        #[cfg(feature = "xml")]
+        if let Some(ContentType::Xml) = request.content_type() {
+            return self.into_response_with(ErrorBody::Default(Response::xml));
+        }
+        self.into_response_with(ErrorBody::for_format(
+            error_configuration.format,
+            Some(request.path()),
+        ))
+    }
+
+    /// Error page rendered with the template registered for the status, for requests that accept
+    /// HTML. None if there is no template or it fails to render, so the error is sent as JSON
+    #[cfg(feature = "templates")]
+    fn template_response(
+        &self,
+        templates: &HashMap<StatusCode, String>,
+        headers: &HeaderMap,
+    ) -> Option<Response> {
+        let status = self.status();
+        let template_name = templates.get(&status)?;
+        if !util::accepts_html(headers) {
+            return None;
+        }
+
+        let context = ErrorTemplateContext {
+            status: status.as_u16(),
+            reason: status.canonical_reason().unwrap_or_default(),
+            cause: self
+                .cause
+                .as_deref()
+                .unwrap_or(self.error_type.default_message()),
         };
-        let cause = error
+        match Response::template_with_status(status, template_name, &context) {
+            Ok(response) => Some(response),
+            Err(e) => {
+                error!("Error rendering error template {}: {}", template_name, e);
+                None
+            }
+        }
+    }
+
+    fn into_response_with(self, error_body: ErrorBody) -> Response {
+        let status_code = self.status();
+        // Read before the error is taken apart
+        let field_errors = match error_body {
+            ErrorBody::Problem { .. } => self.field_errors(),
+            ErrorBody::Default(_) => None,
+        };
+        let cause = self
             .cause
-            .unwrap_or(error.error_type.default_message().to_string());
+            .unwrap_or(self.error_type.default_message().to_string());
 
         if log::log_enabled!(log::Level::Debug) {
             error!("Response status: {} cause: {}", status_code, cause);
@@ -91,31 +307,133 @@ impl From<RequestError> for Response {
             "500 Internal Server Error".to_string()
         };
 
-        let validation_errors =
-            if let ErrorType::FailedValidation(validation_errors) = error.error_type {
-                Some(validation_errors)
-            } else {
-                None
-            };
+        let response = match error_body {
+            ErrorBody::Default(serialize) => {
+                let validation_errors =
+                    if let ErrorType::FailedValidation(validation_errors) = self.error_type {
+                        Some(validation_errors)
+                    } else {
+                        None
+                    };
 
-        let response_body = DefaultErrorResponseBody {
-            status: status_message,
-            cause,
-            date: Utc::now().naive_local(),
-            validation_errors,
+                let response_body = DefaultErrorResponseBody {
+                    status: status_message,
+                    cause,
+                    date: Utc::now().naive_local(),
+                    validation_errors,
+                    supported_media_types: self.supported_media_types,
+                };
+                serialize(Response::new(status_code), response_body)
+            }
+            ErrorBody::Problem { instance } => {
+                let problem_details = ProblemDetails {
+                    problem_type: "about:blank".to_string(),
+                    title: status_code
+                        .canonical_reason()
+                        .unwrap_or("Internal Server Error")
+                        .to_string(),
+                    status: status_code.as_u16(),
+                    detail: cause,
+                    instance: instance.map(str::to_string),
+                    errors: field_errors,
+                    supported_media_types: self.supported_media_types,
+                };
+                Response::new(status_code)
+                    .json(problem_details)
+                    .add_header(CONTENT_TYPE, PROBLEM_JSON_CONTENT_TYPE)
+            }
         };
+        if status_code == StatusCode::SERVICE_UNAVAILABLE
+            || status_code == StatusCode::TOO_MANY_REQUESTS
+        {
+            return response.retry_after(
+                self.retry_after
+                    .unwrap_or(RetryAfter::Seconds(DEFAULT_RETRY_AFTER_SECONDS)),
+            );
+        }
+        response
+    }
+}
 
-        Response::new(status_code).json(response_body)
+fn collect_field_errors(errors: &ValidationErrors, prefix: &str, field_errors: &mut FieldErrors) {
+    for (field, kind) in errors.errors() {
+        let path = if prefix.is_empty() {
+            field.to_string()
+        } else {
+            format!("{}.{}", prefix, field)
+        };
+        match kind {
+            ValidationErrorsKind::Field(errors) => {
+                let messages = errors.iter().map(|error| match &error.message {
+                    Some(message) => message.to_string(),
+                    None => error.code.to_string(),
+                });
+                field_errors.entry(path).or_default().extend(messages);
+            }
+            ValidationErrorsKind::Struct(errors) => {
+                collect_field_errors(errors, &path, field_errors);
+            }
+            ValidationErrorsKind::List(errors) => {
+                for (index, errors) in errors {
+                    collect_field_errors(errors, &format!("{}[{}]", path, index), field_errors);
+                }
+            }
+        }
     }
 }
 
+/// Response with the default error body. Handlers use RequestError::into_response_for to answer
+/// with the error format of the application instead
+impl From<RequestError> for Response {
+    fn from(error: RequestError) -> Self {
+        error.into_response_with(ErrorBody::Default(Response::json))
+    }
+}
+
+const PROBLEM_JSON_CONTENT_TYPE: &str = "application/problem+json";
+
+/// How the body of an error response is written
+enum ErrorBody<'a> {
+    Default(fn(Response, DefaultErrorResponseBody) -> Response),
+    Problem { instance: Option<&'a str> },
+}
+
+impl<'a> ErrorBody<'a> {
+    fn for_format(error_format: ErrorFormat, instance: Option<&'a str>) -> Self {
+        match error_format {
+            ErrorFormat::Default => ErrorBody::Default(Response::json),
+            ErrorFormat::ProblemJson => ErrorBody::Problem { instance },
+        }
+    }
+}
+
+/// RFC 7807 problem details. Validation errors are sent in the errors extension member, by field
 #[derive(Serialize)]
+struct ProblemDetails {
+    #[serde(rename = "type")]
+    problem_type: String,
+    title: String,
+    status: u16,
+    detail: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    instance: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    errors: Option<FieldErrors>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    supported_media_types: Vec<String>,
+}
+
+#[derive(Serialize)]
+// Root element of the body when it is serialized as XML
+#[serde(rename = "error")]
 pub struct DefaultErrorResponseBody {
     status: String,
     cause: String,
     date: NaiveDateTime,
     #[serde(skip_serializing_if = "Option::is_none")]
     validation_errors: Option<ValidationErrors>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    supported_media_types: Vec<String>,
 }
 
 impl DefaultErrorResponseBody {
@@ -130,6 +448,7 @@ impl DefaultErrorResponseBody {
             cause,
             date: Utc::now().naive_local(),
             validation_errors: None,
+            supported_media_types: vec![],
         }
     }
 }
@@ -151,6 +470,16 @@ impl DeserializationError {
             cause: e.to_string(),
         }
     }
+
+    pub fn with_cause(cause: &str) -> Self {
+        DeserializationError {
+            cause: cause.to_string(),
+        }
+    }
+
+    pub fn get_cause(&self) -> &str {
+        &self.cause
+    }
 }
 
 impl From<serde_json::Error> for DeserializationError {
@@ -164,3 +493,158 @@ impl From<serde_html_form::de::Error> for DeserializationError {
         DeserializationError::new(&value)
     }
 }
+
+impl From<serde_qs::Error> for DeserializationError {
+    fn from(value: serde_qs::Error) -> Self {
+        DeserializationError::new(&value)
+    }
+}
+
+impl From<multer::Error> for DeserializationError {
+    fn from(value: multer::Error) -> Self {
+        DeserializationError::new(&value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[cfg(feature = "templates")]
+    use hyper::header::{HeaderValue, ACCEPT};
+    #[cfg(feature = "templates")]
+    use tera::{Context, Tera};
+    use validator::Validate;
+
+    use crate::response::ResponseBody;
+
+    use super::*;
+
+    #[cfg(feature = "templates")]
+    #[derive(Validate)]
+    struct SignUpForm {
+        #[validate(length(min = 3, message = "Username must have at least 3 characters"))]
+        username: String,
+        #[validate(email(message = "Invalid email"))]
+        email: String,
+        #[validate(nested)]
+        address: Address,
+    }
+
+    #[cfg(feature = "templates")]
+    #[derive(Validate)]
+    struct Address {
+        #[validate(length(min = 1))]
+        city: String,
+    }
+
+    #[cfg(feature = "templates")]
+    #[test]
+    fn field_errors_template_test() {
+        let form = SignUpForm {
+            username: "al".to_string(),
+            email: "alice@example.com".to_string(),
+            address: Address {
+                city: String::new(),
+            },
+        };
+        let error =
+            RequestError::default(ErrorType::FailedValidation(form.validate().unwrap_err()));
+
+        let field_errors = error.field_errors().unwrap();
+        assert_eq!(
+            field_errors.get("username").unwrap(),
+            &vec!["Username must have at least 3 characters".to_string()]
+        );
+        assert_eq!(
+            field_errors.get("address.city").unwrap(),
+            &vec!["length".to_string()]
+        );
+        assert!(!field_errors.contains_key("email"));
+
+        let mut context = Context::new();
+        context.insert("errors", &field_errors);
+        let html = Tera::one_off(
+            r#"<input name="username">{% for message in errors.username %}<span>{{ message }}</span>{% endfor %}"#,
+            &context,
+            true,
+        )
+        .unwrap();
+        assert_eq!(
+            html,
+            r#"<input name="username"><span>Username must have at least 3 characters</span>"#
+        );
+
+        assert!(RequestError::default(ErrorType::NotFound)
+            .field_errors()
+            .is_none());
+    }
+
+    #[derive(Validate)]
+    struct SignUp {
+        #[validate(email)]
+        email: String,
+    }
+
+    #[test]
+    fn problem_details_test() {
+        let sign_up = SignUp {
+            email: "alice".to_string(),
+        };
+        let error =
+            RequestError::default(ErrorType::FailedValidation(sign_up.validate().unwrap_err()));
+
+        let response = error.into_response_with(ErrorBody::Problem {
+            instance: Some("/sign-up"),
+        });
+        assert_eq!(response.get_status(), StatusCode::BAD_REQUEST);
+        assert_eq!(
+            response.get_headers().get(CONTENT_TYPE).unwrap(),
+            PROBLEM_JSON_CONTENT_TYPE
+        );
+        let Some(ResponseBody::Full(body)) = response.get_body() else {
+            panic!("Expected a full body");
+        };
+        let body: serde_json::Value = serde_json::from_slice(body).unwrap();
+        assert_eq!(
+            body,
+            serde_json::json!({
+                "type": "about:blank",
+                "title": "Bad Request",
+                "status": 400,
+                "detail": "Request body failed validation",
+                "instance": "/sign-up",
+                "errors": { "email": ["email"] },
+            })
+        );
+
+        // The default body is kept unless problem details are configured
+        let response: Response = RequestError::default(ErrorType::NotFound).into();
+        assert_eq!(
+            response.get_headers().get(CONTENT_TYPE).unwrap(),
+            "application/json"
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "templates")]
+    fn error_template_fallback_test() {
+        let templates =
+            HashMap::from([(StatusCode::NOT_IMPLEMENTED, "missing/501.html".to_string())]);
+        let error = RequestError::default(ErrorType::NotImplemented);
+
+        // Clients that don't accept HTML get the JSON body
+        let mut headers = HeaderMap::new();
+        headers.insert(ACCEPT, HeaderValue::from_static("application/json"));
+        assert!(error.template_response(&templates, &headers).is_none());
+
+        // And so do browsers when the template can't be rendered
+        headers.insert(
+            ACCEPT,
+            HeaderValue::from_static("text/html,application/xhtml+xml;q=0.9"),
+        );
+        assert!(error.template_response(&templates, &headers).is_none());
+
+        // Statuses without a template are not looked up
+        let error = RequestError::default(ErrorType::NotFound);
+        assert!(error.template_response(&templates, &headers).is_none());
+    }
+}
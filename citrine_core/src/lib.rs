@@ -1,24 +1,41 @@
 // exports to avoid having to add necessary libraries as dependencies on the app
 pub use tokio;
+#[cfg(feature = "templates")]
 pub use tera;
+#[cfg(feature = "jwt")]
 pub use jsonwebtoken;
 pub use hyper::{body::Bytes, Method, Uri, StatusCode, header};
 
-pub use error::{ServerError, RequestError, DefaultErrorResponseBody};
-pub use router::{Router, Route, Accepts};
+pub use error::{ServerError, RequestError, DefaultErrorResponseBody, ErrorFormat, ErrorHandler};
+pub use router::{Router, Route, Accepts, Deprecation, GetBodyPolicy};
 
 mod server;
 mod router;
 mod error;
+#[cfg(feature = "templates")]
 mod templates;
 mod configuration;
 mod util;
+mod drain;
+#[cfg(feature = "compression")]
+mod compression;
 pub mod security;
 pub mod middleware;
 pub mod request;
 pub mod response;
 pub mod application;
+#[cfg(feature = "static-files")]
 pub mod static_file_server;
+pub mod resolution;
 pub mod request_matcher;
+pub mod patch;
+pub mod multipart;
+pub mod cookie;
+pub mod single_flight;
+pub mod serializers;
+pub mod slow_request_log;
+pub mod access_log;
+pub mod cors;
+pub mod tls;
 
 extern crate lazy_static;
@@ -1,8 +1,14 @@
 use crate::{
     request::Request,
     request_matcher::{MethodMatcher, RequestMatcher},
+    response::Response,
 };
 
+pub const DEFAULT_MIDDLEWARE_PRIORITY: i32 = 0;
+
+/// Middlewares are checked in ascending priority order and each request enters only the first
+/// one that matches. Middlewares with the same priority keep their registration order, so if no
+/// priorities are set they are checked in the order they were added.
 #[derive(Default)]
 pub struct RequestMiddleware {
     functions: Vec<Middleware>,
@@ -10,7 +16,15 @@ pub struct RequestMiddleware {
 
 struct Middleware {
     request_matcher: RequestMatcher,
-    function: fn(Request) -> Request,
+    function: MiddlewareFunction,
+    priority: i32,
+}
+
+enum MiddlewareFunction {
+    Transform(fn(Request) -> Request),
+    // Returns a response to answer the request without reaching the handler, or None to let it
+    // through unchanged
+    ShortCircuit(fn(&Request) -> Option<Response>),
 }
 
 impl RequestMiddleware {
@@ -19,36 +33,310 @@ impl RequestMiddleware {
     }
 
     pub fn add_middleware(
-        mut self,
+        self,
+        method_matcher: MethodMatcher,
+        path_regex: &str,
+        middleware: fn(Request) -> Request,
+    ) -> Self {
+        self.add_middleware_with_priority(
+            method_matcher,
+            path_regex,
+            DEFAULT_MIDDLEWARE_PRIORITY,
+            middleware,
+        )
+    }
+
+    pub fn add_middleware_with_priority(
+        self,
         method_matcher: MethodMatcher,
         path_regex: &str,
+        priority: i32,
         middleware: fn(Request) -> Request,
     ) -> Self {
-        self.functions.push(Middleware::new(
+        self.insert(
             RequestMatcher::new(path_regex, method_matcher),
+            MiddlewareFunction::Transform(middleware),
+            priority,
+        )
+    }
+
+    /// Middleware that can answer the request itself instead of letting it reach the handler,
+    /// like a cache returning a stored response or a 304 Not Modified when the If-None-Match
+    /// header has the current ETag. The response middlewares still run on its response
+    pub fn add_short_circuit_middleware(
+        self,
+        method_matcher: MethodMatcher,
+        path_regex: &str,
+        middleware: fn(&Request) -> Option<Response>,
+    ) -> Self {
+        self.add_short_circuit_middleware_with_priority(
+            method_matcher,
+            path_regex,
+            DEFAULT_MIDDLEWARE_PRIORITY,
             middleware,
-        ));
+        )
+    }
+
+    pub fn add_short_circuit_middleware_with_priority(
+        self,
+        method_matcher: MethodMatcher,
+        path_regex: &str,
+        priority: i32,
+        middleware: fn(&Request) -> Option<Response>,
+    ) -> Self {
+        self.insert(
+            RequestMatcher::new(path_regex, method_matcher),
+            MiddlewareFunction::ShortCircuit(middleware),
+            priority,
+        )
+    }
+
+    fn insert(
+        mut self,
+        request_matcher: RequestMatcher,
+        function: MiddlewareFunction,
+        priority: i32,
+    ) -> Self {
+        // Insert after every middleware with the same or a lower priority to keep the
+        // registration order between equal priorities
+        let position = self
+            .functions
+            .iter()
+            .position(|registered| registered.priority > priority)
+            .unwrap_or(self.functions.len());
+        self.functions.insert(
+            position,
+            Middleware::new(request_matcher, function, priority),
+        );
         self
     }
 
-    pub fn process(&self, request: Request) -> Request {
+    pub fn validate(&self) -> Vec<String> {
+        self.functions
+            .iter()
+            .filter_map(|middleware| middleware.request_matcher.validate().err())
+            .collect()
+    }
+
+    /// Returns the request to pass to the handler, or along with the response that answers it if
+    /// a short circuit middleware stopped it
+    pub fn process(&self, request: Request) -> (Request, Option<Response>) {
         for middleware in self.functions.iter() {
             if middleware
                 .request_matcher
                 .matches(&request.method, &request.uri)
             {
-                return (middleware.function)(request);
+                return match middleware.function {
+                    MiddlewareFunction::Transform(function) => (function(request), None),
+                    MiddlewareFunction::ShortCircuit(function) => {
+                        let response = function(&request);
+                        (request, response)
+                    }
+                };
             }
         }
-        request
+        (request, None)
     }
 }
 
 impl Middleware {
-    fn new(request_matcher: RequestMatcher, function: fn(Request) -> Request) -> Self {
+    fn new(request_matcher: RequestMatcher, function: MiddlewareFunction, priority: i32) -> Self {
         Middleware {
             request_matcher,
             function,
+            priority,
+        }
+    }
+}
+
+/// Middlewares that run after the handler and can modify its response. Unlike request
+/// middlewares, every matching middleware is executed, chained in registration order.
+#[derive(Default)]
+pub struct ResponseMiddleware {
+    functions: Vec<ResponseMiddlewareFunction>,
+}
+
+struct ResponseMiddlewareFunction {
+    request_matcher: RequestMatcher,
+    function: fn(Request, Response) -> Response,
+}
+
+impl ResponseMiddleware {
+    pub fn new() -> Self {
+        ResponseMiddleware { functions: vec![] }
+    }
+
+    pub fn add_middleware(
+        mut self,
+        method_matcher: MethodMatcher,
+        path_regex: &str,
+        middleware: fn(Request, Response) -> Response,
+    ) -> Self {
+        self.functions.push(ResponseMiddlewareFunction {
+            request_matcher: RequestMatcher::new(path_regex, method_matcher),
+            function: middleware,
+        });
+        self
+    }
+
+    pub fn validate(&self) -> Vec<String> {
+        self.functions
+            .iter()
+            .filter_map(|middleware| middleware.request_matcher.validate().err())
+            .collect()
+    }
+
+    pub fn process(&self, request: &Request, mut response: Response) -> Response {
+        for middleware in self.functions.iter() {
+            if middleware
+                .request_matcher
+                .matches(&request.method, &request.uri)
+            {
+                response = (middleware.function)(request.clone(), response);
+            }
         }
+        response
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use hyper::{
+        header::{HeaderName, HeaderValue, CONTENT_TYPE, ETAG, IF_NONE_MATCH},
+        HeaderMap, Method, StatusCode, Uri,
+    };
+
+    use crate::security::security_configuration::AuthResult;
+
+    use super::*;
+
+    fn mark_request(mut request: Request, name: &'static str) -> Request {
+        request
+            .headers
+            .insert("x-middleware", HeaderValue::from_static(name));
+        request
+    }
+
+    fn test_request() -> Request {
+        Request::new(
+            Method::GET,
+            Uri::from_static("/api/users"),
+            String::new(),
+            HeaderMap::new(),
+            AuthResult::Allowed,
+        )
+    }
+
+    #[test]
+    fn middleware_registration_order_test() {
+        let middleware = RequestMiddleware::new()
+            .add_middleware(MethodMatcher::All, "/api/*", |r| mark_request(r, "api"))
+            .add_middleware(MethodMatcher::All, "/*", |r| mark_request(r, "all"));
+
+        let (request, _) = middleware.process(test_request());
+        assert_eq!(request.headers.get("x-middleware").unwrap(), "api");
+    }
+
+    #[test]
+    fn middleware_priority_test() {
+        let middleware = RequestMiddleware::new()
+            .add_middleware(MethodMatcher::All, "/api/*", |r| mark_request(r, "api"))
+            .add_middleware_with_priority(MethodMatcher::All, "/*", 10, |r| mark_request(r, "low"))
+            .add_middleware_with_priority(MethodMatcher::All, "/*", -10, |r| {
+                mark_request(r, "high")
+            });
+
+        let (request, _) = middleware.process(test_request());
+        assert_eq!(request.headers.get("x-middleware").unwrap(), "high");
+    }
+
+    const USERS_ETAG: &str = "\"users-v7\"";
+
+    fn conditional_users(request: &Request) -> Option<Response> {
+        if request.if_none_match(USERS_ETAG) {
+            return Some(Response::not_modified(USERS_ETAG));
+        }
+        None
+    }
+
+    fn cached_users(request: &Request) -> Option<Response> {
+        let cached = [("/api/users", "[{\"name\":\"alice\"}]")];
+        cached
+            .iter()
+            .find(|(path, _)| *path == request.path())
+            .map(|(_, body)| {
+                Response::new(StatusCode::OK)
+                    .add_header(CONTENT_TYPE, "application/json")
+                    .add_header(HeaderName::from_static("x-cache"), "hit")
+                    .body(body.to_string())
+            })
+    }
+
+    #[test]
+    fn cache_hit_short_circuit_test() {
+        let middleware = RequestMiddleware::new().add_short_circuit_middleware(
+            MethodMatcher::One(Method::GET),
+            "/api/*",
+            cached_users,
+        );
+
+        let (request, response) = middleware.process(test_request());
+        let response = response.unwrap();
+        assert_eq!(response.get_status(), StatusCode::OK);
+        assert_eq!(response.get_headers().get("x-cache").unwrap(), "hit");
+        assert_eq!(request.path(), "/api/users");
+
+        // Misses reach the handler
+        let mut request = test_request();
+        request.uri = Uri::from_static("/api/orders");
+        assert!(middleware.process(request).1.is_none());
+    }
+
+    #[test]
+    fn not_modified_short_circuit_test() {
+        let middleware = RequestMiddleware::new().add_short_circuit_middleware(
+            MethodMatcher::One(Method::GET),
+            "/api/users",
+            conditional_users,
+        );
+
+        let mut request = test_request();
+        request.headers.insert(
+            IF_NONE_MATCH,
+            HeaderValue::from_static("\"users-v6\", W/\"users-v7\""),
+        );
+        let response = middleware.process(request).1.unwrap();
+        assert_eq!(response.get_status(), StatusCode::NOT_MODIFIED);
+        assert_eq!(response.get_headers().get(ETAG).unwrap(), USERS_ETAG);
+
+        let mut request = test_request();
+        request
+            .headers
+            .insert(IF_NONE_MATCH, HeaderValue::from_static("\"users-v6\""));
+        assert!(middleware.process(request).1.is_none());
+    }
+
+    fn mark_response(response: Response, name: &str) -> Response {
+        let marks = match response.get_headers().get("x-middleware") {
+            Some(marks) => format!("{}, {}", marks.to_str().unwrap(), name),
+            None => name.to_string(),
+        };
+        response.add_header(HeaderName::from_static("x-middleware"), &marks)
+    }
+
+    #[test]
+    fn response_middleware_chain_test() {
+        let middleware = ResponseMiddleware::new()
+            .add_middleware(MethodMatcher::All, "/*", |_, r| mark_response(r, "all"))
+            .add_middleware(MethodMatcher::All, "/admin/*", |_, r| {
+                mark_response(r, "admin")
+            })
+            .add_middleware(MethodMatcher::All, "/api/*", |_, r| mark_response(r, "api"));
+
+        let response = middleware.process(&test_request(), Response::new(StatusCode::OK));
+        assert_eq!(
+            response.get_headers().get("x-middleware").unwrap(),
+            "all, api"
+        );
     }
 }
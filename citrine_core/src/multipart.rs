@@ -0,0 +1,261 @@
+use futures_util::FutureExt;
+use hyper::body::Bytes;
+
+use crate::error::{DeserializationError, ErrorType, RequestError};
+
+/// Default limit on the number of parts of multipart bodies
+pub const DEFAULT_MAX_MULTIPART_PARTS: usize = 100;
+
+/// Limits checked while multipart bodies are parsed, so upload endpoints reject huge or numerous
+/// parts with a 413 Payload Too Large. Sizes are only bounded by the body size by default
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MultipartLimits {
+    max_parts: usize,
+    max_part_size: Option<u64>,
+    max_total_size: Option<u64>,
+}
+
+impl MultipartLimits {
+    pub fn max_parts(mut self, max_parts: usize) -> Self {
+        self.max_parts = max_parts;
+        self
+    }
+
+    /// Limit in bytes of the data of each part
+    pub fn max_part_size(mut self, max_part_size: u64) -> Self {
+        self.max_part_size = Some(max_part_size);
+        self
+    }
+
+    /// Limit in bytes of the whole body, for upload routes that accept less than the body limit
+    pub fn max_total_size(mut self, max_total_size: u64) -> Self {
+        self.max_total_size = Some(max_total_size);
+        self
+    }
+
+    fn constraints(&self) -> multer::Constraints {
+        let mut size_limit = multer::SizeLimit::new();
+        if let Some(max_part_size) = self.max_part_size {
+            size_limit = size_limit.per_field(max_part_size);
+        }
+        if let Some(max_total_size) = self.max_total_size {
+            size_limit = size_limit.whole_stream(max_total_size);
+        }
+        multer::Constraints::new().size_limit(size_limit)
+    }
+}
+
+impl Default for MultipartLimits {
+    fn default() -> Self {
+        MultipartLimits {
+            max_parts: DEFAULT_MAX_MULTIPART_PARTS,
+            max_part_size: None,
+            max_total_size: None,
+        }
+    }
+}
+
+/// Parsed multipart/form-data body, with its parts in the order they were sent
+#[derive(Debug, Clone, Default)]
+pub struct Multipart {
+    parts: Vec<MultipartPart>,
+}
+
+/// Single part of a multipart body. File parts have a filename, plain fields don't
+#[derive(Debug, Clone)]
+pub struct MultipartPart {
+    pub name: Option<String>,
+    pub filename: Option<String>,
+    pub content_type: Option<String>,
+    pub data: Bytes,
+}
+
+impl MultipartPart {
+    pub fn is_file(&self) -> bool {
+        self.filename.is_some()
+    }
+
+    /// Part data as text. None if it isn't valid UTF-8
+    pub fn text(&self) -> Option<&str> {
+        std::str::from_utf8(&self.data).ok()
+    }
+}
+
+impl Multipart {
+    /// Parses a complete body. The boundary is read from the multipart/form-data Content-Type
+    pub fn parse(content_type: &str, body: Bytes) -> Result<Self, RequestError> {
+        Self::parse_with_limits(content_type, body, MultipartLimits::default())
+    }
+
+    /// Like parse, rejecting the body with a 413 Payload Too Large when it exceeds the limits
+    pub fn parse_with_limits(
+        content_type: &str,
+        body: Bytes,
+        limits: MultipartLimits,
+    ) -> Result<Self, RequestError> {
+        let boundary_res = multer::parse_boundary(content_type);
+        if let Err(e) = boundary_res {
+            return Err(DeserializationError::from(e).into());
+        }
+
+        let stream = futures_util::stream::once(async move { Ok::<Bytes, multer::Error>(body) });
+        let mut multipart = multer::Multipart::with_constraints(
+            stream,
+            boundary_res.unwrap(),
+            limits.constraints(),
+        );
+
+        // The whole body is already in memory, so every future is ready the first time it is
+        // polled and there is no need to block on a runtime
+        match read_parts(&mut multipart, limits.max_parts).now_or_never() {
+            Some(Ok(parts)) => Ok(Multipart { parts }),
+            Some(Err(e)) => Err(e),
+            None => Err(RequestError::with_message(
+                ErrorType::RequestBodyUnreadable,
+                "Incomplete multipart body",
+            )),
+        }
+    }
+
+    pub fn parts(&self) -> &[MultipartPart] {
+        &self.parts
+    }
+
+    /// Parts without a filename, the regular form fields
+    pub fn fields(&self) -> impl Iterator<Item = &MultipartPart> {
+        self.parts.iter().filter(|part| !part.is_file())
+    }
+
+    pub fn files(&self) -> impl Iterator<Item = &MultipartPart> {
+        self.parts.iter().filter(|part| part.is_file())
+    }
+
+    /// First part with the given field name
+    pub fn get(&self, name: &str) -> Option<&MultipartPart> {
+        self.parts
+            .iter()
+            .find(|part| part.name.as_deref() == Some(name))
+    }
+}
+
+async fn read_parts(
+    multipart: &mut multer::Multipart<'_>,
+    max_parts: usize,
+) -> Result<Vec<MultipartPart>, RequestError> {
+    let mut parts = Vec::new();
+    while let Some(field) = multipart.next_field().await.map_err(multipart_error)? {
+        if parts.len() == max_parts {
+            return Err(RequestError::with_message(
+                ErrorType::PayloadTooLarge,
+                &format!("Multipart body exceeds the limit of {} parts", max_parts),
+            ));
+        }
+        let name = field.name().map(str::to_string);
+        let filename = field.file_name().map(str::to_string);
+        let content_type = field.content_type().map(|mime| mime.to_string());
+        let data = field.bytes().await.map_err(multipart_error)?;
+        parts.push(MultipartPart {
+            name,
+            filename,
+            content_type,
+            data,
+        });
+    }
+
+    Ok(parts)
+}
+
+fn multipart_error(error: multer::Error) -> RequestError {
+    match error {
+        multer::Error::FieldSizeExceeded { .. } | multer::Error::StreamSizeExceeded { .. } => {
+            RequestError::with_message(ErrorType::PayloadTooLarge, &error.to_string())
+        }
+        _ => DeserializationError::from(error).into(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_multipart_test() {
+        let body = "--X-BOUNDARY\r\n\
+            Content-Disposition: form-data; name=\"title\"\r\n\
+            \r\n\
+            Holidays\r\n\
+            --X-BOUNDARY\r\n\
+            Content-Disposition: form-data; name=\"photo\"; filename=\"beach.png\"\r\n\
+            Content-Type: image/png\r\n\
+            \r\n\
+            PNGDATA\r\n\
+            --X-BOUNDARY--\r\n";
+
+        let multipart = Multipart::parse(
+            "multipart/form-data; boundary=X-BOUNDARY",
+            Bytes::from(body),
+        )
+        .unwrap();
+
+        assert_eq!(multipart.parts().len(), 2);
+        assert_eq!(multipart.get("title").unwrap().text(), Some("Holidays"));
+        assert_eq!(multipart.fields().count(), 1);
+
+        let photo = multipart.files().next().unwrap();
+        assert_eq!(photo.name.as_deref(), Some("photo"));
+        assert_eq!(photo.filename.as_deref(), Some("beach.png"));
+        assert_eq!(photo.content_type.as_deref(), Some("image/png"));
+        assert_eq!(photo.data, Bytes::from("PNGDATA"));
+    }
+
+    #[test]
+    fn missing_boundary_test() {
+        let error = Multipart::parse("multipart/form-data", Bytes::from("")).unwrap_err();
+
+        assert_eq!(
+            crate::response::Response::from(error).status,
+            hyper::StatusCode::BAD_REQUEST
+        );
+    }
+
+    #[test]
+    fn multipart_limits_test() {
+        let part = |name: &str| {
+            format!(
+                "--X-BOUNDARY\r\n\
+                Content-Disposition: form-data; name=\"{}\"\r\n\
+                \r\n\
+                value\r\n",
+                name
+            )
+        };
+        let body = format!("{}{}{}--X-BOUNDARY--\r\n", part("a"), part("b"), part("c"));
+        let parse = |limits: MultipartLimits| {
+            Multipart::parse_with_limits(
+                "multipart/form-data; boundary=X-BOUNDARY",
+                Bytes::from(body.clone()),
+                limits,
+            )
+        };
+
+        assert_eq!(parse(MultipartLimits::default()).unwrap().parts().len(), 3);
+
+        let too_many_parts = parse(MultipartLimits::default().max_parts(2)).unwrap_err();
+        assert_eq!(
+            too_many_parts.status(),
+            hyper::StatusCode::PAYLOAD_TOO_LARGE
+        );
+
+        let oversized_part = parse(MultipartLimits::default().max_part_size(4)).unwrap_err();
+        assert_eq!(
+            oversized_part.status(),
+            hyper::StatusCode::PAYLOAD_TOO_LARGE
+        );
+
+        let oversized_body = parse(MultipartLimits::default().max_total_size(64)).unwrap_err();
+        assert_eq!(
+            oversized_body.status(),
+            hyper::StatusCode::PAYLOAD_TOO_LARGE
+        );
+    }
+}
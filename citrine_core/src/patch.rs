@@ -0,0 +1,314 @@
+use derive_more::derive::Display;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+use crate::error::{ErrorType, RequestError};
+
+/// RFC 7386 JSON Merge Patch document. Members of the patch replace the ones of the target, null
+/// members are removed from it and nested objects are merged recursively
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct MergePatch(Value);
+
+impl MergePatch {
+    pub fn new(patch: Value) -> Self {
+        MergePatch(patch)
+    }
+
+    pub fn apply(&self, target: &mut Value) {
+        merge(target, &self.0);
+    }
+
+    /// Applies the patch to the JSON representation of the target and reads the result back
+    pub fn apply_to<T>(&self, target: &T) -> Result<T, RequestError>
+    where
+        T: Serialize + DeserializeOwned,
+    {
+        let mut value = to_value(target)?;
+        self.apply(&mut value);
+        from_value(value)
+    }
+}
+
+fn merge(target: &mut Value, patch: &Value) {
+    if let Value::Object(patch_members) = patch {
+        if !target.is_object() {
+            *target = Value::Object(Map::new());
+        }
+        let target_members = target.as_object_mut().unwrap();
+        for (key, value) in patch_members {
+            if value.is_null() {
+                target_members.remove(key);
+            } else {
+                merge(target_members.entry(key.clone()).or_insert(Value::Null), value);
+            }
+        }
+    } else {
+        *target = patch.clone();
+    }
+}
+
+/// RFC 6902 JSON Patch document. Operations are applied in order and if any of them fails the
+/// target is left untouched
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct JsonPatch(Vec<PatchOperation>);
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(tag = "op", rename_all = "lowercase")]
+pub enum PatchOperation {
+    Add { path: String, value: Value },
+    Remove { path: String },
+    Replace { path: String, value: Value },
+    Move { from: String, path: String },
+    Copy { from: String, path: String },
+    Test { path: String, value: Value },
+}
+
+#[derive(Debug, Display)]
+#[display("Could not apply patch: {}", cause)]
+pub struct PatchError {
+    cause: String,
+}
+
+impl PatchError {
+    fn new(cause: String) -> Self {
+        PatchError { cause }
+    }
+}
+
+impl std::error::Error for PatchError {}
+
+impl From<PatchError> for RequestError {
+    fn from(error: PatchError) -> Self {
+        RequestError::with_message(ErrorType::InvalidPatch, &error.cause)
+    }
+}
+
+impl JsonPatch {
+    pub fn new(operations: Vec<PatchOperation>) -> Self {
+        JsonPatch(operations)
+    }
+
+    pub fn operations(&self) -> &[PatchOperation] {
+        &self.0
+    }
+
+    pub fn apply(&self, target: &mut Value) -> Result<(), PatchError> {
+        let mut patched = target.clone();
+        for operation in self.0.iter() {
+            apply_operation(&mut patched, operation)?;
+        }
+        *target = patched;
+        Ok(())
+    }
+
+    /// Applies the patch to the JSON representation of the target and reads the result back
+    pub fn apply_to<T>(&self, target: &T) -> Result<T, RequestError>
+    where
+        T: Serialize + DeserializeOwned,
+    {
+        let mut value = to_value(target)?;
+        self.apply(&mut value)?;
+        from_value(value)
+    }
+}
+
+fn apply_operation(target: &mut Value, operation: &PatchOperation) -> Result<(), PatchError> {
+    match operation {
+        PatchOperation::Add { path, value } => add(target, path, value.clone()),
+        PatchOperation::Remove { path } => remove(target, path).map(|_| ()),
+        PatchOperation::Replace { path, value } => match target.pointer_mut(path) {
+            Some(current) => {
+                *current = value.clone();
+                Ok(())
+            }
+            None => Err(PatchError::new(format!("No value to replace at {}", path))),
+        },
+        PatchOperation::Move { from, path } => {
+            if path.starts_with(&format!("{}/", from)) {
+                return Err(PatchError::new(format!(
+                    "Can't move {} into one of its children",
+                    from
+                )));
+            }
+            let value = remove(target, from)?;
+            add(target, path, value)
+        }
+        PatchOperation::Copy { from, path } => match target.pointer(from).cloned() {
+            Some(value) => add(target, path, value),
+            None => Err(PatchError::new(format!("No value to copy at {}", from))),
+        },
+        PatchOperation::Test { path, value } => {
+            if target.pointer(path) == Some(value) {
+                Ok(())
+            } else {
+                Err(PatchError::new(format!("Test failed for {}", path)))
+            }
+        }
+    }
+}
+
+/// Splits a JSON pointer into the pointer of the parent and the unescaped last reference token
+fn split_pointer(path: &str) -> Result<(&str, String), PatchError> {
+    match path.rfind('/') {
+        Some(index) => Ok((
+            &path[..index],
+            path[index + 1..].replace("~1", "/").replace("~0", "~"),
+        )),
+        None => Err(PatchError::new(format!("Invalid JSON pointer {}", path))),
+    }
+}
+
+fn add(target: &mut Value, path: &str, value: Value) -> Result<(), PatchError> {
+    if path.is_empty() {
+        *target = value;
+        return Ok(());
+    }
+
+    let (parent_path, token) = split_pointer(path)?;
+    match target.pointer_mut(parent_path) {
+        Some(Value::Object(members)) => {
+            members.insert(token, value);
+            Ok(())
+        }
+        Some(Value::Array(elements)) => {
+            if token == "-" {
+                elements.push(value);
+                return Ok(());
+            }
+            match token.parse::<usize>() {
+                Ok(index) if index <= elements.len() => {
+                    elements.insert(index, value);
+                    Ok(())
+                }
+                _ => Err(PatchError::new(format!("Invalid array index in {}", path))),
+            }
+        }
+        _ => Err(PatchError::new(format!("No container to add to at {}", path))),
+    }
+}
+
+fn remove(target: &mut Value, path: &str) -> Result<Value, PatchError> {
+    let (parent_path, token) = split_pointer(path)?;
+    let removed = match target.pointer_mut(parent_path) {
+        Some(Value::Object(members)) => members.remove(&token),
+        Some(Value::Array(elements)) => match token.parse::<usize>() {
+            Ok(index) if index < elements.len() => Some(elements.remove(index)),
+            _ => None,
+        },
+        _ => None,
+    };
+
+    removed.ok_or_else(|| PatchError::new(format!("No value to remove at {}", path)))
+}
+
+fn to_value<T: Serialize>(target: &T) -> Result<Value, RequestError> {
+    serde_json::to_value(target)
+        .map_err(|e| RequestError::with_message(ErrorType::Internal, &e.to_string()))
+}
+
+fn from_value<T: DeserializeOwned>(value: Value) -> Result<T, RequestError> {
+    serde_json::from_value(value)
+        .map_err(|e| RequestError::with_message(ErrorType::InvalidPatch, &e.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[derive(Serialize, Deserialize, Debug, PartialEq)]
+    struct User {
+        username: String,
+        mail: String,
+    }
+
+    #[test]
+    fn merge_patch_test() {
+        let mut user = json!({
+            "id": "1",
+            "username": "alice123",
+            "mail": "alice@example.com",
+            "tags": ["admin"],
+            "profile": { "bio": "Hi", "theme": "dark" }
+        });
+
+        MergePatch::new(json!({
+            "mail": "alice@example.org",
+            "tags": null,
+            "profile": { "theme": null, "language": "en" }
+        }))
+        .apply(&mut user);
+
+        assert_eq!(
+            user,
+            json!({
+                "id": "1",
+                "username": "alice123",
+                "mail": "alice@example.org",
+                "profile": { "bio": "Hi", "language": "en" }
+            })
+        );
+    }
+
+    #[test]
+    fn merge_patch_typed_test() {
+        let user = User {
+            username: "alice123".to_string(),
+            mail: "alice@example.com".to_string(),
+        };
+
+        let patched = MergePatch::new(json!({ "username": "alice" }))
+            .apply_to(&user)
+            .unwrap();
+
+        assert_eq!(patched.username, "alice");
+        assert_eq!(patched.mail, "alice@example.com");
+    }
+
+    #[test]
+    fn json_patch_test() {
+        let mut user = json!({
+            "id": "1",
+            "username": "alice123",
+            "mail": "alice@example.com",
+            "tags": ["admin"]
+        });
+
+        let patch: JsonPatch = serde_json::from_value(json!([
+            { "op": "test", "path": "/id", "value": "1" },
+            { "op": "replace", "path": "/username", "value": "alice" },
+            { "op": "add", "path": "/tags/-", "value": "editor" },
+            { "op": "add", "path": "/tags/0", "value": "owner" },
+            { "op": "remove", "path": "/mail" },
+            { "op": "copy", "from": "/id", "path": "/legacy_id" },
+            { "op": "move", "from": "/legacy_id", "path": "/old_id" }
+        ]))
+        .unwrap();
+        patch.apply(&mut user).unwrap();
+
+        assert_eq!(
+            user,
+            json!({
+                "id": "1",
+                "username": "alice",
+                "tags": ["owner", "admin", "editor"],
+                "old_id": "1"
+            })
+        );
+    }
+
+    #[test]
+    fn failed_json_patch_test() {
+        let mut user = json!({ "id": "1", "username": "alice123" });
+
+        let patch: JsonPatch = serde_json::from_value(json!([
+            { "op": "replace", "path": "/username", "value": "alice" },
+            { "op": "test", "path": "/id", "value": "2" }
+        ]))
+        .unwrap();
+
+        assert!(patch.apply(&mut user).is_err());
+        assert_eq!(user, json!({ "id": "1", "username": "alice123" }));
+    }
+}
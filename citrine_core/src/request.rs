@@ -1,32 +1,76 @@
-use std::{collections::HashMap, io::Read};
+use std::{
+    borrow::Cow,
+    collections::HashMap,
+    fmt::Debug,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+};
 
-use http_body_util::BodyExt;
+use futures_util::{Stream, StreamExt};
+use http_body_util::{BodyExt, LengthLimitError, Limited};
 use hyper::{
-    body::{Buf, Incoming},
+    body::{Body, Bytes, Incoming},
+    header::{HeaderName, CONTENT_LENGTH, CONTENT_TYPE, COOKIE, IF_NONE_MATCH},
+    http::Extensions,
     HeaderMap, Method, Uri,
 };
-use serde::de::DeserializeOwned;
+use serde::de::{value::StrDeserializer, DeserializeOwned};
 use validator::Validate;
 
 use crate::{
+    cookie,
     error::{DeserializationError, ErrorType, RequestError},
+    multipart::{Multipart, MultipartLimits},
+    patch::{JsonPatch, MergePatch},
+    router::RouteUrls,
     security::security_configuration::AuthResult,
+    ServerError,
 };
 
+/// Headers that keep the traces of a request connected across services: W3C Trace Context and
+/// Baggage, and the usual request and correlation ids
+const PROPAGATION_HEADERS: [&str; 5] = [
+    "traceparent",
+    "tracestate",
+    "baggage",
+    "x-request-id",
+    "x-correlation-id",
+];
+
 pub struct RequestMetadata {
     pub method: Method,
     pub uri: Uri,
     pub headers: HeaderMap,
-    pub original_request: hyper::Request<hyper::body::Incoming>,
+    // Unread request body. None for requests built inside the framework, like the ones in tests
+    pub body: Option<Incoming>,
+    // Typed data added while authenticating the request, passed on to the handler
+    pub extensions: Extensions,
+}
+
+impl RequestMetadata {
+    pub fn new(method: Method, uri: Uri, headers: HeaderMap) -> Self {
+        RequestMetadata {
+            method,
+            uri,
+            headers,
+            body: None,
+            extensions: Extensions::new(),
+        }
+    }
 }
 
 impl From<hyper::Request<Incoming>> for RequestMetadata {
     fn from(req: hyper::Request<Incoming>) -> Self {
+        let (parts, body) = req.into_parts();
         RequestMetadata {
-            method: req.method().clone(),
-            uri: req.uri().clone(),
-            headers: req.headers().clone(),
-            original_request: req,
+            method: parts.method,
+            uri: parts.uri,
+            headers: parts.headers,
+            body: Some(body),
+            extensions: parts.extensions,
         }
     }
 }
@@ -35,26 +79,157 @@ impl From<hyper::Request<Incoming>> for RequestMetadata {
 pub struct Request {
     pub method: Method,
     pub uri: Uri,
-    body: Option<String>,
+    body: Option<Bytes>,
     path_variables: HashMap<String, String>,
     pub headers: HeaderMap,
     pub auth_result: AuthResult,
     content_type: Option<ContentType>,
+    trim_strings: bool,
+    form_limits: FormLimits,
+    multipart_limits: MultipartLimits,
+    body_stream: BodyStream,
+    shutdown_state: ShutdownState,
+    extensions: Extensions,
+}
+
+/// Shared flag that is set when the server starts shutting down gracefully, so long running
+/// handlers and streams can wind down
+#[derive(Debug, Clone, Default)]
+pub struct ShutdownState {
+    shutting_down: Arc<AtomicBool>,
+    connections: Arc<AtomicUsize>,
+}
+
+impl ShutdownState {
+    pub fn is_shutting_down(&self) -> bool {
+        self.shutting_down.load(Ordering::Acquire)
+    }
+
+    /// Connections being served, including the ones still draining after the shutdown begins
+    pub fn in_flight_connections(&self) -> usize {
+        self.connections.load(Ordering::Acquire)
+    }
+
+    pub(crate) fn begin_shutdown(&self) {
+        self.shutting_down.store(true, Ordering::Release);
+    }
+
+    /// Counts a connection as in flight until the returned guard is dropped
+    pub(crate) fn track_connection(&self) -> ConnectionGuard {
+        self.connections.fetch_add(1, Ordering::AcqRel);
+        ConnectionGuard(self.connections.clone())
+    }
+}
+
+pub(crate) struct ConnectionGuard(Arc<AtomicUsize>);
+
+impl Drop for ConnectionGuard {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+/// How much of the request body is kept for the response middlewares and interceptor once the
+/// handler has run, to avoid retaining and logging large bodies
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum BodyLogging {
+    #[default]
+    Full,
+    /// Only the first bytes of the body
+    Prefix(usize),
+    Skip,
+}
+
+/// Default limit on the number of fields of form-urlencoded bodies
+pub const DEFAULT_MAX_FORM_FIELDS: usize = 1000;
+
+/// Limits on the fields of form-urlencoded bodies, checked before they are parsed so forms with
+/// an excessive number of fields, as in hash flooding attacks, are rejected with a 413 Payload Too
+/// Large
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FormLimits {
+    max_fields: usize,
+    max_field_size: Option<usize>,
+}
+
+impl FormLimits {
+    pub fn max_fields(mut self, max_fields: usize) -> Self {
+        self.max_fields = max_fields;
+        self
+    }
+
+    /// Limit in bytes of the encoded name and value of each field. Fields are only bounded by the
+    /// body size by default
+    pub fn max_field_size(mut self, max_field_size: usize) -> Self {
+        self.max_field_size = Some(max_field_size);
+        self
+    }
+
+    fn check(&self, body: &Bytes) -> Result<(), RequestError> {
+        let fields = body
+            .split(|byte| *byte == b'&')
+            .filter(|field| !field.is_empty());
+        for (index, field) in fields.enumerate() {
+            if index >= self.max_fields {
+                return Err(RequestError::with_message(
+                    ErrorType::PayloadTooLarge,
+                    &format!("Form exceeds the limit of {} fields", self.max_fields),
+                ));
+            }
+            if let Some(max_field_size) = self.max_field_size {
+                if field.len() > max_field_size {
+                    return Err(RequestError::with_message(
+                        ErrorType::PayloadTooLarge,
+                        &format!("Form field exceeds the limit of {} bytes", max_field_size),
+                    ));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Default for FormLimits {
+    fn default() -> Self {
+        FormLimits {
+            max_fields: DEFAULT_MAX_FORM_FIELDS,
+            max_field_size: None,
+        }
+    }
+}
+
+pub type RequestBodyStream = Pin<Box<dyn Stream<Item = Result<Bytes, ServerError>> + Send>>;
+
+/// Body of a request to a streaming route. It is shared between the clones of the request and can
+/// only be taken once
+#[derive(Clone, Default)]
+struct BodyStream(Arc<Mutex<BodyStreamState>>);
+
+#[derive(Default)]
+enum BodyStreamState {
+    #[default]
+    None,
+    Unread(RequestBodyStream),
+    // Remembered so reading the body again fails with a clear error instead of a missing body
+    Consumed,
+}
+
+impl Debug for BodyStream {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "BodyStream")
+    }
 }
 
 impl Request {
     pub fn new(
         method: Method,
         uri: Uri,
-        body: String,
+        body: impl Into<Bytes>,
         headers: HeaderMap,
         auth_result: AuthResult,
     ) -> Self {
-        let body = if method == Method::GET || body.is_empty() {
-            None
-        } else {
-            Some(body)
-        };
+        let body: Bytes = body.into();
+        let body = if body.is_empty() { None } else { Some(body) };
         Request {
             method,
             uri,
@@ -63,28 +238,111 @@ impl Request {
             headers,
             auth_result,
             content_type: None,
+            trim_strings: false,
+            form_limits: FormLimits::default(),
+            multipart_limits: MultipartLimits::default(),
+            body_stream: BodyStream::default(),
+            shutdown_state: ShutdownState::default(),
+            extensions: Extensions::new(),
+        }
+    }
+
+    pub fn with_body_stream(
+        self,
+        body_stream: impl Stream<Item = Result<Bytes, ServerError>> + Send + 'static,
+    ) -> Self {
+        *self.body_stream.0.lock().unwrap() = BodyStreamState::Unread(Box::pin(body_stream));
+        self
+    }
+
+    /// Builds the request without reading the body, which is left for the handler to consume
+    /// with body_stream
+    pub fn from_metadata_streaming(metadata: RequestMetadata, auth_result: AuthResult) -> Self {
+        let mut request = Request::new(
+            metadata.method,
+            metadata.uri,
+            String::new(),
+            metadata.headers,
+            auth_result,
+        );
+        request.extensions = metadata.extensions;
+
+        match metadata.body {
+            Some(body) => request.with_body_stream(
+                body.into_data_stream()
+                    .map(|chunk| chunk.map_err(ServerError::from)),
+            ),
+            None => request,
         }
     }
 
+    /// Reads the whole body of the request. If a maximum body size is set, bodies that exceed it
+    /// are rejected with a PayloadTooLarge error without reading the rest of the body
     pub async fn from_metadata_and_auth(
-        mut metadata: RequestMetadata,
+        metadata: RequestMetadata,
         auth_result: AuthResult,
-    ) -> Result<Self, Box<dyn std::error::Error>> {
-        let req_body = metadata.original_request.body_mut().collect().await?;
+        max_body_size: Option<usize>,
+    ) -> Result<Self, RequestError> {
+        // Bodies that declare a bigger size are rejected before reading anything. Bodies
+        // without a Content-Length, like chunked ones, are checked while they are read
+        let content_length = declared_content_length(&metadata.headers);
+        if let Some(max_body_size) = max_body_size {
+            if content_length.is_some_and(|content_length| content_length > max_body_size) {
+                return Err(payload_too_large(max_body_size));
+            }
+        }
 
-        let mut body_string = String::new();
-        req_body
-            .aggregate()
-            .reader()
-            .read_to_string(&mut body_string)?;
+        let body_bytes = match metadata.body {
+            Some(body) => {
+                let body_bytes = read_body(body, max_body_size).await?;
+                check_content_length(content_length, &body_bytes)?;
+                body_bytes
+            }
+            None => Bytes::new(),
+        };
 
-        Ok(Request::new(
+        let mut request = Request::new(
             metadata.method,
             metadata.uri,
-            body_string,
+            body_bytes,
             metadata.headers,
             auth_result,
-        ))
+        );
+        request.extensions = metadata.extensions;
+        Ok(request)
+    }
+
+    pub fn raw_uri(&self) -> &Uri {
+        &self.uri
+    }
+
+    pub fn path(&self) -> &str {
+        self.uri.path()
+    }
+
+    /// Query string without the leading '?'. Empty if the request has no query
+    pub fn query_string(&self) -> &str {
+        self.uri.query().unwrap_or("")
+    }
+
+    pub fn full_path_and_query(&self) -> &str {
+        self.uri
+            .path_and_query()
+            .map(|path_and_query| path_and_query.as_str())
+            .unwrap_or(self.uri.path())
+    }
+
+    pub(crate) fn set_shutdown_state(&mut self, shutdown_state: ShutdownState) {
+        self.shutdown_state = shutdown_state;
+    }
+
+    /// Can be cloned and moved into streams or tasks that outlive the handler
+    pub fn shutdown_state(&self) -> &ShutdownState {
+        &self.shutdown_state
+    }
+
+    pub fn is_shutting_down(&self) -> bool {
+        self.shutdown_state.is_shutting_down()
     }
 
     pub fn set_path_variables(&mut self, path_variables: HashMap<String, String>) {
@@ -95,23 +353,209 @@ impl Request {
         &self.path_variables
     }
 
+    /// Typed data attached to the request, like the state of the router that handles it
+    pub fn extensions(&self) -> &Extensions {
+        &self.extensions
+    }
+
+    pub fn extensions_mut(&mut self) -> &mut Extensions {
+        &mut self.extensions
+    }
+
+    /// State added with Router::with_state to the router of the route, or any of its parents
+    pub fn state<S: Send + Sync + 'static>(&self) -> Option<Arc<S>> {
+        self.extensions.get::<Arc<S>>().cloned()
+    }
+
+    /// Claims of the authenticated user deserialized into an application defined type, like a
+    /// struct with the claims the application uses. Returns None if there are no claims or they
+    /// don't match the type
+    pub fn get_claims_as<C: DeserializeOwned>(&self) -> Option<C> {
+        self.auth_result.get_claims_as()
+    }
+
+    /// Value of a cookie sent by the client in the Cookie headers
+    pub fn get_cookie(&self, name: &str) -> Option<String> {
+        self.headers
+            .get_all(COOKIE)
+            .iter()
+            .filter_map(|header| header.to_str().ok())
+            .find_map(|header| cookie::find_cookie(header, name))
+    }
+
+    /// Whether the If-None-Match headers contain the ETag or *, so the client already has the
+    /// current version and a 304 Not Modified can be returned. Comparison is weak, as RFC 9110
+    /// requires for If-None-Match, so W/"v1" matches "v1"
+    pub fn if_none_match(&self, etag: &str) -> bool {
+        let etag = etag.trim_start_matches("W/");
+        self.headers
+            .get_all(IF_NONE_MATCH)
+            .iter()
+            .filter_map(|header| header.to_str().ok())
+            .flat_map(|header| header.split(','))
+            .map(|candidate| candidate.trim())
+            .any(|candidate| candidate == "*" || candidate.trim_start_matches("W/") == etag)
+    }
+
+    pub fn headers(&self) -> &HeaderMap {
+        &self.headers
+    }
+
+    /// Method, URI and headers of the request, without its body
+    pub(crate) fn metadata(&self) -> RequestMetadata {
+        RequestMetadata::new(self.method.clone(), self.uri.clone(), self.headers.clone())
+    }
+
+    /// Tracing and correlation headers of the request, to be forwarded in the calls the handler
+    /// makes to other services so their traces stay connected to this one
+    pub fn propagation_headers(&self) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for name in PROPAGATION_HEADERS {
+            for value in self.headers.get_all(name) {
+                headers.append(HeaderName::from_static(name), value.clone());
+            }
+        }
+        headers
+    }
+
     pub fn set_content_type(&mut self, content_type: ContentType) {
         self.content_type = Some(content_type);
     }
 
-    pub fn get_body_raw(&self) -> &Option<String> {
-        &self.body
+    /// Content type of the body, matched against the ones accepted by the route
+    pub fn content_type(&self) -> Option<ContentType> {
+        self.content_type
+    }
+
+    /// URL of a route added with Router::add_named_route, with its path variables replaced by the
+    /// given values. Only available once the request has reached a route
+    pub fn url_for(
+        &self,
+        name: &str,
+        path_variables: &[(&str, &str)],
+    ) -> Result<String, ServerError> {
+        match self.extensions.get::<RouteUrls>() {
+            Some(route_urls) => route_urls.url_for(name, path_variables),
+            None => Err(ServerError::from(
+                "Route URLs are only available to requests that reached a route",
+            )),
+        }
+    }
+
+    /// String fields of the body are trimmed before deserializing it with get_body and
+    /// get_body_validated
+    pub(crate) fn set_trim_strings(&mut self, trim_strings: bool) {
+        self.trim_strings = trim_strings;
+    }
+
+    /// Limits checked before form bodies are deserialized with get_body and get_body_validated
+    pub(crate) fn set_form_limits(&mut self, form_limits: FormLimits) {
+        self.form_limits = form_limits;
+    }
+
+    /// Limits checked while multipart bodies are parsed with get_multipart
+    pub(crate) fn set_multipart_limits(&mut self, multipart_limits: MultipartLimits) {
+        self.multipart_limits = multipart_limits;
+    }
+
+    /// Takes the body of a request to a streaming route, so it can be read chunk by chunk without
+    /// holding it all in memory. It returns None if the route doesn't stream its body or if it was
+    /// already taken
+    pub fn body_stream(&self) -> Option<RequestBodyStream> {
+        let mut state = self.body_stream.0.lock().unwrap();
+        match std::mem::replace(&mut *state, BodyStreamState::Consumed) {
+            BodyStreamState::Unread(body_stream) => Some(body_stream),
+            previous => {
+                *state = previous;
+                None
+            }
+        }
+    }
+
+    /// Whether the body was already taken with body_stream. The body can only be read once, so
+    /// middlewares that read it leave nothing for the handler
+    pub fn is_body_consumed(&self) -> bool {
+        matches!(
+            *self.body_stream.0.lock().unwrap(),
+            BodyStreamState::Consumed
+        )
+    }
+
+    /// Error for reading a body the request doesn't have, telling apart the bodies that are
+    /// streamed or were already consumed from the missing ones
+    fn missing_body_error(&self) -> RequestError {
+        match *self.body_stream.0.lock().unwrap() {
+            BodyStreamState::None => RequestError::default(ErrorType::MissingBody),
+            BodyStreamState::Unread(_) => RequestError::with_message(
+                ErrorType::MissingBody,
+                "The body of streaming routes must be read with Request::body_stream",
+            ),
+            BodyStreamState::Consumed => RequestError::with_message(
+                ErrorType::BodyAlreadyConsumed,
+                "The body was already read with Request::body_stream",
+            ),
+        }
+    }
+
+    /// Body as text. None if there is no body or it isn't valid UTF-8, like binary uploads,
+    /// which can be read with get_body_bytes
+    pub fn get_body_raw(&self) -> Option<&str> {
+        self.body
+            .as_ref()
+            .and_then(|body| std::str::from_utf8(body).ok())
+    }
+
+    pub fn get_body_bytes(&self) -> Option<&Bytes> {
+        self.body.as_ref()
+    }
+
+    /// Body to be written in logs, with invalid UTF-8 replaced. After the router has run it only
+    /// contains what the BodyLogging of the route allows
+    pub fn get_body_for_logging(&self) -> Option<Cow<'_, str>> {
+        self.body.as_ref().map(|body| String::from_utf8_lossy(body))
+    }
+
+    pub(crate) fn discard_body(&mut self) {
+        self.body = None;
+    }
+
+    /// Drops the part of the body that should not be logged. The prefix is copied so the full
+    /// body can be freed
+    pub(crate) fn retain_body_for_logging(&mut self, body_logging: BodyLogging) {
+        self.body = match body_logging {
+            BodyLogging::Full => self.body.take(),
+            BodyLogging::Prefix(len) => self.body.take().map(|body| {
+                if body.len() > len {
+                    Bytes::copy_from_slice(&body[..len])
+                } else {
+                    body
+                }
+            }),
+            BodyLogging::Skip => None,
+        };
     }
 
     pub fn get_body<T>(&self) -> Result<T, RequestError>
     where
         T: DeserializeOwned,
     {
-        if self.body.is_none() || self.content_type.is_none() {
+        if self.body.is_none() {
+            return Err(self.missing_body_error());
+        }
+        if self.content_type.is_none() {
             return Err(RequestError::default(ErrorType::MissingBody));
         }
 
-        let body_res: Result<T, DeserializationError> = self.content_type.unwrap().parse(&self.body);
+        let content_type = self.content_type.unwrap();
+        let body = self.body.as_ref().unwrap();
+        if let ContentType::FormUrlEncoded = content_type {
+            self.form_limits.check(body)?;
+        }
+        let body_res: Result<T, DeserializationError> = if self.trim_strings {
+            content_type.parse_trimmed(body)
+        } else {
+            content_type.parse(body)
+        };
         if let Err(e) = body_res {
             return Err(e.into());
         }
@@ -119,6 +563,96 @@ impl Request {
         Ok(body_res.unwrap())
     }
 
+    /*
+     * Reads a JSON body without a target type, for schema-less or dynamic payloads. Only JSON
+     * media types are accepted, so form, text or XML bodies are rejected with a 415
+     */
+    pub fn body_json_value(&self) -> Result<serde_json::Value, RequestError> {
+        if !matches!(
+            self.content_type,
+            None | Some(
+                ContentType::Json | ContentType::MergePatchJson | ContentType::JsonPatchJson
+            )
+        ) {
+            return Err(RequestError::with_message(
+                ErrorType::UnsupportedMediaType,
+                "Request body is not JSON",
+            ));
+        }
+
+        self.get_body()
+    }
+
+    /*
+     * Reads a multipart/form-data body, with its fields and uploaded files
+     */
+    pub fn get_multipart(&self) -> Result<Multipart, RequestError> {
+        if self.body.is_none() {
+            return Err(self.missing_body_error());
+        }
+        if self.content_type.is_none() {
+            return Err(RequestError::default(ErrorType::MissingBody));
+        }
+        if !matches!(self.content_type, Some(ContentType::Multipart)) {
+            return Err(RequestError::with_message(
+                ErrorType::UnsupportedMediaType,
+                "Request body is not multipart/form-data",
+            ));
+        }
+
+        let content_type = self
+            .headers
+            .get(CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .unwrap_or_default();
+        Multipart::parse_with_limits(
+            content_type,
+            self.body.clone().unwrap(),
+            self.multipart_limits,
+        )
+    }
+
+    /*
+     * Reads the body as a JSON Merge Patch (RFC 7386) document
+     */
+    pub fn get_merge_patch(&self) -> Result<MergePatch, RequestError> {
+        self.get_body()
+    }
+
+    /*
+     * Reads the body as a JSON Patch (RFC 6902) document
+     */
+    pub fn get_json_patch(&self) -> Result<JsonPatch, RequestError> {
+        self.get_body()
+    }
+
+    /*
+     * Deserializes the query string. Repeated keys (?ids=1&ids=2) are read as a sequence and
+     * bracketed keys are read as nested structures (?filter[name]=x) or sequences (?ids[]=1)
+     */
+    pub fn get_query<T>(&self) -> Result<T, RequestError>
+    where
+        T: DeserializeOwned,
+    {
+        let query = self.query_string();
+
+        let query_res: Result<T, DeserializationError> = if has_bracketed_keys(query) {
+            serde_qs::Config::new(5, false)
+                .deserialize_str(&repeated_keys_as_sequences(query))
+                .map_err(DeserializationError::from)
+        } else {
+            serde_html_form::from_str(query).map_err(DeserializationError::from)
+        };
+
+        match query_res {
+            Ok(query) => Ok(query),
+            Err(e) => Err(RequestError::with_message(
+                ErrorType::RequestQueryUnreadable,
+                e.get_cause(),
+            )),
+        }
+    }
+
     pub fn get_body_validated<T>(&self) -> Result<T, RequestError>
     where
         T: DeserializeOwned + Validate,
@@ -133,33 +667,162 @@ impl Request {
     }
 }
 
+async fn read_body<B>(body: B, max_body_size: Option<usize>) -> Result<Bytes, RequestError>
+where
+    B: Body,
+    B::Error: Into<ServerError>,
+{
+    let collect_res = match max_body_size {
+        Some(max_body_size) => Limited::new(body, max_body_size).collect().await,
+        None => body.collect().await.map_err(Into::into),
+    };
+
+    if let Err(e) = collect_res {
+        if e.downcast_ref::<LengthLimitError>().is_some() {
+            return Err(payload_too_large(max_body_size.unwrap_or_default()));
+        }
+        return Err(RequestError::with_message(
+            ErrorType::RequestBodyUnreadable,
+            &e.to_string(),
+        ));
+    }
+
+    Ok(collect_res.unwrap().to_bytes())
+}
+
+fn declared_content_length(headers: &HeaderMap) -> Option<usize> {
+    headers
+        .get(CONTENT_LENGTH)
+        .and_then(|content_length| content_length.to_str().ok())
+        .and_then(|content_length| content_length.parse::<usize>().ok())
+}
+
+/// A body with a different size than the declared one was truncated or tampered with, so it is
+/// rejected with a 400 Bad Request instead of reaching the handler
+fn check_content_length(
+    content_length: Option<usize>,
+    body_bytes: &Bytes,
+) -> Result<(), RequestError> {
+    match content_length {
+        Some(content_length) if content_length != body_bytes.len() => {
+            Err(RequestError::with_message(
+                ErrorType::RequestBodyUnreadable,
+                &format!(
+                    "Content-Length of {} bytes does not match the {} bytes received",
+                    content_length,
+                    body_bytes.len()
+                ),
+            ))
+        }
+        _ => Ok(()),
+    }
+}
+
+fn payload_too_large(max_body_size: usize) -> RequestError {
+    RequestError::with_message(
+        ErrorType::PayloadTooLarge,
+        &format!("Request body exceeds the limit of {} bytes", max_body_size),
+    )
+}
+
+fn query_keys(query: &str) -> impl Iterator<Item = &str> {
+    query
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| pair.split('=').next().unwrap_or(""))
+}
+
+fn is_bracketed_key(key: &str) -> bool {
+    key.contains('[') || key.to_ascii_lowercase().contains("%5b")
+}
+
+fn has_bracketed_keys(query: &str) -> bool {
+    query_keys(query).any(is_bracketed_key)
+}
+
+/// serde_qs only reads sequences from bracketed keys, so plain keys that appear more than once
+/// are rewritten as key[]
+fn repeated_keys_as_sequences(query: &str) -> String {
+    let mut key_count = HashMap::<&str, usize>::new();
+    for key in query_keys(query).filter(|key| !is_bracketed_key(key)) {
+        *key_count.entry(key).or_insert(0) += 1;
+    }
+
+    query
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+            if key_count.get(key).copied().unwrap_or(0) > 1 {
+                format!("{}[]={}", key, value)
+            } else {
+                pair.to_string()
+            }
+        })
+        .collect::<Vec<String>>()
+        .join("&")
+}
 
 #[derive(Debug, Clone, Copy)]
 pub enum ContentType {
     Json,
     FormUrlEncoded,
+    MergePatchJson,
+    JsonPatchJson,
+    Multipart,
+    Text,
+    #[cfg(feature = "xml")]
+    Xml,
 }
 
 impl ContentType {
     pub fn is_valid(&self, content_type: &str) -> bool {
-        content_type == self.as_header_value()
+        match self {
+            // The header carries the boundary as a parameter, so only the media type is compared
+            Self::Multipart => content_type
+                .parse::<mime::Mime>()
+                .map(|mime| {
+                    mime.essence_str() == mime::MULTIPART_FORM_DATA.essence_str()
+                        && mime.get_param(mime::BOUNDARY).is_some()
+                })
+                .unwrap_or(false),
+            Self::Text => content_type
+                .parse::<mime::Mime>()
+                .map(|mime| mime.essence_str() == mime::TEXT_PLAIN.essence_str())
+                .unwrap_or(false),
+            // Legacy clients send text/xml, often with a charset parameter
+            #[cfg(feature = "xml")]
+            Self::Xml => content_type
+                .parse::<mime::Mime>()
+                .map(|mime| {
+                    mime.essence_str() == mime::TEXT_XML.essence_str()
+                        || mime.essence_str() == "application/xml"
+                })
+                .unwrap_or(false),
+            _ => content_type == self.as_header_value(),
+        }
     }
 
     pub fn as_header_value(&self) -> String {
         match self {
             Self::Json => mime::APPLICATION_JSON.to_string(),
             Self::FormUrlEncoded => mime::APPLICATION_WWW_FORM_URLENCODED.to_string(),
+            Self::MergePatchJson => "application/merge-patch+json".to_string(),
+            Self::JsonPatchJson => "application/json-patch+json".to_string(),
+            Self::Multipart => mime::MULTIPART_FORM_DATA.to_string(),
+            Self::Text => mime::TEXT_PLAIN.to_string(),
+            #[cfg(feature = "xml")]
+            Self::Xml => "application/xml".to_string(),
         }
     }
 
-    pub fn parse<T>(&self, body: &Option<String>) -> Result<T, DeserializationError>
+    pub fn parse<T>(&self, body: &Bytes) -> Result<T, DeserializationError>
     where
         T: DeserializeOwned,
     {
-        let body_str = body.as_ref().unwrap();
         match self {
-            ContentType::Json => {
-                let res: Result<T, _> = serde_json::from_str(body_str);
+            ContentType::Json | ContentType::MergePatchJson | ContentType::JsonPatchJson => {
+                let res: Result<T, _> = serde_json::from_slice(body);
                 if let Err(e) = res {
                     Err(e.into())
                 } else {
@@ -167,13 +830,589 @@ impl ContentType {
                 }
             }
             ContentType::FormUrlEncoded => {
-                let res: Result<T, _> = serde_html_form::from_str(body_str);
+                let res: Result<T, _> = serde_html_form::from_bytes(body);
                 if let Err(e) = res {
                     Err(e.into())
                 } else {
                     Ok(res.unwrap())
                 }
             }
+            ContentType::Multipart => Err(DeserializationError::with_cause(
+                "Multipart bodies must be read with Request::get_multipart",
+            )),
+            ContentType::Text => {
+                let text = std::str::from_utf8(body).map_err(|e| DeserializationError::new(&e))?;
+                deserialize_text(text)
+            }
+            #[cfg(feature = "xml")]
+            ContentType::Xml => {
+                let xml = std::str::from_utf8(body).map_err(|e| DeserializationError::new(&e))?;
+                quick_xml::de::from_str(xml).map_err(|e| DeserializationError::new(&e))
+            }
+        }
+    }
+
+    /// Like parse, with leading and trailing whitespace removed from every string in the body,
+    /// nested ones included, before deserializing it into the target type
+    pub fn parse_trimmed<T>(&self, body: &Bytes) -> Result<T, DeserializationError>
+    where
+        T: DeserializeOwned,
+    {
+        match self {
+            ContentType::Json | ContentType::MergePatchJson | ContentType::JsonPatchJson => {
+                let mut value: serde_json::Value = serde_json::from_slice(body)?;
+                trim_json_strings(&mut value);
+                Ok(serde_json::from_value(value)?)
+            }
+            ContentType::FormUrlEncoded => {
+                let fields: Vec<(String, String)> = serde_html_form::from_bytes(body)?;
+                let fields: Vec<(String, String)> = fields
+                    .into_iter()
+                    .map(|(key, value)| (key, value.trim().to_string()))
+                    .collect();
+                let trimmed = serde_html_form::to_string(fields)
+                    .map_err(|e| DeserializationError::new(&e))?;
+                Ok(serde_html_form::from_str(&trimmed)?)
+            }
+            ContentType::Multipart => self.parse(body),
+            ContentType::Text => {
+                let text = std::str::from_utf8(body).map_err(|e| DeserializationError::new(&e))?;
+                deserialize_text(text.trim())
+            }
+            // Text is already trimmed by the XML deserializer
+            #[cfg(feature = "xml")]
+            ContentType::Xml => self.parse(body),
+        }
+    }
+}
+
+/// Plain text bodies are deserialized as a single string, so they can be read as a String or as any
+/// type that deserializes from one
+fn deserialize_text<T: DeserializeOwned>(text: &str) -> Result<T, DeserializationError> {
+    let deserializer = StrDeserializer::<serde::de::value::Error>::new(text);
+    T::deserialize(deserializer).map_err(|e| DeserializationError::new(&e))
+}
+
+fn trim_json_strings(value: &mut serde_json::Value) {
+    match value {
+        serde_json::Value::String(string) => {
+            let trimmed = string.trim();
+            if trimmed.len() != string.len() {
+                *string = trimmed.to_string();
+            }
         }
+        serde_json::Value::Array(values) => values.iter_mut().for_each(trim_json_strings),
+        serde_json::Value::Object(fields) => fields.values_mut().for_each(trim_json_strings),
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde::Deserialize;
+
+    use super::*;
+
+    #[derive(Deserialize, Debug, PartialEq)]
+    struct Filter {
+        name: String,
+    }
+
+    #[derive(Deserialize)]
+    struct ListQuery {
+        ids: Vec<u32>,
+        filter: Option<Filter>,
+    }
+
+    fn request_with_uri(uri: &'static str) -> Request {
+        Request::new(
+            Method::GET,
+            Uri::from_static(uri),
+            String::new(),
+            HeaderMap::new(),
+            AuthResult::Allowed,
+        )
+    }
+
+    #[tokio::test]
+    async fn body_stream_test() {
+        let chunk_size = 64 * 1024;
+        let chunks: Vec<Result<Bytes, ServerError>> = (0..32)
+            .map(|i| Ok(Bytes::from(vec![i as u8; chunk_size])))
+            .collect();
+        let request =
+            request_with_uri("/upload").with_body_stream(futures_util::stream::iter(chunks));
+
+        let mut body_stream = request.body_stream().unwrap();
+        let mut received_chunks = 0;
+        let mut received_bytes = 0;
+        while let Some(chunk) = body_stream.next().await {
+            let chunk = chunk.unwrap();
+            assert!(chunk.iter().all(|byte| *byte == received_chunks as u8));
+            received_chunks += 1;
+            received_bytes += chunk.len();
+        }
+
+        assert_eq!(received_chunks, 32);
+        assert_eq!(received_bytes, 32 * chunk_size);
+        assert!(request.body_stream().is_none());
+    }
+
+    #[test]
+    fn shutdown_state_test() {
+        let shutdown_state = ShutdownState::default();
+        let mut request = request_with_uri("/reports");
+        request.set_shutdown_state(shutdown_state.clone());
+        let handler = |request: &Request| {
+            if request.is_shutting_down() {
+                "rejected"
+            } else {
+                "accepted"
+            }
+        };
+
+        assert_eq!(handler(&request), "accepted");
+        shutdown_state.begin_shutdown();
+        assert_eq!(handler(&request), "rejected");
+        assert!(request.clone().shutdown_state().is_shutting_down());
+    }
+
+    #[tokio::test]
+    async fn body_size_limit_test() {
+        let body = http_body_util::Full::new(Bytes::from(vec![b'a'; 1024]));
+
+        assert_eq!(read_body(body.clone(), None).await.unwrap().len(), 1024);
+        assert_eq!(
+            read_body(body.clone(), Some(2048)).await.unwrap().len(),
+            1024
+        );
+
+        let error = read_body(body, Some(512)).await.unwrap_err();
+        assert_eq!(
+            crate::response::Response::from(error).status,
+            hyper::StatusCode::PAYLOAD_TOO_LARGE
+        );
+    }
+
+    #[test]
+    fn binary_body_test() {
+        let png_header = vec![0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A, 0xFF, 0x00];
+        let request = Request::new(
+            Method::POST,
+            Uri::from_static("/images"),
+            png_header.clone(),
+            HeaderMap::new(),
+            AuthResult::Allowed,
+        );
+
+        assert_eq!(
+            request.get_body_bytes().unwrap().as_ref(),
+            png_header.as_slice()
+        );
+        assert!(request.get_body_raw().is_none());
+
+        let request = Request::new(
+            Method::POST,
+            Uri::from_static("/notes"),
+            "Hello".to_string(),
+            HeaderMap::new(),
+            AuthResult::Allowed,
+        );
+        assert_eq!(request.get_body_raw(), Some("Hello"));
+    }
+
+    #[test]
+    fn body_already_consumed_test() {
+        let chunks: Vec<Result<Bytes, ServerError>> = vec![Ok(Bytes::from("{}"))];
+        let mut request =
+            request_with_uri("/upload").with_body_stream(futures_util::stream::iter(chunks));
+        request.set_content_type(ContentType::Json);
+
+        let error = request.get_body::<serde_json::Value>().unwrap_err();
+        assert_eq!(
+            crate::response::Response::from(error).status,
+            hyper::StatusCode::BAD_REQUEST
+        );
+        assert!(!request.is_body_consumed());
+
+        // The middleware takes the stream and the handler then tries to read the body again
+        let middleware_request = request.clone();
+        assert!(middleware_request.body_stream().is_some());
+        assert!(request.is_body_consumed());
+        assert!(request.body_stream().is_none());
+        let error = request.get_body::<serde_json::Value>().unwrap_err();
+        assert_eq!(
+            crate::response::Response::from(error).status,
+            hyper::StatusCode::INTERNAL_SERVER_ERROR
+        );
+    }
+
+    #[tokio::test]
+    async fn content_length_limit_test() {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            CONTENT_LENGTH,
+            hyper::header::HeaderValue::from_static("4096"),
+        );
+        let metadata = RequestMetadata::new(Method::POST, Uri::from_static("/upload"), headers);
+
+        let error = Request::from_metadata_and_auth(metadata, AuthResult::Allowed, Some(1024))
+            .await
+            .unwrap_err();
+        assert_eq!(
+            crate::response::Response::from(error).status,
+            hyper::StatusCode::PAYLOAD_TOO_LARGE
+        );
+    }
+
+    #[tokio::test]
+    async fn content_length_mismatch_test() {
+        let body = read_body(http_body_util::Full::new(Bytes::from("Hello")), None)
+            .await
+            .unwrap();
+
+        assert!(check_content_length(Some(5), &body).is_ok());
+        // Bodies without a Content-Length, like chunked ones, have nothing to compare against
+        assert!(check_content_length(None, &body).is_ok());
+
+        let error = check_content_length(Some(12), &body).unwrap_err();
+        assert_eq!(
+            crate::response::Response::from(error).status,
+            hyper::StatusCode::BAD_REQUEST
+        );
+        assert!(check_content_length(Some(3), &body).is_err());
+    }
+
+    #[derive(Deserialize, Validate, Debug)]
+    struct SignUp {
+        #[validate(email)]
+        email: String,
+        tags: Vec<String>,
+    }
+
+    #[test]
+    fn trim_strings_test() {
+        let mut request = Request::new(
+            Method::POST,
+            Uri::from_static("/sign-up"),
+            r#"{"email": "  alice@example.com\n", "tags": [" beta "]}"#.to_string(),
+            HeaderMap::new(),
+            AuthResult::Allowed,
+        );
+        request.set_content_type(ContentType::Json);
+        assert!(request.get_body_validated::<SignUp>().is_err());
+
+        request.set_trim_strings(true);
+        let body = request.get_body_validated::<SignUp>().unwrap();
+        assert_eq!(body.email, "alice@example.com");
+        assert_eq!(body.tags, vec!["beta"]);
+
+        let mut request = Request::new(
+            Method::POST,
+            Uri::from_static("/sign-up"),
+            "email=+alice%40example.com+&tags=beta".to_string(),
+            HeaderMap::new(),
+            AuthResult::Allowed,
+        );
+        request.set_content_type(ContentType::FormUrlEncoded);
+        request.set_trim_strings(true);
+        let body = request.get_body_validated::<SignUp>().unwrap();
+        assert_eq!(body.email, "alice@example.com");
+    }
+
+    #[test]
+    fn form_limits_test() {
+        let fields = (0..=DEFAULT_MAX_FORM_FIELDS)
+            .map(|index| format!("field{}=value", index))
+            .collect::<Vec<String>>()
+            .join("&");
+        let mut request = Request::new(
+            Method::POST,
+            Uri::from_static("/users"),
+            fields,
+            HeaderMap::new(),
+            AuthResult::Allowed,
+        );
+        request.set_content_type(ContentType::FormUrlEncoded);
+        let error = request.get_body::<HashMap<String, String>>().unwrap_err();
+        assert_eq!(error.status(), hyper::StatusCode::PAYLOAD_TOO_LARGE);
+
+        let mut request = Request::new(
+            Method::POST,
+            Uri::from_static("/users"),
+            "name=alice&bio=long+enough+to+be+rejected",
+            HeaderMap::new(),
+            AuthResult::Allowed,
+        );
+        request.set_content_type(ContentType::FormUrlEncoded);
+        assert!(request.get_body::<HashMap<String, String>>().is_ok());
+        request.set_form_limits(FormLimits::default().max_field_size(16));
+        let error = request.get_body::<HashMap<String, String>>().unwrap_err();
+        assert_eq!(error.status(), hyper::StatusCode::PAYLOAD_TOO_LARGE);
+    }
+
+    #[test]
+    fn propagation_headers_test() {
+        use hyper::header::{HeaderValue, AUTHORIZATION};
+
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            "traceparent",
+            HeaderValue::from_static("00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01"),
+        );
+        headers.insert("baggage", HeaderValue::from_static("tenant=acme"));
+        headers.insert("x-request-id", HeaderValue::from_static("abc123"));
+        headers.insert(AUTHORIZATION, HeaderValue::from_static("Bearer secret"));
+        headers.insert(COOKIE, HeaderValue::from_static("session=secret"));
+        let request = Request::new(
+            Method::GET,
+            Uri::from_static("/orders"),
+            String::new(),
+            headers,
+            AuthResult::Allowed,
+        );
+
+        let propagation_headers = request.propagation_headers();
+        assert_eq!(propagation_headers.len(), 3);
+        assert_eq!(
+            propagation_headers.get("traceparent").unwrap(),
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01"
+        );
+        assert_eq!(propagation_headers.get("baggage").unwrap(), "tenant=acme");
+        assert_eq!(propagation_headers.get("x-request-id").unwrap(), "abc123");
+        assert!(propagation_headers.get(AUTHORIZATION).is_none());
+    }
+
+    #[test]
+    fn query_repeated_keys_test() {
+        let query: ListQuery = request_with_uri("/users?ids=1&ids=2").get_query().unwrap();
+
+        assert_eq!(query.ids, vec![1, 2]);
+        assert_eq!(query.filter, None);
+    }
+
+    #[test]
+    fn body_json_value_test() {
+        let mut request = Request::new(
+            Method::POST,
+            Uri::from_static("/events"),
+            r#"{"type": "signup", "payload": {"user": "alice", "tags": ["beta"]}}"#.to_string(),
+            HeaderMap::new(),
+            AuthResult::Allowed,
+        );
+        request.set_content_type(ContentType::Json);
+
+        let body = request.body_json_value().unwrap();
+        assert_eq!(body["type"], "signup");
+        assert_eq!(body["payload"]["user"], "alice");
+        assert_eq!(body["payload"]["tags"][0], "beta");
+
+        request.set_content_type(ContentType::MergePatchJson);
+        assert_eq!(request.body_json_value().unwrap()["type"], "signup");
+
+        for content_type in [
+            ContentType::FormUrlEncoded,
+            ContentType::Text,
+            ContentType::Multipart,
+        ] {
+            request.set_content_type(content_type);
+            assert_eq!(
+                request.body_json_value().unwrap_err().status(),
+                hyper::StatusCode::UNSUPPORTED_MEDIA_TYPE
+            );
+        }
+    }
+
+    #[test]
+    fn get_cookie_test() {
+        let mut headers = HeaderMap::new();
+        headers.append(
+            COOKIE,
+            hyper::header::HeaderValue::from_static("theme=dark; session=abc123"),
+        );
+        headers.append(COOKIE, hyper::header::HeaderValue::from_static("lang=en"));
+        let request = Request::new(
+            Method::GET,
+            Uri::from_static("/"),
+            String::new(),
+            headers,
+            AuthResult::Allowed,
+        );
+
+        assert_eq!(request.get_cookie("session"), Some("abc123".to_string()));
+        assert_eq!(request.get_cookie("lang"), Some("en".to_string()));
+        assert!(request.get_cookie("missing").is_none());
+    }
+
+    #[derive(Deserialize, Debug, PartialEq)]
+    struct UserClaims {
+        sub: String,
+        roles: Vec<String>,
+        tenant: Option<String>,
+    }
+
+    #[test]
+    fn get_claims_as_test() {
+        let claims = serde_json::from_value(serde_json::json!({
+            "sub": "alice",
+            "roles": ["admin", "billing"],
+            "exp": 1924992000
+        }))
+        .unwrap();
+        let request = Request::new(
+            Method::GET,
+            Uri::from_static("/"),
+            String::new(),
+            HeaderMap::new(),
+            AuthResult::Authenticated(claims),
+        );
+
+        assert_eq!(
+            request.get_claims_as::<UserClaims>(),
+            Some(UserClaims {
+                sub: "alice".to_string(),
+                roles: vec!["admin".to_string(), "billing".to_string()],
+                tenant: None,
+            })
+        );
+        // Claims that don't match the type don't panic
+        assert!(request.get_claims_as::<ListQuery>().is_none());
+
+        let request = Request::new(
+            Method::GET,
+            Uri::from_static("/"),
+            String::new(),
+            HeaderMap::new(),
+            AuthResult::Allowed,
+        );
+        assert!(request.get_claims_as::<UserClaims>().is_none());
+    }
+
+    #[test]
+    fn multipart_body_test() {
+        let content_type = "multipart/form-data; boundary=citrine";
+        assert!(ContentType::Multipart.is_valid(content_type));
+        assert!(!ContentType::Multipart.is_valid("multipart/form-data"));
+        assert!(!ContentType::Json.is_valid(content_type));
+
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            CONTENT_TYPE,
+            hyper::header::HeaderValue::from_static(content_type),
+        );
+        let mut request = Request::new(
+            Method::POST,
+            Uri::from_static("/avatars"),
+            "--citrine\r\nContent-Disposition: form-data; name=\"avatar\"; filename=\"me.png\"\r\n\
+                Content-Type: image/png\r\n\r\nPNG\r\n--citrine--\r\n"
+                .to_string(),
+            headers,
+            AuthResult::Allowed,
+        );
+        request.set_content_type(ContentType::Multipart);
+
+        let multipart = request.get_multipart().unwrap();
+        let avatar = multipart.get("avatar").unwrap();
+        assert_eq!(avatar.filename.as_deref(), Some("me.png"));
+        assert_eq!(avatar.data, Bytes::from("PNG"));
+    }
+
+    #[test]
+    fn text_body_test() {
+        assert!(ContentType::Text.is_valid("text/plain"));
+        assert!(ContentType::Text.is_valid("text/plain; charset=utf-8"));
+        assert!(!ContentType::Text.is_valid("text/html"));
+
+        let mut request = Request::new(
+            Method::POST,
+            Uri::from_static("/webhooks/deploy"),
+            " deploy finished\n".to_string(),
+            HeaderMap::new(),
+            AuthResult::Allowed,
+        );
+        request.set_content_type(ContentType::Text);
+        assert_eq!(request.get_body::<String>().unwrap(), " deploy finished\n");
+
+        request.set_trim_strings(true);
+        assert_eq!(request.get_body::<String>().unwrap(), "deploy finished");
+    }
+
+    #[test]
+    #[cfg(feature = "xml")]
+    fn xml_body_test() {
+        use crate::response::ResponseBody;
+
+        assert!(ContentType::Xml.is_valid("application/xml"));
+        assert!(ContentType::Xml.is_valid("text/xml; charset=utf-8"));
+        assert!(!ContentType::Xml.is_valid("application/json"));
+
+        let mut request = Request::new(
+            Method::POST,
+            Uri::from_static("/sign-up"),
+            "<SignUp><email>alice@example.com</email><tags>beta</tags><tags>admin</tags></SignUp>"
+                .to_string(),
+            HeaderMap::new(),
+            AuthResult::Allowed,
+        );
+        request.set_content_type(ContentType::Xml);
+        let body: SignUp = request.get_body().unwrap();
+        assert_eq!(body.email, "alice@example.com");
+        assert_eq!(body.tags, vec!["beta", "admin"]);
+
+        // Errors are answered in the format of the request
+        let response = RequestError::default(ErrorType::NotFound).into_response_for(&request);
+        assert_eq!(
+            response.get_headers().get(CONTENT_TYPE).unwrap(),
+            "application/xml"
+        );
+        assert!(matches!(
+            response.get_body(),
+            Some(ResponseBody::Full(body)) if body.starts_with(b"<error><status>404 Not Found")
+        ));
+    }
+
+    #[test]
+    fn query_nested_keys_test() {
+        let query: ListQuery = request_with_uri("/users?ids=1&ids=2&filter%5Bname%5D=alice")
+            .get_query()
+            .unwrap();
+
+        assert_eq!(query.ids, vec![1, 2]);
+        assert_eq!(
+            query.filter,
+            Some(Filter {
+                name: "alice".to_string()
+            })
+        );
+    }
+
+    #[test]
+    fn uri_accessors_test() {
+        let request = Request::new(
+            Method::GET,
+            Uri::from_static("http://domain.com/api/users?page=2&size=10"),
+            String::new(),
+            HeaderMap::new(),
+            AuthResult::Allowed,
+        );
+
+        assert_eq!(request.path(), "/api/users");
+        assert_eq!(request.query_string(), "page=2&size=10");
+        assert_eq!(request.full_path_and_query(), "/api/users?page=2&size=10");
+        assert_eq!(
+            request.raw_uri().to_string(),
+            "http://domain.com/api/users?page=2&size=10"
+        );
+
+        let request = Request::new(
+            Method::GET,
+            Uri::from_static("/api/users"),
+            String::new(),
+            HeaderMap::new(),
+            AuthResult::Allowed,
+        );
+
+        assert_eq!(request.query_string(), "");
+        assert_eq!(request.full_path_and_query(), "/api/users");
     }
 }
@@ -1,6 +1,7 @@
 use std::fmt::Display;
 
 use hyper::{Method, Uri};
+use log::error;
 use regex::Regex;
 
 pub enum MethodMatcher {
@@ -9,19 +10,19 @@ pub enum MethodMatcher {
     All,
 }
 
+/// Malformed path regexes don't panic when the matcher is created. They never match a request and
+/// are reported when the application configuration is validated on startup
 pub struct RequestMatcher {
-    path_regex: Regex,
+    path_pattern: String,
+    path_regex: Result<Regex, regex::Error>,
     method_matcher: MethodMatcher,
 }
 
 impl RequestMatcher {
     pub fn new(path_regex: &str, method_matcher: MethodMatcher) -> Self {
-        let regex_res = Regex::new(path_regex);
-        if let Err(e) = regex_res {
-            panic!("Malformed request matcher: {}", e);
-        }
         RequestMatcher {
-            path_regex: regex_res.unwrap(),
+            path_pattern: path_regex.to_string(),
+            path_regex: Regex::new(path_regex),
             method_matcher,
         }
     }
@@ -35,14 +36,29 @@ impl RequestMatcher {
     }
 
     pub fn matches(&self, method: &Method, uri: &Uri) -> bool {
-        self.matches_method(method) && self.path_regex.is_match(uri.path())
+        match &self.path_regex {
+            Ok(path_regex) => self.matches_method(method) && path_regex.is_match(uri.path()),
+            Err(e) => {
+                error!("Malformed request matcher {}: {}", self.path_pattern, e);
+                false
+            }
+        }
     }
 
+    pub fn validate(&self) -> Result<(), String> {
+        match &self.path_regex {
+            Ok(_) => Ok(()),
+            Err(e) => Err(format!(
+                "Malformed request matcher {}: {}",
+                self.path_pattern, e
+            )),
+        }
+    }
 }
 
 impl Display for RequestMatcher {
 fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-    write!(f, "{} {}", self.method_matcher, self.path_regex)
+    write!(f, "{} {}", self.method_matcher, self.path_pattern)
 }
 }
 
@@ -0,0 +1,93 @@
+#[cfg(feature = "static-files")]
+use std::path::PathBuf;
+
+/// Source a request can be answered from
+#[derive(Debug, Clone, PartialEq)]
+pub enum Resolver {
+    #[cfg(feature = "static-files")]
+    StaticFiles,
+    /// Only answers the requests whose path matches one of its routes, whatever their method, so
+    /// the sources after it can answer the rest
+    Router,
+    /// Index file of a single page application, served to GET requests that accept HTML for paths
+    /// without an extension, so the client side router can handle deep links
+    #[cfg(feature = "static-files")]
+    SpaIndex(PathBuf),
+}
+
+/// Order in which the sources are tried until one of them answers the request, for the whole
+/// application or for the paths under a prefix. Requests that none of them answers get a 404, from
+/// the router if it is in the order so the middlewares and the interceptor see it
+#[derive(Debug, Clone)]
+pub struct ResolutionOrder {
+    default: Vec<Resolver>,
+    prefixes: Vec<(String, Vec<Resolver>)>,
+}
+
+impl ResolutionOrder {
+    pub fn new(resolvers: Vec<Resolver>) -> Self {
+        ResolutionOrder {
+            default: resolvers,
+            prefixes: vec![],
+        }
+    }
+
+    /// Order for the paths under the prefix, like router first for /api and static files first for
+    /// the rest. The longest prefix that matches a path is the one used
+    pub fn for_prefix(mut self, prefix: &str, resolvers: Vec<Resolver>) -> Self {
+        self.prefixes
+            .push((prefix.trim_end_matches('/').to_string(), resolvers));
+        self
+    }
+
+    pub(crate) fn resolvers(&self, path: &str) -> &[Resolver] {
+        self.prefixes
+            .iter()
+            .filter(|(prefix, _)| is_under_prefix(path, prefix))
+            .max_by_key(|(prefix, _)| prefix.len())
+            .map(|(_, resolvers)| resolvers.as_slice())
+            .unwrap_or(&self.default)
+    }
+}
+
+/// Static files first and then the router
+impl Default for ResolutionOrder {
+    fn default() -> Self {
+        ResolutionOrder::new(vec![
+            #[cfg(feature = "static-files")]
+            Resolver::StaticFiles,
+            Resolver::Router,
+        ])
+    }
+}
+
+/// Prefixes match whole segments, so /api doesn't match /apis
+fn is_under_prefix(path: &str, prefix: &str) -> bool {
+    match path.strip_prefix(prefix) {
+        Some(rest) => rest.is_empty() || rest.starts_with('/'),
+        None => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn prefix_resolvers_test() {
+        let resolution_order = ResolutionOrder::default()
+            .for_prefix("/api/", vec![Resolver::Router])
+            .for_prefix("/api/v2", vec![]);
+
+        assert_eq!(resolution_order.resolvers("/api"), &[Resolver::Router]);
+        assert_eq!(
+            resolution_order.resolvers("/api/users"),
+            &[Resolver::Router]
+        );
+        assert!(resolution_order.resolvers("/api/v2/users").is_empty());
+        assert_eq!(
+            resolution_order.resolvers("/apis"),
+            ResolutionOrder::default().resolvers("/apis")
+        );
+    }
+}
@@ -1,15 +1,152 @@
-use http_body_util::Full;
-use hyper::header::{HeaderName, HeaderValue, CONTENT_TYPE};
+use futures_util::{Stream, StreamExt};
+use http_body_util::combinators::UnsyncBoxBody;
+use http_body_util::{BodyExt, Full, StreamBody};
+use hyper::body::Frame;
+use chrono::{DateTime, Utc};
+use hyper::header::{
+    HeaderName, HeaderValue, ACCEPT, CONNECTION, CONTENT_DISPOSITION, CONTENT_LENGTH, CONTENT_TYPE,
+    ETAG, LAST_MODIFIED, LOCATION, PROXY_AUTHENTICATE, PROXY_AUTHORIZATION, RETRY_AFTER,
+    SET_COOKIE, TE, TRAILER, TRANSFER_ENCODING, UPGRADE, VARY,
+};
+#[cfg(feature = "compression")]
+use hyper::header::CONTENT_ENCODING;
 use hyper::{body::Bytes, HeaderMap, StatusCode};
+use log::error;
 use serde::Serialize;
+use std::any::Any;
+use std::io::ErrorKind;
+use std::path::PathBuf;
+use std::sync::Arc;
+#[cfg(feature = "templates")]
 use tera::Context;
+use tokio::io::AsyncReadExt;
 
-use crate::{templates, DefaultErrorResponseBody};
+#[cfg(feature = "compression")]
+use crate::compression::{Encoding, MIN_COMPRESSED_SIZE};
+use crate::error::{self, ErrorType, RequestError};
+use crate::request::Request;
+#[cfg(feature = "templates")]
+use crate::templates;
+use crate::serializers::ResponseSerializers;
+use crate::{cookie::Cookie, util, DefaultErrorResponseBody, ServerError};
+
+/// RFC 7464 record separator, written before every JSON text in a JSON sequence
+const JSON_SEQ_RECORD_SEPARATOR: u8 = 0x1E;
+
+/// Size of the chunks files are streamed in
+const FILE_CHUNK_SIZE: usize = 64 * 1024;
+
+/// Characters of a redirect location sent as they are. Anything else, like line breaks or
+/// non-ASCII characters, isn't valid in a header and is percent-encoded
+const LOCATION_PUNCTUATION: &str = "!\"#$%&'()*+,-./:;<=>?@[\\]^_`{|}~";
+
+pub type BoxedBody = UnsyncBoxBody<Bytes, ServerError>;
+
+/// Headers that only apply to a single connection, so they are not forwarded by proxies
+const HOP_BY_HOP_HEADERS: [HeaderName; 7] = [
+    CONNECTION,
+    PROXY_AUTHENTICATE,
+    PROXY_AUTHORIZATION,
+    TE,
+    TRAILER,
+    TRANSFER_ENCODING,
+    UPGRADE,
+];
+
+pub enum ResponseBody {
+    Full(Bytes),
+    Stream(BoxedBody),
+}
+
+/// Value of a Retry-After header, either a delay in seconds or the date to retry at
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RetryAfter {
+    Seconds(u64),
+    Date(DateTime<Utc>),
+}
+
+impl From<u64> for RetryAfter {
+    fn from(seconds: u64) -> Self {
+        RetryAfter::Seconds(seconds)
+    }
+}
+
+impl From<DateTime<Utc>> for RetryAfter {
+    fn from(date: DateTime<Utc>) -> Self {
+        RetryAfter::Date(date)
+    }
+}
+
+impl std::fmt::Display for RetryAfter {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RetryAfter::Seconds(seconds) => write!(f, "{}", seconds),
+            RetryAfter::Date(date) => write!(f, "{}", util::http_date(date)),
+        }
+    }
+}
+
+/// Validator of a cached representation: its entity tag or the date it was last modified
+#[derive(Debug, Clone, PartialEq)]
+pub enum Validator {
+    ETag(String),
+    LastModified(DateTime<Utc>),
+}
+
+impl From<&str> for Validator {
+    fn from(etag: &str) -> Self {
+        Validator::ETag(etag.to_string())
+    }
+}
+
+impl From<String> for Validator {
+    fn from(etag: String) -> Self {
+        Validator::ETag(etag)
+    }
+}
+
+impl From<DateTime<Utc>> for Validator {
+    fn from(date: DateTime<Utc>) -> Self {
+        Validator::LastModified(date)
+    }
+}
+
+/// Header value of an entity tag (RFC 9110): visible characters other than quotes between quotes,
+/// optionally marked as weak with W/. None for anything else
+fn entity_tag(etag: &str) -> Option<HeaderValue> {
+    let opaque_tag = etag.strip_prefix("W/").unwrap_or(etag);
+    let tag = opaque_tag.strip_prefix('"')?.strip_suffix('"')?;
+    if tag
+        .bytes()
+        .all(|byte| byte == b'!' || (b'#'..=b'~').contains(&byte))
+    {
+        HeaderValue::from_str(etag).ok()
+    } else {
+        None
+    }
+}
+
+/// Conversion of the errors of fallible handlers into their response. Applications implement it
+/// for their error enums to map every variant to its status, and anything that already converts
+/// into a response, like RequestError, implements it through its Into implementation
+pub trait IntoResponse {
+    fn into_response(self) -> Response;
+}
+
+impl<T: Into<Response>> IntoResponse for T {
+    fn into_response(self) -> Response {
+        self.into()
+    }
+}
 
 pub struct Response {
     pub status: StatusCode,
-    pub body: Option<Full<Bytes>>,
+    pub body: Option<ResponseBody>,
     headers: HeaderMap,
+    skip_interceptor: bool,
+    // Compressed before sending if the client accepts it, like rendered templates
+    #[cfg(feature = "compression")]
+    compressible: bool,
 }
 
 impl Response {
@@ -18,48 +155,126 @@ impl Response {
             status,
             body: None,
             headers: HeaderMap::new(),
+            skip_interceptor: false,
+            #[cfg(feature = "compression")]
+            compressible: false,
         }
     }
 
-    pub fn static_template(template_name: &str) -> Result<Self, tera::Error> {
-        let mut response = Self::new(StatusCode::OK).body(templates::render_view_with_context(
-            template_name,
-            &Context::new(),
-        )?);
+    /// Response without a body, sent with Content-Length: 0 unless its status can't carry one,
+    /// like 204 No Content
+    pub fn empty(status: StatusCode) -> Self {
+        let mut response = Self::new(status);
+        response.body = Some(ResponseBody::Full(Bytes::new()));
+        response
+    }
 
-        response.headers.insert(
-            CONTENT_TYPE,
-            HeaderValue::from_static(mime::TEXT_HTML_UTF_8.essence_str()),
-        );
+    /// 304 Not Modified without a body, for handlers that check the conditional headers of the
+    /// request themselves. The validator is sent in the ETag or Last-Modified header. An ETag that
+    /// isn't a quoted entity tag, like "v2" or W/"v2", is answered with a 500
+    pub fn not_modified(validator: impl Into<Validator>) -> Self {
+        let mut response = Self::new(StatusCode::NOT_MODIFIED);
+        match validator.into() {
+            Validator::ETag(etag) => match entity_tag(&etag) {
+                Some(etag) => {
+                    response.headers.insert(ETAG, etag);
+                    response
+                }
+                None => {
+                    let message = format!("Invalid ETag {:?}", etag);
+                    RequestError::with_message(ErrorType::Internal, &message).into()
+                }
+            },
+            Validator::LastModified(date) => {
+                response.add_header(LAST_MODIFIED, &util::http_date(&date))
+            }
+        }
+    }
 
-        Ok(response)
+    /// 302 Found redirect to the location
+    pub fn redirect(location: &str) -> Self {
+        Self::redirect_with_status(StatusCode::FOUND, location)
     }
 
-    pub fn template(template_name: &str, data: &impl Serialize) -> Result<Self, tera::Error> {
-        let mut response =
-            Self::new(StatusCode::OK).body(templates::render_view(template_name, data)?);
+    /// 301 Moved Permanently redirect to the location
+    pub fn redirect_permanent(location: &str) -> Self {
+        Self::redirect_with_status(StatusCode::MOVED_PERMANENTLY, location)
+    }
 
-        response.headers.insert(
-            CONTENT_TYPE,
-            HeaderValue::from_static(mime::TEXT_HTML_UTF_8.essence_str()),
-        );
+    /// Redirect with any status, like 303 See Other after a form submission or 307 and 308 to
+    /// keep the method and body of the request. Locations built from user input are
+    /// percent-encoded where they can't be sent in a header
+    pub fn redirect_with_status(status: StatusCode, location: &str) -> Self {
+        let location = util::percent_encode(location, LOCATION_PUNCTUATION);
+        match HeaderValue::try_from(location) {
+            Ok(location) => {
+                let mut response = Self::empty(status);
+                response.headers.insert(LOCATION, location);
+                response
+            }
+            Err(e) => RequestError::with_message(ErrorType::Internal, &e.to_string()).into(),
+        }
+    }
 
-        Ok(response)
+    #[cfg(feature = "templates")]
+    pub fn static_template(template_name: &str) -> Result<Self, tera::Error> {
+        Ok(Self::rendered_template(
+            templates::render_view_with_context(template_name, &Context::new())?,
+        ))
     }
 
+    #[cfg(feature = "templates")]
+    pub fn template(template_name: &str, data: &impl Serialize) -> Result<Self, tera::Error> {
+        Ok(Self::rendered_template(templates::render_view(
+            template_name,
+            data,
+        )?))
+    }
+
+    /// Template rendered with a status other than 200, like an HTML 404 page or a form re-rendered
+    /// with its validation errors and a 422
+    #[cfg(feature = "templates")]
+    pub fn template_with_status(
+        status: StatusCode,
+        template_name: &str,
+        data: &impl Serialize,
+    ) -> Result<Self, tera::Error> {
+        Ok(Self::rendered_template_with_status(
+            status,
+            templates::render_view(template_name, data)?,
+        ))
+    }
+
+    #[cfg(feature = "templates")]
     pub fn template_from_context(
         template_name: &str,
         context: &Context,
     ) -> Result<Self, tera::Error> {
-        let mut response = Self::new(StatusCode::OK)
-            .body(templates::render_view_with_context(template_name, context)?);
+        Ok(Self::rendered_template(
+            templates::render_view_with_context(template_name, context)?,
+        ))
+    }
+
+    /// HTML response of a rendered template, compressed for the clients that accept it
+    #[cfg(feature = "templates")]
+    fn rendered_template(html: String) -> Self {
+        Self::rendered_template_with_status(StatusCode::OK, html)
+    }
+
+    #[cfg(feature = "templates")]
+    fn rendered_template_with_status(status: StatusCode, html: String) -> Self {
+        let mut response = Self::new(status).body(html);
 
         response.headers.insert(
             CONTENT_TYPE,
             HeaderValue::from_static(mime::TEXT_HTML_UTF_8.essence_str()),
         );
+        #[cfg(feature = "compression")]
+        {
+            response.compressible = true;
+        }
 
-        Ok(response)
+        response
     }
 
     pub fn add_header(mut self, key: HeaderName, value: &str) -> Self {
@@ -69,11 +284,80 @@ impl Response {
         self
     }
 
-    pub fn json(mut self, body: impl Serialize) -> Self {
+    /// Adds the header keeping the values it already had, for headers that can be sent more
+    /// than once like Set-Cookie. Values that aren't valid in a header, like ones with line
+    /// breaks, are logged and skipped
+    pub fn append_header(mut self, key: HeaderName, value: &str) -> Self {
+        match HeaderValue::from_str(value) {
+            Ok(value) => {
+                self.headers.append(key, value);
+            }
+            Err(e) => error!("Skipping invalid value for header {}: {}", key, e),
+        }
+
+        self
+    }
+
+    /// Adds a Set-Cookie header. Every call adds a new cookie. Cookies with a name, value or path
+    /// that isn't valid are logged and skipped
+    pub fn set_cookie(self, cookie: Cookie) -> Self {
+        if let Err(e) = cookie.validate() {
+            error!("Skipping cookie: {}", e);
+            return self;
+        }
+        self.append_header(SET_COOKIE, &cookie.to_string())
+    }
+
+    /// Adds the headers the response doesn't already have
+    pub(crate) fn with_default_headers(mut self, default_headers: &HeaderMap) -> Self {
+        for key in default_headers.keys() {
+            if !self.headers.contains_key(key) {
+                for value in default_headers.get_all(key) {
+                    self.headers.append(key.clone(), value.clone());
+                }
+            }
+        }
+        self
+    }
+
+    /// Tells the client when to retry the request, for 503 Service Unavailable and 429 Too Many
+    /// Requests responses. Accepts a delay in seconds or a date
+    pub fn retry_after(self, retry_after: impl Into<RetryAfter>) -> Self {
+        self.add_header(RETRY_AFTER, &retry_after.into().to_string())
+    }
+
+    pub fn json(self, body: impl Serialize) -> Self {
+        self.serialized_json(&body, false)
+    }
+
+    /// Like json, with the body indented to be read by people, for debugging and developer facing
+    /// endpoints
+    pub fn json_pretty(self, body: impl Serialize) -> Self {
+        self.serialized_json(&body, true)
+    }
+
+    /// Like json, with the serializer registered in the application for the type of the body if
+    /// there is one. The serializers are looked up by type, so the body is an owned value
+    pub fn json_registered<S: Serialize + Any>(self, request: &Request, body: S) -> Self {
+        let registered = request
+            .extensions()
+            .get::<Arc<ResponseSerializers>>()
+            .and_then(|serializers| serializers.serialize(&body));
+        match registered {
+            Some(value) => self.serialized_json(&value, false),
+            None => self.serialized_json(&body, false),
+        }
+    }
+
+    fn serialized_json<S: Serialize + ?Sized>(mut self, body: &S, pretty: bool) -> Self {
         //todo check how to better handle serialization errors
-        let body_bytes = serde_json::to_string(&body).unwrap();
+        let body_bytes = if pretty {
+            serde_json::to_string_pretty(body).unwrap()
+        } else {
+            serde_json::to_string(body).unwrap()
+        };
 
-        self.body = Some(Full::new(body_bytes.into()));
+        self.body = Some(ResponseBody::Full(body_bytes.into()));
 
         self.headers.insert(
             CONTENT_TYPE,
@@ -83,6 +367,196 @@ impl Response {
         self
     }
 
+    /// Plain text body, sent as text/plain; charset=utf-8
+    pub fn text(self, body: &str) -> Self {
+        self.body(body.to_string())
+            .add_header(CONTENT_TYPE, mime::TEXT_PLAIN_UTF_8.as_ref())
+    }
+
+    /// Serializes the body as XML, with the name of its type as the root element
+    #[cfg(feature = "xml")]
+    pub fn xml(mut self, body: impl Serialize) -> Self {
+        match quick_xml::se::to_string(&body) {
+            Ok(xml) => {
+                self.body = Some(ResponseBody::Full(xml.into()));
+                self.headers
+                    .insert(CONTENT_TYPE, HeaderValue::from_static("application/xml"));
+                self
+            }
+            Err(e) => RequestError::with_message(ErrorType::Internal, &e.to_string()).into(),
+        }
+    }
+
+    /// Serializes the data in the representation the client prefers according to the Accept
+    /// header of the request: JSON, form-encoded or, when a template name is given, HTML. Requests
+    /// without an Accept header get JSON, and a 406 Not Acceptable is returned when none of the
+    /// accepted media types can be produced
+    pub fn negotiated(
+        request: &Request,
+        data: impl Serialize,
+        template_name: Option<&str>,
+    ) -> Self {
+        let html_available = cfg!(feature = "templates") && template_name.is_some();
+        let representation = match Representation::negotiate(&request.headers, html_available) {
+            Some(representation) => representation,
+            None => {
+                let supported_media_types = Representation::available(html_available)
+                    .iter()
+                    .map(|representation| representation.media_type().to_string())
+                    .collect();
+                let error = RequestError::default(ErrorType::NotAcceptable)
+                    .supported_media_types(supported_media_types);
+                return error::route_error_response(error, request).append_header(VARY, "Accept");
+            }
+        };
+
+        let response = match representation {
+            Representation::Json => Response::new(StatusCode::OK).json(data),
+            Representation::Form => match serde_html_form::to_string(&data) {
+                Ok(body) => Response::new(StatusCode::OK)
+                    .body(body)
+                    .add_header(CONTENT_TYPE, representation.media_type()),
+                Err(e) => RequestError::with_message(ErrorType::Internal, &e.to_string()).into(),
+            },
+            #[cfg(feature = "templates")]
+            Representation::Html => {
+                match Response::template(template_name.unwrap_or_default(), &data) {
+                    Ok(response) => response,
+                    Err(e) => {
+                        RequestError::with_message(ErrorType::Internal, &e.to_string()).into()
+                    }
+                }
+            }
+            #[cfg(not(feature = "templates"))]
+            Representation::Html => unreachable!("HTML is only offered with templates enabled"),
+        };
+        response.append_header(VARY, "Accept")
+    }
+
+    /// Streams every record of the stream as an RFC 7464 JSON text sequence, so large result sets
+    /// don't have to be buffered and clients can parse them incrementally
+    pub fn json_seq<S, I>(mut self, records: S) -> Self
+    where
+        S: Stream<Item = I> + Send + 'static,
+        I: Serialize + 'static,
+    {
+        let frames = records.map(|record| -> Result<Frame<Bytes>, ServerError> {
+            let mut record_bytes = vec![JSON_SEQ_RECORD_SEPARATOR];
+            serde_json::to_writer(&mut record_bytes, &record)?;
+            record_bytes.push(b'\n');
+            Ok(Frame::data(Bytes::from(record_bytes)))
+        });
+
+        self.body = Some(ResponseBody::Stream(StreamBody::new(frames).boxed_unsync()));
+
+        self.headers.insert(
+            CONTENT_TYPE,
+            HeaderValue::from_static("application/json-seq"),
+        );
+
+        self
+    }
+
+    /// Sends the file as a download. The Content-Type is guessed from the extension and the body
+    /// is streamed from disk, so big files are not loaded in memory. Missing files are returned as
+    /// a NotFound error
+    pub fn file(path: PathBuf) -> Result<Self, RequestError> {
+        let file_res = std::fs::File::open(&path).and_then(|file| {
+            let metadata = file.metadata()?;
+            if metadata.is_file() {
+                Ok((file, metadata.len()))
+            } else {
+                Err(ErrorKind::NotFound.into())
+            }
+        });
+        if let Err(e) = file_res {
+            let error_type = if e.kind() == ErrorKind::NotFound {
+                ErrorType::NotFound
+            } else {
+                ErrorType::Internal
+            };
+            return Err(RequestError::with_message(
+                error_type,
+                &format!("Could not read file {}", path.display()),
+            ));
+        }
+        let (file, length) = file_res.unwrap();
+
+        let frames = futures_util::stream::unfold(
+            Some(tokio::fs::File::from_std(file)),
+            |file| async move {
+                let mut file = file?;
+                let mut buffer = vec![0; FILE_CHUNK_SIZE];
+                match file.read(&mut buffer).await {
+                    Ok(0) => None,
+                    Ok(read) => {
+                        buffer.truncate(read);
+                        Some((Ok(Frame::data(Bytes::from(buffer))), Some(file)))
+                    }
+                    // The stream ends after an error
+                    Err(e) => Some((Err(ServerError::from(e)), None)),
+                }
+            },
+        );
+
+        let content_type = mime_guess::from_path(&path).first_or_octet_stream();
+        let filename = path
+            .file_name()
+            .map(|filename| filename.to_string_lossy())
+            .unwrap_or_default();
+
+        let mut response = Self::new(StatusCode::OK)
+            .add_header(CONTENT_TYPE, content_type.as_ref())
+            .add_header(CONTENT_DISPOSITION, &attachment_disposition(&filename))
+            .add_header(CONTENT_LENGTH, &length.to_string());
+        response.body = Some(ResponseBody::Stream(StreamBody::new(frames).boxed_unsync()));
+
+        Ok(response)
+    }
+
+    /// Response with the status, headers and body of an upstream response, for gateways and
+    /// reverse proxies. The body is streamed as it arrives and hop-by-hop headers are not copied.
+    /// Any body type works, like a buffered Full or the body of a reqwest response converted into a
+    /// hyper one
+    pub fn proxy<B>(upstream: hyper::Response<B>) -> Self
+    where
+        B: hyper::body::Body<Data = Bytes> + Send + 'static,
+        B::Error: Into<ServerError>,
+    {
+        let (parts, body) = upstream.into_parts();
+
+        // The Connection header can list more headers that only apply to the upstream connection
+        let connection_headers: Vec<String> = parts
+            .headers
+            .get_all(CONNECTION)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .map(|name| name.trim().to_ascii_lowercase())
+            .collect();
+
+        let mut response = Self::new(parts.status);
+        for (key, value) in parts.headers.iter() {
+            let hop_by_hop = HOP_BY_HOP_HEADERS.contains(key)
+                || key.as_str() == "keep-alive"
+                || connection_headers.iter().any(|name| name == key.as_str());
+            if !hop_by_hop {
+                response.headers.append(key.clone(), value.clone());
+            }
+        }
+        response.body = Some(ResponseBody::Stream(
+            body.map_err(Into::into).boxed_unsync(),
+        ));
+
+        response
+    }
+
+    /// Error response with the status and the default error body, for IntoResponse
+    /// implementations of application errors
+    pub fn error(status: StatusCode, cause: &str) -> Self {
+        Response::new(status).json(DefaultErrorResponseBody::new(status, cause.to_string()))
+    }
+
     pub fn default_error(e: &dyn std::error::Error) -> Self {
         Response::new(StatusCode::INTERNAL_SERVER_ERROR).json(DefaultErrorResponseBody::new(
                 StatusCode::INTERNAL_SERVER_ERROR,
@@ -93,29 +567,223 @@ impl Response {
 
     pub fn body(mut self, body: String) -> Self {
         //todo check how to better handle serialization errors
-        self.body = Some(Full::new(body.into()));
+        self.body = Some(ResponseBody::Full(body.into()));
 
         self
     }
 
+    /// The response interceptor will not be called for this response. Useful for health checks or
+    /// high frequency endpoints that would fill the logs
+    pub fn skip_interceptor(mut self) -> Self {
+        self.skip_interceptor = true;
+        self
+    }
+
+    pub fn is_interceptor_skipped(&self) -> bool {
+        self.skip_interceptor
+    }
+
     pub fn get_status(&self) -> StatusCode {
         self.status
     }
 
-    pub fn get_body_with_ownership(self) -> Option<Full<Bytes>> {
+    pub fn get_body_with_ownership(self) -> Option<ResponseBody> {
         self.body
     }
 
-    pub fn get_body(&self) -> &Option<Full<Bytes>> {
+    pub fn get_body(&self) -> &Option<ResponseBody> {
         &self.body
     }
 
     pub fn get_headers(&self) -> &HeaderMap {
         &self.headers
     }
+
+    /// Mutable access to the headers, for edits add_header can't do like removing a header or
+    /// changing the values it already has
+    pub fn headers_mut(&mut self) -> &mut HeaderMap {
+        &mut self.headers
+    }
+
+    /// Compresses the body with the best encoding in the Accept-Encoding headers of the request,
+    /// if the response is compressible and big enough to be worth it
+    #[cfg(feature = "compression")]
+    pub(crate) fn compress_for(mut self, request_headers: &HeaderMap) -> Self {
+        if !self.compressible || self.headers.contains_key(CONTENT_ENCODING) {
+            return self;
+        }
+        let body = match self.body.as_ref() {
+            Some(ResponseBody::Full(body)) if body.len() >= MIN_COMPRESSED_SIZE => body,
+            _ => return self,
+        };
+
+        // Caches must keep a copy per encoding even if this client gets the body uncompressed
+        self.headers
+            .append(VARY, HeaderValue::from_static("Accept-Encoding"));
+        let encoding = match Encoding::negotiate(request_headers) {
+            Some(encoding) => encoding,
+            None => return self,
+        };
+        match encoding.compress(body) {
+            Ok(compressed) => {
+                self.body = Some(ResponseBody::Full(compressed.into()));
+                self.headers.remove(CONTENT_LENGTH);
+                self.headers.insert(
+                    CONTENT_ENCODING,
+                    HeaderValue::from_static(encoding.as_str()),
+                );
+            }
+            Err(e) => error!(
+                "Error compressing response with {}: {}",
+                encoding.as_str(),
+                e
+            ),
+        }
+        self
+    }
+
+    /// Copy of the response. None if the body is a stream, as it can only be read once
+    pub(crate) fn try_clone(&self) -> Option<Response> {
+        let body = match self.body.as_ref() {
+            Some(ResponseBody::Stream(_)) => return None,
+            Some(ResponseBody::Full(bytes)) => Some(ResponseBody::Full(bytes.clone())),
+            None => None,
+        };
+
+        Some(Response {
+            status: self.status,
+            body,
+            headers: self.headers.clone(),
+            skip_interceptor: self.skip_interceptor,
+            #[cfg(feature = "compression")]
+            compressible: self.compressible,
+        })
+    }
+}
+
+/// Representations Response::negotiated can produce, in order of preference when the client
+/// accepts several with the same quality
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Representation {
+    Json,
+    Form,
+    Html,
+}
+
+impl Representation {
+    fn available(html_available: bool) -> &'static [Representation] {
+        if html_available {
+            &[
+                Representation::Json,
+                Representation::Form,
+                Representation::Html,
+            ]
+        } else {
+            &[Representation::Json, Representation::Form]
+        }
+    }
+
+    fn media_type(&self) -> &'static str {
+        match self {
+            Representation::Json => mime::APPLICATION_JSON.essence_str(),
+            Representation::Form => mime::APPLICATION_WWW_FORM_URLENCODED.essence_str(),
+            Representation::Html => mime::TEXT_HTML.essence_str(),
+        }
+    }
+
+    /// How specific the media range is when it matches the representation: 2 for its exact media
+    /// type, 1 for type/* and 0 for */*. None if it doesn't match
+    fn specificity(&self, media_range: &str) -> Option<u8> {
+        let media_type = self.media_type();
+        match media_range.split_once('/') {
+            Some(("*", "*")) => Some(0),
+            Some((range_type, "*")) => media_type
+                .split_once('/')
+                .is_some_and(|(main_type, _)| main_type.eq_ignore_ascii_case(range_type))
+                .then_some(1),
+            _ => media_type.eq_ignore_ascii_case(media_range).then_some(2),
+        }
+    }
+
+    /// Quality the client gives to the representation, taken from the most specific media range
+    /// that matches it as RFC 9110 describes, along with the position of that range. None if no
+    /// range matches it
+    fn quality(&self, media_ranges: &[(&str, u16)]) -> Option<(u16, usize)> {
+        media_ranges
+            .iter()
+            .enumerate()
+            .filter_map(|(position, (range, quality))| {
+                self.specificity(range)
+                    .map(|specificity| (specificity, *quality, position))
+            })
+            .max_by_key(|(specificity, _, _)| *specificity)
+            .map(|(_, quality, position)| (quality, position))
+    }
+
+    /// Representation with the highest quality in the Accept headers, or None if the client
+    /// accepts none of them. Representations excluded with q=0 aren't accepted through a wildcard
+    fn negotiate(request_headers: &HeaderMap, html_available: bool) -> Option<Representation> {
+        let available = Self::available(html_available);
+        let media_ranges: Vec<(&str, u16)> = request_headers
+            .get_all(ACCEPT)
+            .iter()
+            .filter_map(|header| header.to_str().ok())
+            .flat_map(|header| header.split(','))
+            .filter_map(|media_range| {
+                let mut parts = media_range.split(';');
+                let range = parts.next().unwrap_or_default().trim();
+                // Quality in thousandths, the precision allowed by RFC 9110
+                let quality = parts
+                    .find_map(|parameter| parameter.trim().strip_prefix("q="))
+                    .and_then(|quality| quality.parse::<f32>().ok())
+                    .map(|quality| (quality.clamp(0.0, 1.0) * 1000.0) as u16)
+                    .unwrap_or(1000);
+                (!range.is_empty()).then_some((range, quality))
+            })
+            .collect();
+        if media_ranges.is_empty() {
+            return Some(Representation::Json);
+        }
+
+        // Ties go to the range the client sent first, and then to the first available
+        available
+            .iter()
+            .filter_map(|representation| {
+                let (quality, position) = representation.quality(&media_ranges)?;
+                (quality > 0).then_some((*representation, quality, position))
+            })
+            .min_by(|(_, a_quality, a_position), (_, b_quality, b_position)| {
+                b_quality.cmp(a_quality).then(a_position.cmp(b_position))
+            })
+            .map(|(representation, _, _)| representation)
+    }
 }
 
-impl TryFrom<Response> for hyper::Response<Full<Bytes>> {
+/// Content-Disposition of a download. Names that aren't plain ASCII are sent in filename* as
+/// RFC 6266 describes, with a sanitized filename for older clients
+fn attachment_disposition(filename: &str) -> String {
+    let fallback: String = filename
+        .chars()
+        .map(|c| match c {
+            ' '..='~' if c != '"' && c != '\\' => c,
+            _ => '_',
+        })
+        .collect();
+    if fallback == filename {
+        return format!("attachment; filename=\"{}\"", filename);
+    }
+    format!(
+        "attachment; filename=\"{}\"; filename*=UTF-8''{}",
+        fallback,
+        util::percent_encode(filename, "!#$&+-.^_`|~")
+    )
+}
+
+pub(crate) fn full_body(bytes: Bytes) -> BoxedBody {
+    Full::new(bytes).map_err(|never| match never {}).boxed_unsync()
+}
+
+impl TryFrom<Response> for hyper::Response<BoxedBody> {
     type Error = crate::ServerError;
 
     fn try_from(response: Response) -> Result<Self, Self::Error> {
@@ -126,9 +794,28 @@ impl TryFrom<Response> for hyper::Response<Full<Bytes>> {
             response_builder = response_builder.header(key, value);
         }
 
-        let response_body = response
-            .get_body_with_ownership()
-            .unwrap_or(Full::new(Bytes::new()));
+        // Some clients wait for a body unless the length is explicitly set to 0. 1xx and 204
+        // responses must not have a Content-Length (RFC 9110), and a 304 can only carry the length
+        // of the representation it stands for, so they are left out
+        let is_empty = match response.get_body() {
+            Some(ResponseBody::Full(bytes)) => bytes.is_empty(),
+            Some(ResponseBody::Stream(_)) => false,
+            None => true,
+        };
+        let allows_content_length = !status_response.is_informational()
+            && status_response != StatusCode::NO_CONTENT
+            && status_response != StatusCode::NOT_MODIFIED;
+        if is_empty && allows_content_length && !response.get_headers().contains_key(CONTENT_LENGTH)
+        {
+            response_builder =
+                response_builder.header(CONTENT_LENGTH, HeaderValue::from_static("0"));
+        }
+
+        let response_body = match response.get_body_with_ownership() {
+            Some(ResponseBody::Full(bytes)) => full_body(bytes),
+            Some(ResponseBody::Stream(stream)) => stream,
+            None => full_body(Bytes::new()),
+        };
 
         match response_builder.body(response_body) {
             Ok(response) => Ok(response),
@@ -136,3 +823,425 @@ impl TryFrom<Response> for hyper::Response<Full<Bytes>> {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+    use futures_util::stream;
+    use serde_json::json;
+
+    use crate::error::{
+        ErrorConfiguration, ErrorFormat, ErrorType, RequestError, DEFAULT_RETRY_AFTER_SECONDS,
+    };
+    use crate::security::security_configuration::AuthResult;
+
+    use super::*;
+
+    #[test]
+    fn retry_after_test() {
+        let response: Response = RequestError::default(ErrorType::TooManyRequests).into();
+        assert_eq!(response.get_status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(
+            response.get_headers().get(RETRY_AFTER).unwrap(),
+            &DEFAULT_RETRY_AFTER_SECONDS.to_string()
+        );
+
+        let response: Response = RequestError::default(ErrorType::TooManyRequests)
+            .retry_after(30)
+            .into();
+        assert_eq!(response.get_headers().get(RETRY_AFTER).unwrap(), "30");
+
+        let response = Response::new(StatusCode::SERVICE_UNAVAILABLE)
+            .retry_after(Utc.with_ymd_and_hms(2030, 1, 15, 8, 30, 0).unwrap());
+        assert_eq!(
+            response.get_headers().get(RETRY_AFTER).unwrap(),
+            "Tue, 15 Jan 2030 08:30:00 GMT"
+        );
+
+        let response: Response = RequestError::default(ErrorType::NotFound).into();
+        assert!(response.get_headers().get(RETRY_AFTER).is_none());
+    }
+
+    #[test]
+    fn redirect_test() {
+        let response = Response::redirect("/users/5");
+        assert_eq!(response.get_status(), StatusCode::FOUND);
+        assert_eq!(response.get_headers().get(LOCATION).unwrap(), "/users/5");
+        assert!(matches!(response.get_body(), Some(ResponseBody::Full(body)) if body.is_empty()));
+
+        let response = Response::redirect_permanent("/new-home");
+        assert_eq!(response.get_status(), StatusCode::MOVED_PERMANENTLY);
+
+        let response = Response::redirect_with_status(StatusCode::SEE_OTHER, "/users");
+        assert_eq!(response.get_status(), StatusCode::SEE_OTHER);
+        assert_eq!(response.get_headers().get(LOCATION).unwrap(), "/users");
+
+        // Locations from user input can't inject headers or make the response panic
+        let response = Response::redirect("/search?q=café\r\nSet-Cookie: a=b&page=2#top");
+        assert_eq!(
+            response.get_headers().get(LOCATION).unwrap(),
+            "/search?q=caf%C3%A9%0D%0ASet-Cookie:%20a=b&page=2#top"
+        );
+        assert!(response.get_headers().get(SET_COOKIE).is_none());
+    }
+
+    #[test]
+    fn set_cookie_test() {
+        let response = Response::new(StatusCode::OK)
+            .set_cookie(Cookie::new("session", "abc123").http_only())
+            .set_cookie(Cookie::new("theme", "dark"));
+
+        let cookies: Vec<&HeaderValue> =
+            response.get_headers().get_all(SET_COOKIE).iter().collect();
+        assert_eq!(cookies, vec!["session=abc123; HttpOnly", "theme=dark"]);
+
+        // Invalid cookies and header values are skipped instead of panicking
+        let response = Response::new(StatusCode::OK)
+            .set_cookie(Cookie::new("session", "abc; Domain=evil.com"))
+            .append_header(HeaderName::from_static("x-trace"), "a\r\nX-Injected: 1");
+        assert!(response.get_headers().get(SET_COOKIE).is_none());
+        assert!(response.get_headers().get("x-trace").is_none());
+    }
+
+    #[test]
+    fn empty_response_content_length_test() {
+        let hyper_response: hyper::Response<BoxedBody> =
+            Response::empty(StatusCode::OK).try_into().unwrap();
+        assert_eq!(hyper_response.headers().get(CONTENT_LENGTH).unwrap(), "0");
+
+        let hyper_response: hyper::Response<BoxedBody> =
+            Response::new(StatusCode::ACCEPTED).try_into().unwrap();
+        assert_eq!(hyper_response.headers().get(CONTENT_LENGTH).unwrap(), "0");
+
+        // 204 No Content must not carry a Content-Length
+        let hyper_response: hyper::Response<BoxedBody> =
+            Response::empty(StatusCode::NO_CONTENT).try_into().unwrap();
+        assert!(hyper_response.headers().get(CONTENT_LENGTH).is_none());
+    }
+
+    #[tokio::test]
+    async fn file_response_test() {
+        let path = std::env::temp_dir().join("citrine_users_export.csv");
+        std::fs::write(&path, "id,name\n1,alice\n").unwrap();
+
+        let hyper_response: hyper::Response<BoxedBody> =
+            Response::file(path.clone()).unwrap().try_into().unwrap();
+        assert_eq!(
+            hyper_response.headers().get(CONTENT_TYPE).unwrap(),
+            "text/csv"
+        );
+        assert_eq!(
+            hyper_response.headers().get(CONTENT_DISPOSITION).unwrap(),
+            "attachment; filename=\"citrine_users_export.csv\""
+        );
+        let body = hyper_response.into_body().collect().await.unwrap().to_bytes();
+        assert_eq!(&body[..], b"id,name\n1,alice\n");
+
+        std::fs::remove_file(&path).unwrap();
+        let response: Response = Response::file(path).err().unwrap().into();
+        assert_eq!(response.get_status(), StatusCode::NOT_FOUND);
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn file_response_disposition_test() {
+        let path = std::env::temp_dir().join("citrine \"résumé\"\r\n.pdf");
+        std::fs::write(&path, "%PDF").unwrap();
+
+        let response = Response::file(path.clone()).unwrap();
+        assert_eq!(
+            response.get_headers().get(CONTENT_DISPOSITION).unwrap(),
+            "attachment; filename=\"citrine _r_sum____.pdf\"; \
+             filename*=UTF-8''citrine%20%22r%C3%A9sum%C3%A9%22%0D%0A.pdf"
+        );
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn proxy_test() {
+        let upstream = hyper::Response::builder()
+            .status(StatusCode::CREATED)
+            .header(CONTENT_TYPE, "application/json")
+            .header("x-request-id", "abc123")
+            .header(CONNECTION, "keep-alive, x-upstream-node")
+            .header("keep-alive", "timeout=5")
+            .header("x-upstream-node", "node-3")
+            .header(TRANSFER_ENCODING, "chunked")
+            .body(Full::new(Bytes::from(r#"{"id":7}"#)))
+            .unwrap();
+
+        let response = Response::proxy(upstream);
+        assert_eq!(response.get_status(), StatusCode::CREATED);
+        let headers = response.get_headers();
+        assert_eq!(headers.get(CONTENT_TYPE).unwrap(), "application/json");
+        assert_eq!(headers.get("x-request-id").unwrap(), "abc123");
+        assert!(headers.get(CONNECTION).is_none());
+        assert!(headers.get("keep-alive").is_none());
+        assert!(headers.get("x-upstream-node").is_none());
+        assert!(headers.get(TRANSFER_ENCODING).is_none());
+
+        let hyper_response: hyper::Response<BoxedBody> = response.try_into().unwrap();
+        let body = hyper_response.into_body().collect().await.unwrap().to_bytes();
+        assert_eq!(&body[..], br#"{"id":7}"#);
+    }
+
+    #[tokio::test]
+    async fn json_seq_test() {
+        let response = Response::new(StatusCode::OK)
+            .json_seq(stream::iter(vec![json!({ "id": 1 }), json!({ "id": 2 })]));
+
+        let hyper_response: hyper::Response<BoxedBody> = response.try_into().unwrap();
+        assert_eq!(
+            hyper_response
+                .headers()
+                .get(CONTENT_TYPE)
+                .unwrap()
+                .to_str()
+                .unwrap(),
+            "application/json-seq"
+        );
+
+        let body = hyper_response.into_body().collect().await.unwrap().to_bytes();
+        assert_eq!(&body[..], b"\x1e{\"id\":1}\n\x1e{\"id\":2}\n");
+    }
+
+    #[test]
+    fn json_pretty_test() {
+        let body = serde_json::json!({ "name": "Citrine", "tags": ["web"] });
+
+        let response = Response::new(StatusCode::OK).json_pretty(body.clone());
+        assert_eq!(
+            response.get_headers().get(CONTENT_TYPE).unwrap(),
+            "application/json"
+        );
+        assert!(matches!(
+            response.get_body(),
+            Some(ResponseBody::Full(bytes))
+                if bytes == "{\n  \"name\": \"Citrine\",\n  \"tags\": [\n    \"web\"\n  ]\n}"
+        ));
+
+        let response = Response::new(StatusCode::OK).json(body);
+        assert!(matches!(
+            response.get_body(),
+            Some(ResponseBody::Full(bytes)) if bytes == r#"{"name":"Citrine","tags":["web"]}"#
+        ));
+    }
+
+    #[test]
+    fn not_modified_test() {
+        use hyper::body::Body;
+
+        let response = Response::not_modified("\"v2\"");
+        assert_eq!(response.get_status(), StatusCode::NOT_MODIFIED);
+        assert_eq!(response.get_headers().get(ETAG).unwrap(), "\"v2\"");
+        let response = Response::not_modified("W/\"v2\"");
+        assert_eq!(response.get_headers().get(ETAG).unwrap(), "W/\"v2\"");
+
+        // Invalid entity tags are reported instead of panicking
+        for etag in [
+            "v2",
+            "\"",
+            "\"v2\r\nX-Injected: 1\"",
+            "\"v\"2\"",
+            "\"versión\"",
+        ] {
+            let response = Response::not_modified(etag);
+            assert_eq!(response.get_status(), StatusCode::INTERNAL_SERVER_ERROR);
+            assert!(response.get_headers().get(ETAG).is_none());
+        }
+
+        let response = Response::not_modified(Utc.with_ymd_and_hms(2030, 1, 15, 8, 30, 0).unwrap());
+        assert_eq!(
+            response.get_headers().get(LAST_MODIFIED).unwrap(),
+            "Tue, 15 Jan 2030 08:30:00 GMT"
+        );
+
+        let hyper_response: hyper::Response<BoxedBody> = response.try_into().unwrap();
+        assert!(hyper_response.headers().get(CONTENT_LENGTH).is_none());
+        assert!(hyper_response.body().is_end_stream());
+    }
+
+    #[test]
+    fn headers_mut_test() {
+        let mut response = Response::new(StatusCode::OK)
+            .add_header(LOCATION, "/users/5")
+            .retry_after(30);
+
+        response.headers_mut().remove(LOCATION);
+        assert!(response.get_headers().get(LOCATION).is_none());
+        assert_eq!(response.get_headers().get(RETRY_AFTER).unwrap(), "30");
+    }
+
+    #[test]
+    fn text_test() {
+        let response = Response::new(StatusCode::OK).text("pong");
+        assert_eq!(
+            response.get_headers().get(CONTENT_TYPE).unwrap(),
+            "text/plain; charset=utf-8"
+        );
+        assert!(matches!(response.get_body(), Some(ResponseBody::Full(body)) if body == "pong"));
+    }
+
+    #[test]
+    fn negotiated_test() {
+        #[derive(Serialize)]
+        struct User {
+            name: &'static str,
+            age: u32,
+        }
+        let request_accepting = |accept: Option<&'static str>| {
+            let mut headers = HeaderMap::new();
+            if let Some(accept) = accept {
+                headers.insert(ACCEPT, HeaderValue::from_static(accept));
+            }
+            Request::new(
+                hyper::Method::GET,
+                hyper::Uri::from_static("/users/1"),
+                String::new(),
+                headers,
+                AuthResult::Allowed,
+            )
+        };
+        let user = || User {
+            name: "Ana",
+            age: 30,
+        };
+
+        for accept in [None, Some("application/json"), Some("*/*")] {
+            let response = Response::negotiated(&request_accepting(accept), user(), None);
+            assert_eq!(
+                response.get_headers().get(CONTENT_TYPE).unwrap(),
+                "application/json"
+            );
+            assert_eq!(response.get_headers().get(VARY).unwrap(), "Accept");
+        }
+
+        let response = Response::negotiated(
+            &request_accepting(Some(
+                "application/json;q=0.5, application/x-www-form-urlencoded",
+            )),
+            user(),
+            None,
+        );
+        assert_eq!(
+            response.get_headers().get(CONTENT_TYPE).unwrap(),
+            "application/x-www-form-urlencoded"
+        );
+        assert!(matches!(
+            response.get_body(),
+            Some(ResponseBody::Full(bytes)) if bytes == "name=Ana&age=30"
+        ));
+
+        // HTML can't be produced without a template
+        let response = Response::negotiated(&request_accepting(Some("text/html")), user(), None);
+        assert_eq!(response.get_status(), StatusCode::NOT_ACCEPTABLE);
+        let response = Response::negotiated(
+            &request_accepting(Some("application/json;q=0, text/*")),
+            user(),
+            None,
+        );
+        assert_eq!(response.get_status(), StatusCode::NOT_ACCEPTABLE);
+
+        // The 406 is answered with the error format of the router
+        let mut request = request_accepting(Some("text/html"));
+        request.extensions_mut().insert(ErrorConfiguration {
+            format: ErrorFormat::ProblemJson,
+            ..Default::default()
+        });
+        let response = Response::negotiated(&request, user(), None);
+        assert_eq!(response.get_status(), StatusCode::NOT_ACCEPTABLE);
+        assert_eq!(
+            response.get_headers().get(CONTENT_TYPE).unwrap(),
+            "application/problem+json"
+        );
+
+        // A representation excluded with q=0 isn't chosen through a wildcard
+        let response = Response::negotiated(
+            &request_accepting(Some("application/json;q=0, */*")),
+            user(),
+            None,
+        );
+        assert_eq!(
+            response.get_headers().get(CONTENT_TYPE).unwrap(),
+            "application/x-www-form-urlencoded"
+        );
+        #[cfg(feature = "templates")]
+        {
+            let response = Response::negotiated(
+                &request_accepting(Some("text/html;q=0, */*")),
+                user(),
+                Some("user.html"),
+            );
+            assert_eq!(
+                response.get_headers().get(CONTENT_TYPE).unwrap(),
+                "application/json"
+            );
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "templates")]
+    fn template_with_status_test() {
+        // Rendered from the templates folder of the crate
+        let response = Response::template_with_status(
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "error.html",
+            &serde_json::json!({
+                "status": 422,
+                "reason": "Unprocessable Entity",
+                "cause": "<alice> is already taken",
+            }),
+        )
+        .unwrap();
+
+        assert_eq!(response.get_status(), StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(
+            response.get_headers().get(CONTENT_TYPE).unwrap(),
+            "text/html"
+        );
+        assert!(
+            matches!(response.get_body(), Some(ResponseBody::Full(body)) if body == "<h1>422 Unprocessable Entity</h1>\n<p>&lt;alice&gt; is already taken</p>\n")
+        );
+
+        assert!(Response::template_with_status(
+            StatusCode::NOT_FOUND,
+            "missing.html",
+            &serde_json::json!({})
+        )
+        .is_err());
+    }
+
+    #[test]
+    #[cfg(all(feature = "templates", feature = "compression"))]
+    fn compressed_template_test() {
+        use std::io::Read;
+
+        use flate2::read::GzDecoder;
+        use hyper::header::ACCEPT_ENCODING;
+
+        let html = format!("<ul>{}</ul>", "<li>Citrine</li>".repeat(64));
+        let mut request_headers = HeaderMap::new();
+        request_headers.insert(ACCEPT_ENCODING, HeaderValue::from_static("gzip, deflate"));
+
+        let response = Response::rendered_template(html.clone()).compress_for(&request_headers);
+        let headers = response.get_headers();
+        assert_eq!(headers.get(CONTENT_ENCODING).unwrap(), "gzip");
+        assert_eq!(headers.get(VARY).unwrap(), "Accept-Encoding");
+        let compressed = match response.get_body() {
+            Some(ResponseBody::Full(bytes)) => bytes.clone(),
+            _ => panic!("Expected a full body"),
+        };
+        assert!(compressed.len() < html.len());
+        let mut decompressed = String::new();
+        GzDecoder::new(&compressed[..])
+            .read_to_string(&mut decompressed)
+            .unwrap();
+        assert_eq!(decompressed, html);
+
+        // Clients that don't accept any encoding get the HTML as is
+        let response = Response::rendered_template(html.clone()).compress_for(&HeaderMap::new());
+        assert!(response.get_headers().get(CONTENT_ENCODING).is_none());
+        assert_eq!(response.get_headers().get(VARY).unwrap(), "Accept-Encoding");
+    }
+}
@@ -1,29 +1,161 @@
-use hyper::header::CONTENT_TYPE;
-use hyper::Method;
-use log::debug;
+use chrono::{DateTime, Utc};
+use hyper::header::{HeaderName, HeaderValue, ALLOW, CONTENT_TYPE};
+use hyper::http::Extensions;
+use hyper::{HeaderMap, Method, StatusCode};
+use log::{debug, error};
+use serde::de::DeserializeOwned;
 use std::collections::HashMap;
 use std::fmt::Display;
 use std::sync::Arc;
+use validator::Validate;
 
+use crate::error;
+use crate::error::ErrorConfiguration;
+use crate::error::ErrorFormat;
+use crate::error::ErrorHandler;
 use crate::error::ErrorType;
 use crate::error::RequestError;
 use crate::error::ServerError;
+use crate::multipart::MultipartLimits;
+use crate::request::BodyLogging;
 use crate::request::ContentType;
+use crate::request::FormLimits;
 use crate::request::Request;
-use crate::response::Response;
+use crate::response::{IntoResponse, Response};
+use crate::serializers::ResponseSerializers;
+use crate::single_flight::{self, SingleFlight, SingleFlightKey};
+use crate::util;
 
-pub type RequestHandler<T> = fn(Arc<T>, Request) -> Response;
+const ACCEPT_PATCH: HeaderName = HeaderName::from_static("accept-patch");
+const ACCEPT_POST: HeaderName = HeaderName::from_static("accept-post");
+
+/// Handler as stored by the router. Routes take plain functions as well as closures that capture
+/// values, like configuration or handlers generated in a loop. Wrapping them allows routes to run
+/// extra logic around the user defined handler, like the body deserialization of post_json
+pub type RequestHandler<T> = Arc<dyn Fn(Arc<T>, Request) -> Response + Send + Sync>;
+
+/// Nests a router only when the crate that uses the macro is compiled with the feature, so the
+/// routers of optional modules, like an admin module that only some builds ship, are left out
+/// without #[cfg] attributes in the builder chain. Without the feature the nested router is not
+/// compiled at all, so it can come from a module behind the same feature
+///
+/// let router = feature_router!(Router::new().get("", index), "admin", admin::router());
+#[macro_export]
+macro_rules! feature_router {
+    ($router:expr, $feature:literal, $nested:expr $(,)?) => {{
+        let router: $crate::Router<_> = $router;
+        #[cfg(feature = $feature)]
+        let router: $crate::Router<_> = router.add_router($nested);
+        router
+    }};
+}
 
 pub struct Router<T: Send + Sync + 'static> {
     pub base_path: String,
     pub routes: Vec<Route<T>>,
+    // Sub-state added with with_state, given to the routes added after it
+    state: Extensions,
+    // Middlewares added with with_middleware, given to the routes added after it
+    middlewares: Vec<RouteMiddleware>,
 }
 
+/// Middleware attached to the routes of a router with Router::with_middleware
+pub type RouteMiddleware = fn(Request) -> Request;
+
 pub struct Route<T: Send + Sync + 'static> {
     pub method: Method,
     pub path: String,
+    /// Name to build the URL of the route with url_for
+    pub name: Option<String>,
     pub handler: RequestHandler<T>,
     pub accepts_type: Accepts,
+    pub deprecation: Option<Deprecation>,
+    pub stream_body: bool,
+    pub max_body_size: Option<usize>,
+    pub default_headers: HeaderMap,
+    pub body_logging: BodyLogging,
+    /// Requests to the route skip the security rules
+    pub public: bool,
+    /// Router scoped state, added to the extensions of the requests to this route
+    pub extensions: Extensions,
+    /// Middlewares of the routers the route belongs to, run in order before the handler
+    pub middlewares: Vec<RouteMiddleware>,
+    // How many of the middlewares, the first ones, come from the router the route is in now.
+    // Middlewares added later to that router go after them, before the ones of nested routers
+    router_middlewares: usize,
+    // Default response headers whose value can't be sent in a header, reported as an error when
+    // the route is added to the internal router
+    invalid_default_headers: Vec<String>,
+    /// The accepted types were not chosen for this route but are the defaults of the helper
+    /// method that added it, so the application wide default replaces them if there is one
+    pub default_accepts_type: bool,
+}
+
+impl<T> Route<T>
+where
+    T: Send + Sync + 'static,
+{
+    /// Names of the path variables declared in the route path, in order
+    pub fn path_variable_names(&self) -> Vec<String> {
+        declared_path_variables(&self.path)
+            .into_iter()
+            .map(|variable| variable.name)
+            .collect()
+    }
+}
+
+impl<T> Clone for Route<T>
+where
+    T: Send + Sync + 'static,
+{
+    fn clone(&self) -> Self {
+        Route {
+            method: self.method.clone(),
+            path: self.path.clone(),
+            name: self.name.clone(),
+            handler: self.handler.clone(),
+            accepts_type: self.accepts_type.clone(),
+            deprecation: self.deprecation.clone(),
+            stream_body: self.stream_body,
+            max_body_size: self.max_body_size,
+            default_headers: self.default_headers.clone(),
+            body_logging: self.body_logging,
+            public: self.public,
+            extensions: self.extensions.clone(),
+            middlewares: self.middlewares.clone(),
+            router_middlewares: self.router_middlewares,
+            invalid_default_headers: self.invalid_default_headers.clone(),
+            default_accepts_type: self.default_accepts_type,
+        }
+    }
+}
+
+/// Marks a route as deprecated. Its responses will carry a Deprecation header and, if a sunset
+/// date is set, a Sunset header (RFC 8594) with the date the route will stop working
+#[derive(Clone, Debug, Default)]
+pub struct Deprecation {
+    sunset: Option<DateTime<Utc>>,
+}
+
+impl Deprecation {
+    fn apply(&self, mut response: Response) -> Response {
+        response = response.add_header(HeaderName::from_static("deprecation"), "true");
+        if let Some(sunset) = self.sunset {
+            response =
+                response.add_header(HeaderName::from_static("sunset"), &util::http_date(&sunset));
+        }
+        response
+    }
+}
+
+/// What to do with bodies sent in GET and HEAD requests to routes that don't accept any
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum GetBodyPolicy {
+    /// The body is dropped before reaching the handler
+    #[default]
+    Ignore,
+    /// The request is answered with a 400 Bad Request
+    Reject,
 }
 
 #[derive(Clone, Debug)]
@@ -34,6 +166,18 @@ pub enum Accepts {
 }
 
 impl Accepts {
+    /// Media types of the accepted Content-Type headers
+    pub fn media_types(&self) -> Vec<String> {
+        match self {
+            Accepts::None => vec![],
+            Accepts::One(content_type) => vec![content_type.as_header_value()],
+            Accepts::Multiple(content_types) => content_types
+                .iter()
+                .map(|content_type| content_type.as_header_value())
+                .collect(),
+        }
+    }
+
     pub fn get_matching(&self, req: &Request) -> Option<ContentType> {
         if let Some(content_type) = req.headers.get(CONTENT_TYPE) {
             let content_type = content_type.to_str().unwrap();
@@ -87,17 +231,24 @@ where
         Router {
             base_path: String::new(),
             routes: Vec::new(),
+            state: Extensions::new(),
+            middlewares: Vec::new(),
         }
     }
 
     pub fn add_router(mut self, nested: Router<T>) -> Self {
-        for route in nested.routes.iter() {
-            self = self.add_route(
-                route.method.clone(),
-                &route.path,
-                route.handler,
-                route.accepts_type.clone(),
-            );
+        for mut route in nested.routes {
+            route.path = self.full_path(&route.path);
+            // State of the nested router takes precedence over the one of its parent
+            let mut extensions = self.state.clone();
+            extensions.extend(route.extensions);
+            route.extensions = extensions;
+            // Middlewares of the parent run before the ones of the nested router
+            let mut middlewares = self.middlewares.clone();
+            middlewares.append(&mut route.middlewares);
+            route.middlewares = middlewares;
+            route.router_middlewares = self.middlewares.len();
+            self.routes.push(route);
         }
 
         self
@@ -107,52 +258,357 @@ where
         Router {
             base_path: base_path.to_string(),
             routes: Vec::new(),
+            state: Extensions::new(),
+            middlewares: Vec::new(),
+        }
+    }
+
+    /// Sub-state only available to the handlers of this router and its nested routers, read with
+    /// Request::state. Useful to give a module its own services instead of adding them to the
+    /// global context. Nested routers can set their own state of the same type, which takes
+    /// precedence over this one
+    pub fn with_state<S: Send + Sync + 'static>(mut self, state: S) -> Self {
+        let state = Arc::new(state);
+        for route in self.routes.iter_mut() {
+            if route.extensions.get::<Arc<S>>().is_none() {
+                route.extensions.insert(state.clone());
+            }
+        }
+        self.state.insert(state);
+        self
+    }
+
+    /// Middleware that runs before the handler of every route of this router and its nested
+    /// routers, including the ones added before it. Middlewares run in the order they were added,
+    /// with the ones of a router before the ones of its nested routers. Unlike the application
+    /// middlewares, they are not matched by path, so every one of them runs
+    pub fn with_middleware(mut self, middleware: RouteMiddleware) -> Self {
+        for route in self.routes.iter_mut() {
+            route
+                .middlewares
+                .insert(route.router_middlewares, middleware);
+            route.router_middlewares += 1;
         }
+        self.middlewares.push(middleware);
+        self
     }
 
     pub fn add_route(
+        self,
+        method: Method,
+        path: &str,
+        handler: impl Fn(Arc<T>, Request) -> Response + Send + Sync + 'static,
+        accepts_type: Accepts,
+    ) -> Self {
+        self.add_boxed_route(method, path, Arc::new(handler), accepts_type)
+    }
+
+    /// Route with a name, so its URL can be built from the name and the values of its path
+    /// variables with Request::url_for instead of hardcoding it, like in links between pages
+    pub fn add_named_route(
+        self,
+        name: &str,
+        method: Method,
+        path: &str,
+        handler: impl Fn(Arc<T>, Request) -> Response + Send + Sync + 'static,
+        accepts_type: Accepts,
+    ) -> Self {
+        let mut router = self.add_route(method, path, handler, accepts_type);
+        if let Some(route) = router.routes.last_mut() {
+            route.name = Some(name.to_string());
+        }
+        router
+    }
+
+    /// Route with a handler that returns a Result, so errors can be propagated with ? instead of
+    /// matching them in every handler. Errors are converted into the response with their
+    /// IntoResponse implementation, which becomes the single place to shape error responses
+    pub fn add_fallible_route<E>(
+        self,
+        method: Method,
+        path: &str,
+        handler: impl Fn(Arc<T>, Request) -> Result<Response, E> + Send + Sync + 'static,
+        accepts_type: Accepts,
+    ) -> Self
+    where
+        E: IntoResponse + 'static,
+    {
+        self.add_boxed_route(
+            method,
+            path,
+            Arc::new(move |context, request| match handler(context, request) {
+                Ok(response) => response,
+                Err(e) => e.into_response(),
+            }),
+            accepts_type,
+        )
+    }
+
+    fn add_boxed_route(
         mut self,
         method: Method,
         path: &str,
         handler: RequestHandler<T>,
         accepts_type: Accepts,
     ) -> Self {
-        let mut real_path = format!("{}{}", self.base_path, path);
-        if real_path.is_empty() {
-            real_path = "/".to_string();
-        }
+        let real_path = self.full_path(path);
         self.routes.push(Route {
             method,
             path: real_path,
+            name: None,
             handler,
             accepts_type,
+            deprecation: None,
+            stream_body: false,
+            max_body_size: None,
+            default_headers: HeaderMap::new(),
+            body_logging: BodyLogging::Full,
+            public: false,
+            extensions: self.state.clone(),
+            middlewares: self.middlewares.clone(),
+            router_middlewares: self.middlewares.len(),
+            invalid_default_headers: Vec::new(),
+            default_accepts_type: false,
         });
         self
     }
 
-    pub fn get(self, path: &str, handler: RequestHandler<T>) -> Self {
+    /// Adds a route of a helper method, with the accepted types of the method if the application
+    /// does not set a default
+    fn add_route_with_default_accepts(
+        self,
+        method: Method,
+        path: &str,
+        handler: impl Fn(Arc<T>, Request) -> Response + Send + Sync + 'static,
+        accepts_type: Accepts,
+    ) -> Self {
+        let mut router = self.add_route(method, path, handler, accepts_type);
+        if let Some(route) = router.routes.last_mut() {
+            route.default_accepts_type = true;
+        }
+        router
+    }
+
+    /// Replaces the accepted types of the routes that use the defaults of their helper method
+    pub(crate) fn apply_default_accepts(&mut self, accepts_type: &Accepts) {
+        for route in self
+            .routes
+            .iter_mut()
+            .filter(|route| route.default_accepts_type)
+        {
+            route.accepts_type = accepts_type.clone();
+        }
+    }
+
+    /// Header added to every response of the last added route, unless the handler sets it
+    /// itself. Useful for things like Cache-Control for a whole group of routes. A value that
+    /// can't be sent in a header makes building the router fail
+    pub fn default_response_header(mut self, key: HeaderName, value: &str) -> Self {
+        if let Some(route) = self.routes.last_mut() {
+            match HeaderValue::from_str(value) {
+                Ok(value) => {
+                    route.default_headers.insert(key, value);
+                }
+                Err(e) => route
+                    .invalid_default_headers
+                    .push(format!("{}: {}", key, e)),
+            }
+        }
+        self
+    }
+
+    /// Maximum size in bytes of the body of the last added route. Requests with a bigger body will
+    /// be rejected with a 413 Payload Too Large
+    pub fn max_body_size(mut self, max_body_size: usize) -> Self {
+        if let Some(route) = self.routes.last_mut() {
+            route.max_body_size = Some(max_body_size);
+        }
+        self
+    }
+
+    /// How much of the body of the last added route is kept for logging after the handler has run.
+    /// The response middlewares and interceptor only see that part of the body
+    pub fn body_logging(mut self, body_logging: BodyLogging) -> Self {
+        if let Some(route) = self.routes.last_mut() {
+            route.body_logging = body_logging;
+        }
+        self
+    }
+
+    /// The body of the last added route will not be read before reaching the handler, which can
+    /// consume it as a stream of chunks with Request::body_stream. Useful for large uploads
+    pub fn stream_body(mut self) -> Self {
+        if let Some(route) = self.routes.last_mut() {
+            route.stream_body = true;
+        }
+        self
+    }
+
+    /// Concurrent identical requests to the last added route share a single execution of the
+    /// handler, and all of them get its response. Meant for expensive GET routes, to avoid running
+    /// the same work many times on cache misses. Requests are identical if they have the same
+    /// method, path and query
+    pub fn single_flight(self) -> Self {
+        self.single_flight_by(single_flight::method_and_path_key)
+    }
+
+    /// Like single_flight, with a custom key to tell identical requests apart. The key should
+    /// include anything the response depends on, like the user for personalized responses
+    pub fn single_flight_by(mut self, key: SingleFlightKey) -> Self {
+        if let Some(route) = self.routes.last_mut() {
+            let handler = route.handler.clone();
+            let single_flight = Arc::new(SingleFlight::default());
+            route.handler = Arc::new(move |context, request| {
+                single_flight.run(key(&request), || handler(context, request))
+            });
+        }
+        self
+    }
+
+    /// Requests to the last added route are allowed without going through the security rules,
+    /// even if a rule protecting a whole subtree like /api/* matches them. Useful for endpoints
+    /// like health checks without carefully ordering the rules
+    pub fn public(mut self) -> Self {
+        if let Some(route) = self.routes.last_mut() {
+            route.public = true;
+        }
+        self
+    }
+
+    /// Marks the last added route as deprecated
+    pub fn deprecated(mut self) -> Self {
+        if let Some(route) = self.routes.last_mut() {
+            route.deprecation.get_or_insert_with(Deprecation::default);
+        }
+        self
+    }
+
+    /// Marks the last added route as deprecated and sets the date it will stop working
+    pub fn sunset(mut self, sunset: DateTime<Utc>) -> Self {
+        if let Some(route) = self.routes.last_mut() {
+            route.deprecation = Some(Deprecation {
+                sunset: Some(sunset),
+            });
+        }
+        self
+    }
+
+    fn full_path(&self, path: &str) -> String {
+        let real_path = format!("{}{}", self.base_path, path);
+        if real_path.is_empty() {
+            "/".to_string()
+        } else {
+            real_path
+        }
+    }
+
+    pub fn get(
+        self,
+        path: &str,
+        handler: impl Fn(Arc<T>, Request) -> Response + Send + Sync + 'static,
+    ) -> Self {
         self.add_route(Method::GET, path, handler, Accepts::None)
     }
 
-    pub fn post(self, path: &str, handler: RequestHandler<T>) -> Self {
-        self.add_route(Method::POST, path, handler, Accepts::One(ContentType::Json))
+    /// Handler for the requests of the method to any path under the base path of the router that
+    /// no other route matches, like a `/*path` route. Every other route takes precedence over it,
+    /// and the rest of the path is bound to the `path` variable
+    pub fn catch_all(
+        self,
+        method: Method,
+        handler: impl Fn(Arc<T>, Request) -> Response + Send + Sync + 'static,
+    ) -> Self {
+        let accepts_type = if method == Method::GET || method == Method::HEAD {
+            Accepts::None
+        } else {
+            Accepts::One(ContentType::Json)
+        };
+        self.add_route_with_default_accepts(method, "/*path", handler, accepts_type)
     }
 
-    pub fn put(self, path: &str, handler: RequestHandler<T>) -> Self {
-        self.add_route(Method::PUT, path, handler, Accepts::One(ContentType::Json))
+    /// GET route under /.well-known (RFC 8615), like /.well-known/openid-configuration. The path
+    /// is relative to the base path of the router, so it should be registered in the root one
+    pub fn well_known(
+        self,
+        name: &str,
+        handler: impl Fn(Arc<T>, Request) -> Response + Send + Sync + 'static,
+    ) -> Self {
+        self.get(&format!("/.well-known/{}", name), handler)
     }
 
-    pub fn patch(self, path: &str, handler: RequestHandler<T>) -> Self {
-        self.add_route(
-            Method::PATCH,
+    pub fn post(
+        self,
+        path: &str,
+        handler: impl Fn(Arc<T>, Request) -> Response + Send + Sync + 'static,
+    ) -> Self {
+        self.add_route_with_default_accepts(
+            Method::POST,
+            path,
+            handler,
+            Accepts::One(ContentType::Json),
+        )
+    }
+
+    /// POST route that receives its JSON body already deserialized and validated. Requests with a
+    /// body that can't be read or fails validation are answered with a 400 Bad Request without
+    /// reaching the handler
+    pub fn post_json<B>(
+        self,
+        path: &str,
+        handler: impl Fn(Arc<T>, B, Request) -> Response + Send + Sync + 'static,
+    ) -> Self
+    where
+        B: DeserializeOwned + Validate + 'static,
+    {
+        self.add_boxed_route(
+            Method::POST,
+            path,
+            Arc::new(
+                move |context, request: Request| match request.get_body_validated::<B>() {
+                    Ok(body) => handler(context, body, request),
+                    Err(e) => error::route_error_response(e, &request),
+                },
+            ),
+            Accepts::One(ContentType::Json),
+        )
+    }
+
+    pub fn put(
+        self,
+        path: &str,
+        handler: impl Fn(Arc<T>, Request) -> Response + Send + Sync + 'static,
+    ) -> Self {
+        self.add_route_with_default_accepts(
+            Method::PUT,
             path,
             handler,
             Accepts::One(ContentType::Json),
         )
     }
 
-    pub fn delete(self, path: &str, handler: RequestHandler<T>) -> Self {
-        self.add_route(
+    pub fn patch(
+        self,
+        path: &str,
+        handler: impl Fn(Arc<T>, Request) -> Response + Send + Sync + 'static,
+    ) -> Self {
+        self.add_route_with_default_accepts(
+            Method::PATCH,
+            path,
+            handler,
+            Accepts::Multiple(vec![
+                ContentType::Json,
+                ContentType::MergePatchJson,
+                ContentType::JsonPatchJson,
+            ]),
+        )
+    }
+
+    pub fn delete(
+        self,
+        path: &str,
+        handler: impl Fn(Arc<T>, Request) -> Response + Send + Sync + 'static,
+    ) -> Self {
+        self.add_route_with_default_accepts(
             Method::DELETE,
             path,
             handler,
@@ -170,15 +626,77 @@ where
     }
 }
 
+impl<T> Clone for Router<T>
+where
+    T: Send + Sync + 'static,
+{
+    fn clone(&self) -> Self {
+        Router {
+            base_path: self.base_path.clone(),
+            routes: self.routes.clone(),
+            state: self.state.clone(),
+            middlewares: self.middlewares.clone(),
+        }
+    }
+}
+
 pub struct InternalRouter<T: Send + Sync + 'static> {
     routes: HashMap<Method, HashMap<String, RouterNode<T>>>,
+    default_max_body_size: Option<usize>,
+    get_body_policy: GetBodyPolicy,
+    allow_trace_and_connect: bool,
+    trim_strings: bool,
+    form_limits: FormLimits,
+    multipart_limits: MultipartLimits,
+    route_urls: RouteUrls,
+    error_configuration: ErrorConfiguration,
+    response_serializers: Option<Arc<ResponseSerializers>>,
+}
+
+/// A path variable declared in a route and the position of its segment in the path
+#[derive(Clone, Debug)]
+struct PathVariable {
+    name: String,
+    index: usize,
+    catch_all: bool,
+}
+
+fn declared_path_variables(path: &str) -> Vec<PathVariable> {
+    path.split("/")
+        .enumerate()
+        .filter_map(|(index, elem)| {
+            if let Some(name) = elem.strip_prefix(":") {
+                Some(PathVariable {
+                    name: name.to_string(),
+                    index,
+                    catch_all: false,
+                })
+            } else {
+                elem.strip_prefix("*").map(|name| PathVariable {
+                    name: name.to_string(),
+                    index,
+                    catch_all: true,
+                })
+            }
+        })
+        .collect()
 }
 
 pub struct RouterNode<T: Send + Sync + 'static> {
     routes: HashMap<String, RouterNode<T>>,
     handler: Option<RequestHandler<T>>,
-    variable: Option<String>,
+    // Variables are read from the route of the handler, as nodes for variable segments are shared
+    // by every route that goes through them, whatever name each route gives the variable
+    path_variables: Vec<PathVariable>,
     accepts_type: Accepts,
+    deprecation: Option<Deprecation>,
+    stream_body: bool,
+    max_body_size: Option<usize>,
+    default_headers: HeaderMap,
+    body_logging: BodyLogging,
+    public: bool,
+    extensions: Extensions,
+    middlewares: Vec<RouteMiddleware>,
 }
 
 impl<T> InternalRouter<T>
@@ -188,6 +706,15 @@ where
     pub fn new() -> InternalRouter<T> {
         InternalRouter {
             routes: HashMap::new(),
+            default_max_body_size: None,
+            get_body_policy: GetBodyPolicy::default(),
+            allow_trace_and_connect: false,
+            trim_strings: false,
+            form_limits: FormLimits::default(),
+            multipart_limits: MultipartLimits::default(),
+            route_urls: RouteUrls::default(),
+            error_configuration: ErrorConfiguration::default(),
+            response_serializers: None,
         }
     }
 
@@ -203,7 +730,24 @@ where
 
     pub fn add_route(&mut self, route: Route<T>) -> Result<(), ServerError> {
         debug!("Binding route {} {}", route.method, route.path);
+        if let Some(invalid_header) = route.invalid_default_headers.first() {
+            return Err(ServerError::from(format!(
+                "Invalid default response header {} in route {} {}",
+                invalid_header, route.method, route.path
+            )));
+        }
+        if let Some(name) = route.name.as_ref() {
+            let route_urls = Arc::make_mut(&mut self.route_urls.0);
+            if route_urls.contains_key(name) {
+                return Err(ServerError::from(format!(
+                    "Route name {} is already used",
+                    name
+                )));
+            }
+            route_urls.insert(name.clone(), route.path.clone());
+        }
         let routes: Vec<String> = route.path.split("/").map(|s| s.to_string()).collect();
+        let path_variables = declared_path_variables(&route.path);
 
         let method_map = self.routes.get(&route.method);
         if method_map.is_none() {
@@ -216,9 +760,8 @@ where
 
         for (i, elem) in routes.iter().enumerate() {
             let key: String;
-            let variable: Option<String>;
 
-            if let Some(variable_name) = elem.strip_prefix(":") {
+            if elem.starts_with(":") {
                 if elem.len() <= 1 {
                     return Err(ServerError::from(format!(
                         "Malformed path: Variable without name in path {}",
@@ -228,26 +771,56 @@ where
 
                 //todo optimize this
                 key = "VARIABLE".to_string();
-                variable = Some(variable_name.to_string())
+            } else if elem.starts_with("*") {
+                if elem.len() <= 1 {
+                    return Err(ServerError::from(format!(
+                        "Malformed path: Catch-all without name in path {}",
+                        route.path
+                    )));
+                }
+                if i != routes.len() - 1 {
+                    return Err(ServerError::from(format!(
+                        "Malformed path: Catch-all {} must be the last segment in path {}",
+                        elem, route.path
+                    )));
+                }
+
+                key = "CATCH_ALL".to_string();
             } else {
                 // normal path element
                 key = elem.to_string();
-                variable = None;
             }
 
             if !current.contains_key(&key) {
                 let node = RouterNode {
                     routes: HashMap::new(),
                     handler: None,
-                    variable,
+                    path_variables: Vec::new(),
                     accepts_type: Accepts::None,
+                    deprecation: None,
+                    stream_body: false,
+                    max_body_size: None,
+                    default_headers: HeaderMap::new(),
+                    body_logging: BodyLogging::Full,
+                    public: false,
+                    extensions: Extensions::new(),
+                    middlewares: Vec::new(),
                 };
                 current.insert(key.clone(), node);
                 if i == routes.len() - 1 {
                     // Node with handler is inserted
                     let inserted_node = current.get_mut(&key).unwrap();
                     inserted_node.handler = Some(route.handler);
+                    inserted_node.path_variables = path_variables;
                     inserted_node.accepts_type = route.accepts_type;
+                    inserted_node.deprecation = route.deprecation;
+                    inserted_node.stream_body = route.stream_body;
+                    inserted_node.max_body_size = route.max_body_size;
+                    inserted_node.default_headers = route.default_headers;
+                    inserted_node.body_logging = route.body_logging;
+                    inserted_node.public = route.public;
+                    inserted_node.extensions = route.extensions;
+                    inserted_node.middlewares = route.middlewares;
                     break;
                 }
                 current = &mut current.get_mut(&key).unwrap().routes;
@@ -261,6 +834,16 @@ where
                         )));
                     }
                     node.handler = Some(route.handler);
+                    node.path_variables = path_variables;
+                    node.accepts_type = route.accepts_type;
+                    node.deprecation = route.deprecation;
+                    node.stream_body = route.stream_body;
+                    node.max_body_size = route.max_body_size;
+                    node.default_headers = route.default_headers;
+                    node.body_logging = route.body_logging;
+                    node.public = route.public;
+                    node.extensions = route.extensions;
+                    node.middlewares = route.middlewares;
                     break;
                 }
                 current = &mut node.routes;
@@ -270,142 +853,484 @@ where
         Ok(())
     }
 
-    pub fn run(&self, mut req: Request, context: Arc<T>) -> (Request, Response) {
-        let mut path_variables = HashMap::<String, String>::new();
-
-        let method_map = self.routes.get(&req.method);
-        if method_map.is_none() {
-            let path = req.uri.path().to_owned();
-            let method = req.method.clone();
-            return (
-                req,
-                RequestError::with_message(
-                    ErrorType::MethodNotAllowed,
-                    &format!("{} {}", method, &path),
-                )
-                .into(),
-            );
-        }
+    /// Finds the node with the handler for the method and path, along with the values of the
+    /// path variables. A catch-all segment binds the rest of the path, slashes included
+    fn find(
+        &self,
+        method: &Method,
+        path: &str,
+    ) -> Option<(&RouterNode<T>, HashMap<String, String>)> {
+        let routes: Vec<&str> = path.split("/").collect();
+        let node = Self::find_node(self.routes.get(method)?, &routes)?;
+        Some((node, Self::bind_path_variables(node, &routes)))
+    }
 
-        let routes: Vec<String> = req.uri.path().split("/").map(|s| s.to_string()).collect();
-        let mut current = self.routes.get(&req.method).unwrap();
-        for (i, elem) in routes.iter().enumerate() {
-            let mut opt_node = current.get(elem);
-            //no match for this node
-            if opt_node.is_none() {
-                //let's try to match a variable
-                opt_node = current.get("VARIABLE");
-
-                //can't match this route
-                if opt_node.is_none() {
-                    let path = req.uri.path().to_owned();
-                    return (
-                        req,
-                        RequestError::with_message(ErrorType::NotFound, &path).into(),
-                    );
-                }
-            }
-            let node = opt_node.unwrap();
-            if node.variable.is_some() {
-                // can this be optimized?
-                path_variables.insert(node.variable.clone().unwrap(), elem.clone());
-            }
-            if i == routes.len() - 1 {
-                if let Some(function) = node.handler.as_ref() {
-                    req.set_path_variables(path_variables);
-
-                    let content_type_opt = node.accepts_type.get_matching(&req);
-                    // If we have a GET or don't have a body ignore this
-                    if req.get_body_raw().is_some() {
-                        // Matches if request Content-Type is compatible with the route
-                        if let Some(content_type) = content_type_opt {
-                            req.set_content_type(content_type);
-                        } else {
-                            return (
-                                req,
-                                RequestError::with_message(
-                                    ErrorType::UnsupportedMediaType,
-                                    &node.accepts_type.to_string(),
-                                )
-                                .into(),
-                            );
-                        }
-                    }
-                    // The handler has found a valid route
-                    return (req.clone(), function(context.clone(), req));
+    /// Matching precedence is the same at every segment: static segments first, then variables
+    /// and then catch-alls. If the more specific option doesn't lead to a handler, the next one
+    /// is tried, so /users/new/posts still matches /users/:id/posts when /users/new exists
+    fn find_node<'a>(
+        nodes: &'a HashMap<String, RouterNode<T>>,
+        routes: &[&str],
+    ) -> Option<&'a RouterNode<T>> {
+        let (elem, rest) = routes.split_first()?;
+        for key in [*elem, "VARIABLE"] {
+            if let Some(node) = nodes.get(key) {
+                let found = if rest.is_empty() {
+                    node.handler.as_ref().map(|_| node)
                 } else {
-                    let path = req.uri.path().to_owned();
-                    return (
-                        req,
-                        RequestError::with_message(ErrorType::NotFound, &path).into(),
-                    );
+                    Self::find_node(&node.routes, rest)
+                };
+                if found.is_some() {
+                    return found;
                 }
             }
-            current = &node.routes;
         }
 
-        let path = req.uri.path().to_owned();
-        (
-            req,
-            RequestError::with_message(ErrorType::NotFound, &path).into(),
-        )
+        nodes.get("CATCH_ALL").filter(|node| node.handler.is_some())
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use hyper::{HeaderMap, StatusCode, Uri};
+    fn bind_path_variables(node: &RouterNode<T>, routes: &[&str]) -> HashMap<String, String> {
+        node.path_variables
+            .iter()
+            .filter_map(|variable| {
+                let value = if variable.catch_all {
+                    routes.get(variable.index..).map(|rest| rest.join("/"))
+                } else {
+                    routes.get(variable.index).map(|elem| elem.to_string())
+                };
+                value.map(|value| (variable.name.clone(), value))
+            })
+            .collect()
+    }
 
-    use crate::security::AuthResult;
+    /// Whether the route matching the request reads its body as a stream instead of it being
+    /// collected before reaching the handler
+    pub fn streams_body(&self, method: &Method, path: &str) -> bool {
+        self.find(method, path)
+            .map(|(node, _)| node.stream_body)
+            .unwrap_or(false)
+    }
 
-    use super::*;
+    /// Whether any route matches the path, whatever its method
+    pub fn has_route(&self, path: &str) -> bool {
+        self.routes
+            .keys()
+            .any(|method| self.find(method, path).is_some())
+    }
 
-    struct ContextTest {}
+    /// Whether the route matching the request was marked as public, so the security rules are
+    /// skipped for it
+    pub fn is_public(&self, method: &Method, path: &str) -> bool {
+        self.find(method, path)
+            .map(|(node, _)| node.public)
+            .unwrap_or(false)
+    }
 
-    impl Default for ContextTest {
-        fn default() -> Self {
-            ContextTest {}
-        }
+    /// Limit for routes that don't set their own
+    pub fn set_default_max_body_size(&mut self, max_body_size: usize) {
+        self.default_max_body_size = Some(max_body_size);
     }
 
-    #[test]
-    fn router_test() {
-        let mut router = InternalRouter::new();
-        let route = Route {
-            method: Method::GET,
-            path: "/hello".to_string(),
-            handler: |_, _| {
-                return Response::new(StatusCode::OK).json("Hello world");
-            },
-            accepts_type: Accepts::None,
-        };
-        if let Err(e) = router.add_route(route) {
-            panic!("{}", e)
-        }
-        let route = Route {
-            method: Method::POST,
-            path: "/hello/other".to_string(),
-            handler: |_, _| {
-                return Response::new(StatusCode::OK).json("Hello world");
-            },
-            accepts_type: Accepts::One(ContentType::Json),
-        };
-        if let Err(e) = router.add_route(route) {
-            panic!("{}", e)
+    pub fn set_get_body_policy(&mut self, get_body_policy: GetBodyPolicy) {
+        self.get_body_policy = get_body_policy;
+    }
+
+    pub fn set_allow_trace_and_connect(&mut self, allow_trace_and_connect: bool) {
+        self.allow_trace_and_connect = allow_trace_and_connect;
+    }
+
+    pub fn set_trim_strings(&mut self, trim_strings: bool) {
+        self.trim_strings = trim_strings;
+    }
+
+    pub fn set_form_limits(&mut self, form_limits: FormLimits) {
+        self.form_limits = form_limits;
+    }
+
+    pub fn set_multipart_limits(&mut self, multipart_limits: MultipartLimits) {
+        self.multipart_limits = multipart_limits;
+    }
+
+    pub fn set_error_handler(&mut self, error_handler: Option<ErrorHandler>) {
+        self.error_configuration.handler = error_handler;
+    }
+
+    pub fn set_error_format(&mut self, error_format: ErrorFormat) {
+        self.error_configuration.format = error_format;
+    }
+
+    #[cfg(feature = "templates")]
+    pub fn set_error_templates(&mut self, error_templates: HashMap<StatusCode, String>) {
+        self.error_configuration.templates = Arc::new(error_templates);
+    }
+
+    /// Serializers handlers get through the request for Response::json_registered
+    pub fn set_response_serializers(&mut self, response_serializers: Option<ResponseSerializers>) {
+        self.response_serializers = response_serializers.map(Arc::new);
+    }
+
+    /// Response for the errors of the router, rendered by the error handler of the application if
+    /// it has one
+    fn error_response(&self, error: RequestError, req: &Request) -> Response {
+        error::error_response(&self.error_configuration, error, &req.metadata())
+    }
+
+    /// 415 response listing the accepted media types in the body and in the Accept-Patch header for
+    /// PATCH requests (RFC 5789) or the Accept-Post one for the rest, so clients can discover them
+    fn unsupported_media_type(&self, req: &Request, accepts_type: &Accepts) -> Response {
+        let media_types = accepts_type.media_types();
+        let error =
+            RequestError::with_message(ErrorType::UnsupportedMediaType, &accepts_type.to_string())
+                .supported_media_types(media_types.clone());
+        let response = self.error_response(error, req);
+        if media_types.is_empty() {
+            return response;
         }
-        let route = Route {
-            method: Method::GET,
-            path: "/hi/other".to_string(),
-            handler: |_, _| {
-                return Response::new(StatusCode::OK).json("Hello world");
-            },
-            accepts_type: Accepts::None,
+
+        let header = if req.method == Method::PATCH {
+            ACCEPT_PATCH
+        } else {
+            ACCEPT_POST
         };
-        if let Err(e) = router.add_route(route) {
-            panic!("{}", e)
-        }
+        response.add_header(header, &media_types.join(", "))
+    }
 
-        let tabs = 0;
+    pub fn max_body_size(&self, method: &Method, path: &str) -> Option<usize> {
+        self.find(method, path)
+            .and_then(|(node, _)| node.max_body_size)
+            .or(self.default_max_body_size)
+    }
+
+    /// What clients can do with the path, read from the routes that match it in a single pass
+    /// over the route table. The allowed methods are sorted and always include OPTIONS if there is
+    /// any, as it is answered automatically when there isn't a handler for it
+    fn capabilities(&self, path: &str) -> Capabilities {
+        let mut capabilities = Capabilities::default();
+        for method in self.routes.keys() {
+            let Some((node, _)) = self.find(method, path) else {
+                continue;
+            };
+            capabilities.allowed_methods.push(method.clone());
+            if method == Method::POST {
+                capabilities.accept_post = node.accepts_type.media_types();
+            } else if method == Method::PATCH {
+                capabilities.accept_patch = node.accepts_type.media_types();
+            }
+        }
+
+        let methods = &mut capabilities.allowed_methods;
+        if !methods.is_empty() && !methods.contains(&Method::OPTIONS) {
+            methods.push(Method::OPTIONS);
+        }
+        methods.sort_by(|a, b| a.as_str().cmp(b.as_str()));
+        capabilities
+    }
+
+    /// Methods with a handler for any path, sorted, along with OPTIONS. TRACE and CONNECT are left
+    /// out unless they are allowed to reach their routes
+    fn server_allowed_methods(&self) -> Vec<Method> {
+        let mut methods: Vec<Method> = self
+            .routes
+            .keys()
+            .filter(|method| {
+                self.allow_trace_and_connect
+                    || (**method != Method::TRACE && **method != Method::CONNECT)
+            })
+            .cloned()
+            .collect();
+        if !methods.contains(&Method::OPTIONS) {
+            methods.push(Method::OPTIONS);
+        }
+        methods.sort_by(|a, b| a.as_str().cmp(b.as_str()));
+        methods
+    }
+
+    pub fn run(&self, mut req: Request, context: Arc<T>) -> (Request, Response) {
+        // TRACE echoes the request back, which can leak credentials in cross-site tracing attacks,
+        // and CONNECT is meant for proxies, so neither reaches a handler unless allowed
+        if !self.allow_trace_and_connect
+            && (req.method == Method::TRACE || req.method == Method::CONNECT)
+        {
+            let message = format!("{} {}", req.method, req.path());
+            let error = RequestError::with_message(ErrorType::NotImplemented, &message);
+            let response = self.error_response(error, &req);
+            return (req, response);
+        }
+
+        // OPTIONS * asks for the capabilities of the whole server instead of a resource (RFC 9110)
+        if req.method == Method::OPTIONS && req.path() == "*" {
+            let allow = allow_header(&self.server_allowed_methods());
+            return (
+                req,
+                Response::empty(StatusCode::NO_CONTENT).add_header(ALLOW, &allow),
+            );
+        }
+
+        let find_res = self.find(&req.method, req.path());
+        if find_res.is_none() {
+            let path = req.path().to_owned();
+            let capabilities = self.capabilities(&path);
+            if capabilities.allowed_methods.is_empty() {
+                let error = RequestError::with_message(ErrorType::NotFound, &path);
+                let response = self.error_response(error, &req);
+                return (req, response);
+            }
+
+            if req.method == Method::OPTIONS {
+                let response = capabilities.apply(Response::empty(StatusCode::NO_CONTENT));
+                return (req, response);
+            }
+
+            let error = RequestError::with_message(
+                ErrorType::MethodNotAllowed,
+                &format!("{} {}", req.method, &path),
+            );
+            let response = self.error_response(error, &req);
+            return (req, capabilities.apply(response));
+        }
+        let (node, path_variables) = find_res.unwrap();
+        let function = node.handler.as_ref().unwrap();
+
+        // Handlers expect every variable of their route to be present
+        let missing_variables: Vec<&str> = node
+            .path_variables
+            .iter()
+            .filter(|variable| !path_variables.contains_key(&variable.name))
+            .map(|variable| variable.name.as_str())
+            .collect();
+        if !missing_variables.is_empty() {
+            let message = format!(
+                "Path variables {} missing for {} {}",
+                missing_variables.join(", "),
+                req.method,
+                req.path()
+            );
+            error!("{}", message);
+            let error = RequestError::with_message(ErrorType::Internal, &message);
+            let response = self.error_response(error, &req);
+            return (req, response);
+        }
+        req.set_path_variables(path_variables);
+        req.set_trim_strings(self.trim_strings);
+        req.set_form_limits(self.form_limits);
+        req.set_multipart_limits(self.multipart_limits);
+        req.extensions_mut().insert(self.route_urls.clone());
+        req.extensions_mut()
+            .insert(self.error_configuration.clone());
+        if let Some(response_serializers) = self.response_serializers.as_ref() {
+            req.extensions_mut().insert(response_serializers.clone());
+        }
+        req.extensions_mut().extend(node.extensions.clone());
+
+        let bodyless_method = req.method == Method::GET || req.method == Method::HEAD;
+        if bodyless_method
+            && matches!(node.accepts_type, Accepts::None)
+            && req.get_body_bytes().is_some()
+        {
+            match self.get_body_policy {
+                GetBodyPolicy::Ignore => req.discard_body(),
+                GetBodyPolicy::Reject => {
+                    let message = format!("{} {} does not accept a body", req.method, req.path());
+                    let error = RequestError::with_message(ErrorType::UnexpectedBody, &message);
+                    let response = self.error_response(error, &req);
+                    return (req, response);
+                }
+            }
+        }
+
+        let content_type_opt = node.accepts_type.get_matching(&req);
+        // If we have a GET or don't have a body ignore this
+        if req.get_body_bytes().is_some() {
+            // Matches if request Content-Type is compatible with the route
+            if let Some(content_type) = content_type_opt {
+                req.set_content_type(content_type);
+            } else {
+                let response = self.unsupported_media_type(&req, &node.accepts_type);
+                return (req, response);
+            }
+        }
+        for middleware in node.middlewares.iter() {
+            req = middleware(req);
+        }
+        // The handler has found a valid route
+        let mut response =
+            function(context.clone(), req.clone()).with_default_headers(&node.default_headers);
+        if let Some(deprecation) = node.deprecation.as_ref() {
+            response = deprecation.apply(response);
+        }
+        req.retain_body_for_logging(node.body_logging);
+        (req, response)
+    }
+}
+
+/// Paths of the named routes, to build their URLs. Every request that reaches a route carries them
+/// in its extensions, for Request::url_for
+#[derive(Clone, Debug, Default)]
+pub struct RouteUrls(Arc<HashMap<String, String>>);
+
+impl RouteUrls {
+    /// Path of the named route with its path variables replaced by the given values, which are
+    /// percent-encoded so they can't change the path. Catch-all variables keep their slashes.
+    /// Fails if there is no route with the name or a variable is missing
+    pub fn url_for(
+        &self,
+        name: &str,
+        path_variables: &[(&str, &str)],
+    ) -> Result<String, ServerError> {
+        let path = self
+            .0
+            .get(name)
+            .ok_or_else(|| ServerError::from(format!("No route named {}", name)))?;
+
+        let segments = path
+            .split("/")
+            .map(|segment| {
+                let (variable, keep) = match segment.strip_prefix(":") {
+                    Some(variable) => (variable, "-._~"),
+                    None => match segment.strip_prefix("*") {
+                        Some(variable) => (variable, "-._~/"),
+                        None => return Ok(segment.to_string()),
+                    },
+                };
+                path_variables
+                    .iter()
+                    .find(|(key, _)| *key == variable)
+                    .map(|(_, value)| util::percent_encode(value, keep))
+                    .ok_or_else(|| {
+                        ServerError::from(format!(
+                            "Missing path variable {} for route {}",
+                            variable, name
+                        ))
+                    })
+            })
+            .collect::<Result<Vec<String>, ServerError>>()?;
+        Ok(segments.join("/"))
+    }
+}
+
+fn allow_header(methods: &[Method]) -> String {
+    methods
+        .iter()
+        .map(|method| method.as_str())
+        .collect::<Vec<&str>>()
+        .join(", ")
+}
+
+/// Capability headers of a path, so clients and preflight checks can discover what they can send
+#[derive(Default)]
+struct Capabilities {
+    allowed_methods: Vec<Method>,
+    accept_post: Vec<String>,
+    accept_patch: Vec<String>,
+}
+
+impl Capabilities {
+    /// Adds the Allow header and, for paths with POST or PATCH routes that accept a body, the
+    /// Accept-Post and Accept-Patch (RFC 5789) ones
+    fn apply(&self, mut response: Response) -> Response {
+        response = response.add_header(ALLOW, &allow_header(&self.allowed_methods));
+        if !self.accept_post.is_empty() {
+            response = response.add_header(ACCEPT_POST, &self.accept_post.join(", "));
+        }
+        if !self.accept_patch.is_empty() {
+            response = response.add_header(ACCEPT_PATCH, &self.accept_patch.join(", "));
+        }
+        response
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use hyper::{
+        header::{ACCEPT, CACHE_CONTROL},
+        Uri,
+    };
+    use serde::Deserialize;
+
+    use chrono::TimeZone;
+
+    use crate::response::ResponseBody;
+    use crate::security::security_configuration::AuthResult;
+
+    use super::*;
+
+    #[derive(Default)]
+    struct ContextTest {}
+
+    #[test]
+    fn router_test() {
+        let mut router = InternalRouter::new();
+        let route = Route {
+            method: Method::GET,
+            path: "/hello".to_string(),
+            name: None,
+            handler: Arc::new(|_: Arc<ContextTest>, _: Request| {
+                Response::new(StatusCode::OK).json("Hello world")
+            }),
+            accepts_type: Accepts::None,
+            deprecation: None,
+            stream_body: false,
+            max_body_size: None,
+            default_headers: HeaderMap::new(),
+            body_logging: BodyLogging::Full,
+            public: false,
+            extensions: Extensions::new(),
+            middlewares: Vec::new(),
+            router_middlewares: 0,
+            invalid_default_headers: Vec::new(),
+            default_accepts_type: false,
+        };
+        if let Err(e) = router.add_route(route) {
+            panic!("{}", e)
+        }
+        let route = Route {
+            method: Method::POST,
+            path: "/hello/other".to_string(),
+            name: None,
+            handler: Arc::new(|_: Arc<ContextTest>, _: Request| {
+                Response::new(StatusCode::OK).json("Hello world")
+            }),
+            accepts_type: Accepts::One(ContentType::Json),
+            deprecation: None,
+            stream_body: false,
+            max_body_size: None,
+            default_headers: HeaderMap::new(),
+            body_logging: BodyLogging::Full,
+            public: false,
+            extensions: Extensions::new(),
+            middlewares: Vec::new(),
+            router_middlewares: 0,
+            invalid_default_headers: Vec::new(),
+            default_accepts_type: false,
+        };
+        if let Err(e) = router.add_route(route) {
+            panic!("{}", e)
+        }
+        let route = Route {
+            method: Method::GET,
+            path: "/hi/other".to_string(),
+            name: None,
+            handler: Arc::new(|_: Arc<ContextTest>, _: Request| {
+                Response::new(StatusCode::OK).json("Hello world")
+            }),
+            accepts_type: Accepts::None,
+            deprecation: None,
+            stream_body: false,
+            max_body_size: None,
+            default_headers: HeaderMap::new(),
+            body_logging: BodyLogging::Full,
+            public: false,
+            extensions: Extensions::new(),
+            middlewares: Vec::new(),
+            router_middlewares: 0,
+            invalid_default_headers: Vec::new(),
+            default_accepts_type: false,
+        };
+        if let Err(e) = router.add_route(route) {
+            panic!("{}", e)
+        }
+
+        let tabs = 0;
         for (key, value) in &router.routes {
             println!("{}", key);
             print(value, tabs + 1);
@@ -452,6 +1377,1042 @@ mod tests {
         let _ = router.run(req4, context.clone());
     }
 
+    #[test]
+    fn get_body_policy_test() {
+        let mut router =
+            InternalRouter::from(Router::<ContextTest>::new().get("/search", |_, req| {
+                assert!(req.get_body_bytes().is_none());
+                Response::new(StatusCode::OK)
+            }))
+            .unwrap();
+        let context = Arc::new(ContextTest {});
+        let get_with_body = || {
+            Request::new(
+                Method::GET,
+                Uri::from_static("/search"),
+                r#"{"query": "rust"}"#.to_string(),
+                HeaderMap::new(),
+                AuthResult::Allowed,
+            )
+        };
+
+        let (_, response) = router.run(get_with_body(), context.clone());
+        assert_eq!(response.status, StatusCode::OK);
+
+        router.set_get_body_policy(GetBodyPolicy::Reject);
+        let (_, response) = router.run(get_with_body(), context.clone());
+        assert_eq!(response.status, StatusCode::BAD_REQUEST);
+
+        let (_, response) = router.run(test_request(Method::GET, "/search"), context);
+        assert_eq!(response.status, StatusCode::OK);
+    }
+
+    #[test]
+    fn trace_rejected_test() {
+        let mut router = InternalRouter::from(
+            Router::<ContextTest>::new()
+                .get("/echo", |_, _| Response::new(StatusCode::OK))
+                .add_route(
+                    Method::TRACE,
+                    "/echo",
+                    |_, _| Response::new(StatusCode::OK),
+                    Accepts::None,
+                ),
+        )
+        .unwrap();
+        let context = Arc::new(ContextTest {});
+
+        let (_, response) = router.run(test_request(Method::TRACE, "/echo"), context.clone());
+        assert_eq!(response.status, StatusCode::NOT_IMPLEMENTED);
+        let (_, response) = router.run(test_request(Method::CONNECT, "/echo"), context.clone());
+        assert_eq!(response.status, StatusCode::NOT_IMPLEMENTED);
+
+        router.set_allow_trace_and_connect(true);
+        let (_, response) = router.run(test_request(Method::TRACE, "/echo"), context);
+        assert_eq!(response.status, StatusCode::OK);
+    }
+
+    #[test]
+    fn deprecated_route_test() {
+        let router = InternalRouter::from(
+            Router::<ContextTest>::base_path("/api")
+                .get("/current", |_, _| Response::new(StatusCode::OK))
+                .get("/old", |_, _| Response::new(StatusCode::OK))
+                .sunset(Utc.with_ymd_and_hms(2030, 1, 15, 0, 0, 0).unwrap()),
+        )
+        .unwrap();
+        let context = Arc::new(ContextTest {});
+
+        let (_, response) = router.run(test_request(Method::GET, "/api/old"), context.clone());
+        let headers = response.get_headers();
+        assert_eq!(headers.get("deprecation").unwrap(), "true");
+        assert_eq!(
+            headers.get("sunset").unwrap(),
+            "Tue, 15 Jan 2030 00:00:00 GMT"
+        );
+
+        let (_, response) = router.run(test_request(Method::GET, "/api/current"), context);
+        assert!(response.get_headers().get("deprecation").is_none());
+    }
+
+    #[test]
+    fn default_response_headers_test() {
+        let router = InternalRouter::from(
+            Router::<ContextTest>::new()
+                .get("/products", |_, _| Response::new(StatusCode::OK))
+                .default_response_header(CACHE_CONTROL, "public, max-age=300")
+                .get("/products/:id", |_, _| {
+                    Response::new(StatusCode::OK).add_header(CACHE_CONTROL, "no-store")
+                })
+                .default_response_header(CACHE_CONTROL, "public, max-age=300"),
+        )
+        .unwrap();
+        let context = Arc::new(ContextTest {});
+
+        // Invalid values are reported when the router is built instead of panicking
+        let invalid_router = Router::<ContextTest>::new()
+            .get("/products", |_, _| Response::new(StatusCode::OK))
+            .default_response_header(CACHE_CONTROL, "max-age=300\r\nX-Injected: 1");
+        assert!(InternalRouter::from(invalid_router).is_err());
+
+        let (_, response) = router.run(test_request(Method::GET, "/products"), context.clone());
+        assert_eq!(
+            response.get_headers().get(CACHE_CONTROL).unwrap(),
+            "public, max-age=300"
+        );
+
+        let (_, response) = router.run(test_request(Method::GET, "/products/1"), context);
+        assert_eq!(
+            response.get_headers().get(CACHE_CONTROL).unwrap(),
+            "no-store"
+        );
+    }
+
+    #[test]
+    fn default_accepts_test() {
+        let mut router = Router::<ContextTest>::new()
+            .post("/users", |_, _| Response::new(StatusCode::CREATED))
+            .add_route(
+                Method::POST,
+                "/imports",
+                |_, _| Response::new(StatusCode::CREATED),
+                Accepts::One(ContentType::Json),
+            );
+        router.apply_default_accepts(&Accepts::Multiple(vec![
+            ContentType::Json,
+            ContentType::FormUrlEncoded,
+        ]));
+        let router = InternalRouter::from(router).unwrap();
+        let context = Arc::new(ContextTest {});
+
+        let form_request = |path: &'static str| {
+            let mut headers = HeaderMap::new();
+            headers.insert(
+                CONTENT_TYPE,
+                HeaderValue::from_static("application/x-www-form-urlencoded"),
+            );
+            Request::new(
+                Method::POST,
+                Uri::from_static(path),
+                "name=alice".to_string(),
+                headers,
+                AuthResult::Allowed,
+            )
+        };
+
+        let (_, response) = router.run(form_request("/users"), context.clone());
+        assert_eq!(response.status, StatusCode::CREATED);
+
+        let (_, response) = router.run(form_request("/imports"), context);
+        assert_eq!(response.status, StatusCode::UNSUPPORTED_MEDIA_TYPE);
+    }
+
+    #[test]
+    fn unsupported_media_type_test() {
+        let router = InternalRouter::from(
+            Router::<ContextTest>::new()
+                .patch("/users/:id", |_, _| Response::new(StatusCode::OK))
+                .add_route(
+                    Method::POST,
+                    "/imports",
+                    |_, _| Response::new(StatusCode::CREATED),
+                    Accepts::Multiple(vec![ContentType::Json, ContentType::Multipart]),
+                ),
+        )
+        .unwrap();
+        let context = Arc::new(ContextTest {});
+
+        let xml_request = |method: Method, path: &'static str| {
+            let mut headers = HeaderMap::new();
+            headers.insert(CONTENT_TYPE, HeaderValue::from_static("application/xml"));
+            Request::new(
+                method,
+                Uri::from_static(path),
+                "<user/>".to_string(),
+                headers,
+                AuthResult::Allowed,
+            )
+        };
+
+        let (_, response) = router.run(xml_request(Method::PATCH, "/users/1"), context.clone());
+        assert_eq!(response.status, StatusCode::UNSUPPORTED_MEDIA_TYPE);
+        assert_eq!(
+            response.get_headers().get("accept-patch").unwrap(),
+            "application/json, application/merge-patch+json, application/json-patch+json"
+        );
+
+        let (_, response) = router.run(xml_request(Method::POST, "/imports"), context);
+        assert_eq!(
+            response.get_headers().get("accept-post").unwrap(),
+            "application/json, multipart/form-data"
+        );
+        let body = match response.get_body() {
+            Some(ResponseBody::Full(bytes)) => serde_json::from_slice::<serde_json::Value>(bytes),
+            _ => panic!("Expected a full body"),
+        }
+        .unwrap();
+        assert_eq!(
+            body["supported_media_types"],
+            serde_json::json!(["application/json", "multipart/form-data"])
+        );
+    }
+
+    #[test]
+    fn single_flight_test() {
+        let router = InternalRouter::from(
+            Router::<ContextTest>::new()
+                .get("/reports/:id", |_, req| {
+                    let id = req.get_path_variables().get("id").unwrap().clone();
+                    Response::new(StatusCode::OK).json(id)
+                })
+                .single_flight(),
+        )
+        .unwrap();
+
+        // Calls that don't overlap run the handler each time, concurrent ones are covered in
+        // single_flight
+        for (path, id) in [("/reports/7", "\"7\""), ("/reports/8", "\"8\"")] {
+            let (_, response) =
+                router.run(test_request(Method::GET, path), Arc::new(ContextTest {}));
+            assert_eq!(response.status, StatusCode::OK);
+            assert!(matches!(response.get_body(), Some(ResponseBody::Full(body)) if body == id));
+        }
+    }
+
+    struct BillingService {
+        currency: &'static str,
+    }
+
+    #[test]
+    fn router_state_test() {
+        let billing_router = Router::<ContextTest>::base_path("/billing")
+            .get("/currency", |_, req| {
+                let billing = req.state::<BillingService>().unwrap();
+                Response::new(StatusCode::OK).json(billing.currency)
+            })
+            .with_state(BillingService { currency: "EUR" });
+        let router = InternalRouter::from(
+            Router::<ContextTest>::new()
+                .get("/users", |_, req| {
+                    assert!(req.state::<BillingService>().is_none());
+                    Response::new(StatusCode::OK)
+                })
+                .add_router(billing_router),
+        )
+        .unwrap();
+        let context = Arc::new(ContextTest {});
+
+        let (req, response) = router.run(
+            test_request(Method::GET, "/billing/currency"),
+            context.clone(),
+        );
+        assert_eq!(response.status, StatusCode::OK);
+        assert_eq!(req.state::<BillingService>().unwrap().currency, "EUR");
+
+        let (req, response) = router.run(test_request(Method::GET, "/users"), context);
+        assert_eq!(response.status, StatusCode::OK);
+        assert!(req.state::<BillingService>().is_none());
+    }
+
+    #[test]
+    fn body_logging_test() {
+        let router = InternalRouter::from(
+            Router::<ContextTest>::new()
+                .post("/documents", |_, req| {
+                    assert_eq!(req.get_body_raw(), Some(r#"{"title": "Report"}"#));
+                    Response::new(StatusCode::CREATED)
+                })
+                .body_logging(BodyLogging::Prefix(9))
+                .put("/documents", |_, _| Response::new(StatusCode::OK))
+                .body_logging(BodyLogging::Skip),
+        )
+        .unwrap();
+        let context = Arc::new(ContextTest {});
+
+        let (req, response) = router.run(
+            json_request("/documents", r#"{"title": "Report"}"#),
+            context.clone(),
+        );
+        assert_eq!(response.status, StatusCode::CREATED);
+        assert_eq!(req.get_body_for_logging().unwrap(), r#"{"title":"#);
+
+        let mut put_request = json_request("/documents", r#"{"title": "Report"}"#);
+        put_request.method = Method::PUT;
+        let (req, _) = router.run(put_request, context);
+        assert!(req.get_body_for_logging().is_none());
+    }
+
+    #[test]
+    fn well_known_route_test() {
+        let router = InternalRouter::from(
+            Router::<ContextTest>::new()
+                .get("/:page", |_, _| Response::new(StatusCode::OK))
+                .well_known("openid-configuration", |_, _| {
+                    Response::new(StatusCode::OK).json("configuration")
+                }),
+        )
+        .unwrap();
+
+        let (_, path_variables) = router
+            .find(&Method::GET, "/.well-known/openid-configuration")
+            .unwrap();
+        assert!(path_variables.is_empty());
+
+        let (_, path_variables) = router.find(&Method::GET, "/.well-known").unwrap();
+        assert_eq!(path_variables.get("page").unwrap(), ".well-known");
+    }
+
+    #[test]
+    fn route_body_size_limit_test() {
+        let router = InternalRouter::from(
+            Router::<ContextTest>::new()
+                .post("/json", |_, _| Response::new(StatusCode::OK))
+                .max_body_size(1024 * 1024)
+                .post("/upload", |_, _| Response::new(StatusCode::OK))
+                .max_body_size(50 * 1024 * 1024)
+                .post("/unlimited", |_, _| Response::new(StatusCode::OK)),
+        )
+        .unwrap();
+
+        assert_eq!(
+            router.max_body_size(&Method::POST, "/json"),
+            Some(1024 * 1024)
+        );
+        assert_eq!(
+            router.max_body_size(&Method::POST, "/upload"),
+            Some(50 * 1024 * 1024)
+        );
+        assert_eq!(router.max_body_size(&Method::POST, "/unlimited"), None);
+    }
+
+    #[test]
+    fn default_body_size_limit_test() {
+        let mut router = InternalRouter::from(
+            Router::<ContextTest>::new()
+                .post("/json", |_, _| Response::new(StatusCode::OK))
+                .post("/upload", |_, _| Response::new(StatusCode::OK))
+                .max_body_size(50 * 1024 * 1024),
+        )
+        .unwrap();
+        router.set_default_max_body_size(1024);
+
+        assert_eq!(router.max_body_size(&Method::POST, "/json"), Some(1024));
+        assert_eq!(
+            router.max_body_size(&Method::POST, "/upload"),
+            Some(50 * 1024 * 1024)
+        );
+        assert_eq!(router.max_body_size(&Method::POST, "/missing"), Some(1024));
+    }
+
+    #[test]
+    fn catch_all_route_test() {
+        let router = InternalRouter::from(
+            Router::<ContextTest>::new()
+                .get("/files/*path", |_, _| Response::new(StatusCode::OK))
+                .get("/files/:id/metadata", |_, _| Response::new(StatusCode::OK)),
+        )
+        .unwrap();
+
+        let (_, path_variables) = router
+            .find(&Method::GET, "/files/docs/2024/report.pdf")
+            .unwrap();
+        assert_eq!(path_variables.get("path").unwrap(), "docs/2024/report.pdf");
+
+        let (_, path_variables) = router.find(&Method::GET, "/files/report.pdf").unwrap();
+        assert_eq!(path_variables.get("path").unwrap(), "report.pdf");
+
+        let (_, path_variables) = router.find(&Method::GET, "/files/1/metadata").unwrap();
+        assert_eq!(path_variables.get("id").unwrap(), "1");
+        assert!(!path_variables.contains_key("path"));
+
+        assert!(router.find(&Method::GET, "/files").is_none());
+    }
+
+    #[test]
+    fn method_catch_all_test() {
+        let router = InternalRouter::from(
+            Router::<ContextTest>::new()
+                .get("/", |_, _| Response::new(StatusCode::OK))
+                .get("/users/:id", |_, _| Response::new(StatusCode::OK))
+                .catch_all(Method::GET, |_, req| {
+                    Response::new(StatusCode::IM_A_TEAPOT)
+                        .body(req.get_path_variables().get("path").unwrap().clone())
+                }),
+        )
+        .unwrap();
+        let context = Arc::new(ContextTest {});
+
+        let (_, response) = router.run(test_request(Method::GET, "/users/1"), context.clone());
+        assert_eq!(response.get_status(), StatusCode::OK);
+        let (_, response) = router.run(test_request(Method::GET, "/"), context.clone());
+        assert_eq!(response.get_status(), StatusCode::OK);
+
+        let (_, response) =
+            router.run(test_request(Method::GET, "/users/1/posts"), context.clone());
+        assert_eq!(response.get_status(), StatusCode::IM_A_TEAPOT);
+        assert!(
+            matches!(response.get_body(), Some(ResponseBody::Full(body)) if body == "users/1/posts")
+        );
+
+        // Only requests of the method reach the catch-all
+        let (_, response) = router.run(test_request(Method::DELETE, "/users/1"), context);
+        assert_eq!(response.get_status(), StatusCode::METHOD_NOT_ALLOWED);
+    }
+
+    #[test]
+    fn router_middleware_test() {
+        fn trace(mut req: Request, step: &str) -> Request {
+            let steps = match req.extensions().get::<String>() {
+                Some(steps) => format!("{} {}", steps, step),
+                None => step.to_string(),
+            };
+            req.extensions_mut().insert(steps);
+            req
+        }
+
+        let users = Router::<ContextTest>::base_path("/users")
+            .get("/:id", |_, req| {
+                Response::new(StatusCode::OK)
+                    .body(req.extensions().get::<String>().unwrap().clone())
+            })
+            .with_middleware(|req| trace(req, "users"));
+        let router = InternalRouter::from(
+            Router::<ContextTest>::base_path("/api")
+                .with_middleware(|req| trace(req, "api"))
+                .add_router(users)
+                .with_middleware(|req| trace(req, "audit"))
+                .get("/health", |_, req| {
+                    Response::new(StatusCode::OK)
+                        .body(req.extensions().get::<String>().unwrap().clone())
+                }),
+        )
+        .unwrap();
+        let context = Arc::new(ContextTest {});
+
+        // Parent middlewares run before the ones of the nested router, even if added after it
+        let (_, response) = router.run(test_request(Method::GET, "/api/users/1"), context.clone());
+        assert!(
+            matches!(response.get_body(), Some(ResponseBody::Full(body)) if body == "api audit users")
+        );
+
+        let (_, response) = router.run(test_request(Method::GET, "/api/health"), context);
+        assert!(
+            matches!(response.get_body(), Some(ResponseBody::Full(body)) if body == "api audit")
+        );
+    }
+
+    #[test]
+    fn named_routes_test() {
+        let users = Router::<ContextTest>::base_path("/users")
+            .add_named_route(
+                "user_detail",
+                Method::GET,
+                "/:id",
+                |_, req| {
+                    let url = req.url_for("user_posts", &[("id", "5"), ("post", "7")]);
+                    Response::new(StatusCode::OK).body(url.unwrap())
+                },
+                Accepts::None,
+            )
+            .add_named_route(
+                "user_posts",
+                Method::GET,
+                "/:id/posts/:post",
+                |_, _| Response::new(StatusCode::OK),
+                Accepts::None,
+            )
+            .add_named_route(
+                "user_files",
+                Method::GET,
+                "/:id/files/*path",
+                |_, _| Response::new(StatusCode::OK),
+                Accepts::None,
+            );
+        let router = InternalRouter::from(Router::base_path("/api").add_router(users)).unwrap();
+        let urls = &router.route_urls;
+
+        assert_eq!(
+            urls.url_for("user_detail", &[("id", "5")]).unwrap(),
+            "/api/users/5"
+        );
+        assert!(urls.url_for("user_posts", &[("id", "5")]).is_err());
+        assert!(urls.url_for("user_list", &[]).is_err());
+
+        // Values are encoded so they stay in their segment, and catch-alls keep their slashes
+        assert_eq!(
+            urls.url_for("user_posts", &[("id", "a/b?c#d"), ("post", "hello world")])
+                .unwrap(),
+            "/api/users/a%2Fb%3Fc%23d/posts/hello%20world"
+        );
+        assert_eq!(
+            urls.url_for("user_files", &[("id", "5"), ("path", "docs/q1 report.pdf")])
+                .unwrap(),
+            "/api/users/5/files/docs/q1%20report.pdf"
+        );
+
+        let (_, response) = router.run(
+            test_request(Method::GET, "/api/users/1"),
+            Arc::new(ContextTest {}),
+        );
+        assert!(
+            matches!(response.get_body(), Some(ResponseBody::Full(body)) if body == "/api/users/5/posts/7")
+        );
+
+        let duplicated = InternalRouter::from(
+            Router::<ContextTest>::new()
+                .add_named_route(
+                    "home",
+                    Method::GET,
+                    "/",
+                    |_, _| Response::new(StatusCode::OK),
+                    Accepts::None,
+                )
+                .add_named_route(
+                    "home",
+                    Method::GET,
+                    "/home",
+                    |_, _| Response::new(StatusCode::OK),
+                    Accepts::None,
+                ),
+        );
+        assert!(duplicated.is_err());
+    }
+
+    #[test]
+    fn closure_handlers_test() {
+        let mut router = Router::<ContextTest>::new();
+        for (version, status) in [("v1", StatusCode::GONE), ("v2", StatusCode::OK)] {
+            let message = format!("API {}", version);
+            router = router.get(&format!("/{}/status", version), move |_, _| {
+                Response::new(status).body(message.clone())
+            });
+        }
+        let router = InternalRouter::from(router).unwrap();
+        let context = Arc::new(ContextTest {});
+
+        let (_, response) = router.run(test_request(Method::GET, "/v1/status"), context.clone());
+        assert_eq!(response.get_status(), StatusCode::GONE);
+        let (_, response) = router.run(test_request(Method::GET, "/v2/status"), context);
+        assert_eq!(response.get_status(), StatusCode::OK);
+        assert!(matches!(response.get_body(), Some(ResponseBody::Full(body)) if body == "API v2"));
+    }
+
+    #[test]
+    fn feature_router_test() {
+        let router = crate::feature_router!(
+            Router::<ContextTest>::new().get("/health", |_, _| Response::new(StatusCode::OK)),
+            "xml",
+            Router::base_path("/xml").get("/users", |_, _| Response::new(StatusCode::OK)),
+        );
+        let router = InternalRouter::from(router).unwrap();
+
+        assert!(router.has_route("/health"));
+        assert_eq!(router.has_route("/xml/users"), cfg!(feature = "xml"));
+    }
+
+    #[test]
+    fn fallible_route_test() {
+        fn find_user(_: Arc<ContextTest>, req: Request) -> Result<Response, RequestError> {
+            let id: u32 = req
+                .get_path_variables()
+                .get("id")
+                .unwrap()
+                .parse()
+                .map_err(|_| RequestError::with_message(ErrorType::NotFound, "User not found"))?;
+            Ok(Response::new(StatusCode::OK).json(id))
+        }
+
+        let router = InternalRouter::from(
+            Router::<ContextTest>::new()
+                .add_fallible_route(Method::GET, "/users/:id", find_user, Accepts::None)
+                .get("/health", |_, _| Response::new(StatusCode::OK)),
+        )
+        .unwrap();
+        let context = Arc::new(ContextTest {});
+
+        let (_, response) = router.run(test_request(Method::GET, "/users/1"), context.clone());
+        assert_eq!(response.get_status(), StatusCode::OK);
+
+        let (_, response) = router.run(test_request(Method::GET, "/users/alice"), context.clone());
+        assert_eq!(response.get_status(), StatusCode::NOT_FOUND);
+
+        let (_, response) = router.run(test_request(Method::GET, "/health"), context);
+        assert_eq!(response.get_status(), StatusCode::OK);
+    }
+
+    #[test]
+    fn error_into_response_test() {
+        enum UserError {
+            NotFound,
+            Conflict,
+            Database,
+        }
+
+        impl IntoResponse for UserError {
+            fn into_response(self) -> Response {
+                match self {
+                    UserError::NotFound => Response::error(StatusCode::NOT_FOUND, "User not found"),
+                    UserError::Conflict => Response::error(StatusCode::CONFLICT, "User exists"),
+                    UserError::Database => {
+                        Response::error(StatusCode::INTERNAL_SERVER_ERROR, "Database error")
+                    }
+                }
+            }
+        }
+
+        fn create_user(_: Arc<ContextTest>, req: Request) -> Result<Response, UserError> {
+            match req.get_path_variables().get("name").unwrap().as_str() {
+                "missing" => Err(UserError::NotFound),
+                "existing" => Err(UserError::Conflict),
+                "broken" => Err(UserError::Database),
+                _ => Ok(Response::new(StatusCode::CREATED)),
+            }
+        }
+
+        let router = InternalRouter::from(Router::<ContextTest>::new().add_fallible_route(
+            Method::POST,
+            "/users/:name",
+            create_user,
+            Accepts::None,
+        ))
+        .unwrap();
+        let context = Arc::new(ContextTest {});
+
+        for (path, status) in [
+            ("/users/alice", StatusCode::CREATED),
+            ("/users/missing", StatusCode::NOT_FOUND),
+            ("/users/existing", StatusCode::CONFLICT),
+            ("/users/broken", StatusCode::INTERNAL_SERVER_ERROR),
+        ] {
+            let (_, response) = router.run(test_request(Method::POST, path), context.clone());
+            assert_eq!(response.get_status(), status);
+        }
+    }
+
+    #[test]
+    fn malformed_catch_all_route_test() {
+        let not_last = InternalRouter::from(
+            Router::<ContextTest>::new()
+                .get("/files/*path/edit", |_, _| Response::new(StatusCode::OK)),
+        );
+        assert!(not_last.is_err());
+
+        let without_name = InternalRouter::from(
+            Router::<ContextTest>::new().get("/files/*", |_, _| Response::new(StatusCode::OK)),
+        );
+        assert!(without_name.is_err());
+    }
+
+    #[test]
+    fn method_not_allowed_test() {
+        let router = InternalRouter::from(
+            Router::<ContextTest>::new()
+                .get("/users/:id", |_, _| Response::new(StatusCode::OK))
+                .delete("/users/:id", |_, _| Response::new(StatusCode::OK))
+                .post("/users", |_, _| Response::new(StatusCode::OK)),
+        )
+        .unwrap();
+        let context = Arc::new(ContextTest {});
+
+        let (_, response) = router.run(test_request(Method::PUT, "/users/1"), context.clone());
+        assert_eq!(response.get_status(), StatusCode::METHOD_NOT_ALLOWED);
+        assert_eq!(
+            response.get_headers().get(ALLOW).unwrap(),
+            "DELETE, GET, OPTIONS"
+        );
+
+        let (_, response) = router.run(test_request(Method::PUT, "/posts/1"), context);
+        assert_eq!(response.get_status(), StatusCode::NOT_FOUND);
+        assert!(response.get_headers().get(ALLOW).is_none());
+    }
+
+    #[test]
+    fn automatic_options_test() {
+        let router = InternalRouter::from(
+            Router::<ContextTest>::new()
+                .get("/users", |_, _| Response::new(StatusCode::OK))
+                .post("/users", |_, _| Response::new(StatusCode::OK))
+                .get("/posts", |_, _| Response::new(StatusCode::OK))
+                .add_route(
+                    Method::OPTIONS,
+                    "/posts",
+                    |_, _| Response::new(StatusCode::OK),
+                    Accepts::None,
+                ),
+        )
+        .unwrap();
+        let context = Arc::new(ContextTest {});
+
+        let (_, response) = router.run(test_request(Method::OPTIONS, "/users"), context.clone());
+        assert_eq!(response.get_status(), StatusCode::NO_CONTENT);
+        assert_eq!(
+            response.get_headers().get(ALLOW).unwrap(),
+            "GET, OPTIONS, POST"
+        );
+
+        let (_, response) = router.run(test_request(Method::OPTIONS, "/posts"), context.clone());
+        assert_eq!(response.get_status(), StatusCode::OK);
+        assert!(response.get_headers().get(ALLOW).is_none());
+
+        let (_, response) = router.run(test_request(Method::OPTIONS, "/comments"), context);
+        assert_eq!(response.get_status(), StatusCode::NOT_FOUND);
+    }
+
+    #[test]
+    fn capability_headers_test() {
+        let router = InternalRouter::from(
+            Router::<ContextTest>::new()
+                .get("/documents", |_, _| Response::new(StatusCode::OK))
+                .add_route(
+                    Method::POST,
+                    "/documents",
+                    |_, _| Response::new(StatusCode::CREATED),
+                    Accepts::Multiple(vec![ContentType::Json, ContentType::FormUrlEncoded]),
+                )
+                .patch("/documents", |_, _| Response::new(StatusCode::OK)),
+        )
+        .unwrap();
+        let context = Arc::new(ContextTest {});
+
+        let (_, options) = router.run(test_request(Method::OPTIONS, "/documents"), context.clone());
+        let (_, not_allowed) = router.run(test_request(Method::DELETE, "/documents"), context);
+        assert_eq!(options.get_status(), StatusCode::NO_CONTENT);
+        assert_eq!(not_allowed.get_status(), StatusCode::METHOD_NOT_ALLOWED);
+        for response in [options, not_allowed] {
+            let headers = response.get_headers();
+            assert_eq!(headers.get(ALLOW).unwrap(), "GET, OPTIONS, PATCH, POST");
+            assert_eq!(
+                headers.get(ACCEPT_POST).unwrap(),
+                "application/json, application/x-www-form-urlencoded"
+            );
+            assert_eq!(
+                headers.get(ACCEPT_PATCH).unwrap(),
+                "application/json, application/merge-patch+json, application/json-patch+json"
+            );
+        }
+    }
+
+    #[test]
+    fn options_asterisk_test() {
+        let router = InternalRouter::from(
+            Router::<ContextTest>::new()
+                .get("/users", |_, _| Response::new(StatusCode::OK))
+                .delete("/users/:id", |_, _| Response::new(StatusCode::OK))
+                .add_route(
+                    Method::TRACE,
+                    "/debug",
+                    |_, _| Response::new(StatusCode::OK),
+                    Accepts::None,
+                ),
+        )
+        .unwrap();
+
+        let (_, response) =
+            router.run(test_request(Method::OPTIONS, "*"), Arc::new(ContextTest {}));
+        assert_eq!(response.get_status(), StatusCode::NO_CONTENT);
+        assert_eq!(
+            response.get_headers().get(ALLOW).unwrap(),
+            "DELETE, GET, OPTIONS"
+        );
+    }
+
+    #[test]
+    fn error_handler_test() {
+        let mut router = InternalRouter::from(
+            Router::<ContextTest>::new().get("/users", |_, _| Response::new(StatusCode::OK)),
+        )
+        .unwrap();
+        router.set_error_handler(Some(|error, request| {
+            let accepts_html = request
+                .headers
+                .get(ACCEPT)
+                .is_some_and(|accept| accept.as_bytes().starts_with(b"text/html"));
+            if accepts_html {
+                Response::new(StatusCode::NOT_FOUND).text(&format!("Not found: {}", error))
+            } else {
+                error.clone().into()
+            }
+        }));
+
+        let mut request = test_request(Method::GET, "/missing");
+        request
+            .headers
+            .insert(ACCEPT, HeaderValue::from_static("text/html"));
+        let (_, response) = router.run(request, Arc::new(ContextTest {}));
+        assert_eq!(response.get_status(), StatusCode::NOT_FOUND);
+        assert_eq!(
+            response.get_headers().get(CONTENT_TYPE).unwrap(),
+            "text/plain; charset=utf-8"
+        );
+
+        let (_, response) = router.run(
+            test_request(Method::GET, "/missing"),
+            Arc::new(ContextTest {}),
+        );
+        assert_eq!(
+            response.get_headers().get(CONTENT_TYPE).unwrap(),
+            "application/json"
+        );
+
+        // Headers added by the router are kept on the handler's responses
+        let (_, response) = router.run(
+            test_request(Method::POST, "/users"),
+            Arc::new(ContextTest {}),
+        );
+        assert_eq!(response.get_status(), StatusCode::METHOD_NOT_ALLOWED);
+        assert_eq!(response.get_headers().get(ALLOW).unwrap(), "GET, OPTIONS");
+    }
+
+    #[test]
+    fn error_format_test() {
+        let mut router =
+            InternalRouter::from(Router::<ContextTest>::new().get("/users/:id", |_, req| {
+                RequestError::default(ErrorType::NotFound).into_response_for(&req)
+            }))
+            .unwrap();
+        router.set_error_format(ErrorFormat::ProblemJson);
+
+        // Both the errors of the router and the ones of handlers use the format of the application
+        for path in ["/missing", "/users/1"] {
+            let (_, response) =
+                router.run(test_request(Method::GET, path), Arc::new(ContextTest {}));
+            assert_eq!(response.get_status(), StatusCode::NOT_FOUND);
+            assert_eq!(
+                response.get_headers().get(CONTENT_TYPE).unwrap(),
+                "application/problem+json"
+            );
+            let Some(ResponseBody::Full(body)) = response.get_body() else {
+                panic!("Expected a full body");
+            };
+            let body: serde_json::Value = serde_json::from_slice(body).unwrap();
+            assert_eq!(body["instance"], path);
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "templates")]
+    fn error_template_test() {
+        let mut router = InternalRouter::from(
+            Router::<ContextTest>::new().get("/users", |_, _| Response::new(StatusCode::OK)),
+        )
+        .unwrap();
+        // Rendered from the templates folder of the crate
+        router.set_error_templates(HashMap::from([(
+            StatusCode::NOT_FOUND,
+            "error.html".to_string(),
+        )]));
+
+        let mut request = test_request(Method::GET, "/missing");
+        request.headers.insert(
+            ACCEPT,
+            HeaderValue::from_static("text/html,application/xhtml+xml;q=0.9"),
+        );
+        let (_, response) = router.run(request, Arc::new(ContextTest {}));
+        assert_eq!(response.get_status(), StatusCode::NOT_FOUND);
+        assert_eq!(
+            response.get_headers().get(CONTENT_TYPE).unwrap(),
+            "text/html"
+        );
+        assert!(matches!(
+            response.get_body(),
+            Some(ResponseBody::Full(body)) if body.starts_with(b"<h1>404 Not Found</h1>")
+        ));
+
+        // API clients still get JSON
+        let (_, response) = router.run(
+            test_request(Method::GET, "/missing"),
+            Arc::new(ContextTest {}),
+        );
+        assert_eq!(response.get_status(), StatusCode::NOT_FOUND);
+        assert_eq!(
+            response.get_headers().get(CONTENT_TYPE).unwrap(),
+            "application/json"
+        );
+    }
+
+    #[test]
+    fn path_variables_test() {
+        let router = InternalRouter::from(
+            Router::<ContextTest>::base_path("/users")
+                .get("/:id", |_, _| Response::new(StatusCode::OK))
+                .get("/:user_id/posts/:post_id", |_, _| {
+                    Response::new(StatusCode::OK)
+                })
+                .get("/:user_id/files/*path", |_, _| {
+                    Response::new(StatusCode::OK)
+                }),
+        )
+        .unwrap();
+
+        let (_, path_variables) = router.find(&Method::GET, "/users/1").unwrap();
+        assert_eq!(path_variables.len(), 1);
+        assert_eq!(path_variables.get("id").unwrap(), "1");
+
+        let (_, path_variables) = router.find(&Method::GET, "/users/1/posts/2").unwrap();
+        assert_eq!(path_variables.len(), 2);
+        assert_eq!(path_variables.get("user_id").unwrap(), "1");
+        assert_eq!(path_variables.get("post_id").unwrap(), "2");
+
+        let (_, path_variables) = router
+            .find(&Method::GET, "/users/1/files/docs/report.pdf")
+            .unwrap();
+        assert_eq!(path_variables.len(), 2);
+        assert_eq!(path_variables.get("user_id").unwrap(), "1");
+        assert_eq!(path_variables.get("path").unwrap(), "docs/report.pdf");
+    }
+
+    #[test]
+    fn path_variable_names_test() {
+        let router = Router::<ContextTest>::base_path("/users/:user_id")
+            .get("/posts/:post_id", |_, _| Response::new(StatusCode::OK))
+            .get("/files/*path", |_, _| Response::new(StatusCode::OK))
+            .get("", |_, _| Response::new(StatusCode::OK));
+
+        assert_eq!(
+            router.routes[0].path_variable_names(),
+            vec!["user_id", "post_id"]
+        );
+        assert_eq!(
+            router.routes[1].path_variable_names(),
+            vec!["user_id", "path"]
+        );
+        assert_eq!(router.routes[2].path_variable_names(), vec!["user_id"]);
+    }
+
+    #[derive(Deserialize, Validate)]
+    struct NewUser {
+        #[validate(length(min = 1))]
+        username: String,
+    }
+
+    #[test]
+    fn post_json_test() {
+        let mut router = InternalRouter::from(
+            Router::<ContextTest>::new().post_json("/users", |_, user: NewUser, _| {
+                Response::new(StatusCode::CREATED).json(user.username)
+            }),
+        )
+        .unwrap();
+        let context = Arc::new(ContextTest {});
+
+        let (_, response) = router.run(
+            json_request("/users", r#"{"username": "alice"}"#),
+            context.clone(),
+        );
+        assert_eq!(response.get_status(), StatusCode::CREATED);
+
+        let (_, response) = router.run(
+            json_request("/users", r#"{"username": ""}"#),
+            context.clone(),
+        );
+        assert_eq!(response.get_status(), StatusCode::BAD_REQUEST);
+
+        let (_, response) = router.run(
+            json_request("/users", r#"{"name": "alice"}"#),
+            context.clone(),
+        );
+        assert_eq!(response.get_status(), StatusCode::BAD_REQUEST);
+
+        // Validation errors are answered like the other errors of the router
+        router.set_error_format(ErrorFormat::ProblemJson);
+        let (_, response) = router.run(
+            json_request("/users", r#"{"username": ""}"#),
+            context.clone(),
+        );
+        assert_eq!(
+            response.get_headers().get(CONTENT_TYPE).unwrap(),
+            "application/problem+json"
+        );
+        router.set_error_handler(Some(|_, _| {
+            Response::new(StatusCode::UNPROCESSABLE_ENTITY).text("Invalid user")
+        }));
+        let (_, response) = router.run(json_request("/users", r#"{"username": ""}"#), context);
+        assert_eq!(response.get_status(), StatusCode::UNPROCESSABLE_ENTITY);
+    }
+
+    fn json_request(path: &'static str, body: &str) -> Request {
+        let mut headers = HeaderMap::new();
+        headers.insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+        Request::new(
+            Method::POST,
+            Uri::from_static(path),
+            body.to_string(),
+            headers,
+            AuthResult::Allowed,
+        )
+    }
+
+    #[test]
+    fn route_precedence_test() {
+        fn handler(_: Arc<ContextTest>, _: Request) -> Response {
+            Response::new(StatusCode::OK)
+        }
+        let routers = [
+            Router::new()
+                .get("/users/:id", handler)
+                .get("/users/new", handler),
+            Router::new()
+                .get("/users/new", handler)
+                .get("/users/:id", handler),
+        ];
+
+        for router in routers {
+            let router = InternalRouter::from(router.get("/users/*rest", handler)).unwrap();
+
+            let (_, path_variables) = router.find(&Method::GET, "/users/new").unwrap();
+            assert!(path_variables.is_empty());
+
+            let (_, path_variables) = router.find(&Method::GET, "/users/1").unwrap();
+            assert_eq!(path_variables.get("id").unwrap(), "1");
+
+            let (_, path_variables) = router.find(&Method::GET, "/users/1/posts").unwrap();
+            assert_eq!(path_variables.get("rest").unwrap(), "1/posts");
+        }
+    }
+
+    #[test]
+    fn route_precedence_fallback_test() {
+        let router = InternalRouter::from(
+            Router::<ContextTest>::new()
+                .get("/users/new", |_, _| Response::new(StatusCode::OK))
+                .get("/users/:id/posts", |_, _| Response::new(StatusCode::OK)),
+        )
+        .unwrap();
+
+        let (_, path_variables) = router.find(&Method::GET, "/users/new/posts").unwrap();
+        assert_eq!(path_variables.get("id").unwrap(), "new");
+    }
+
+    fn test_request(method: Method, path: &'static str) -> Request {
+        Request::new(
+            method,
+            Uri::from_static(path),
+            String::new(),
+            HeaderMap::new(),
+            AuthResult::Allowed,
+        )
+    }
+
     fn print(map: &HashMap<String, RouterNode<ContextTest>>, tabs: usize) {
         for (key2, value2) in map {
             println!(
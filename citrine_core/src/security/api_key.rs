@@ -0,0 +1,143 @@
+use std::collections::HashMap;
+
+use hyper::header::HeaderName;
+use log::debug;
+
+use super::{credential_source::CredentialSource, security_configuration::AuthResult};
+
+const DEFAULT_API_KEY_HEADER: &str = "x-api-key";
+
+enum ApiKeys {
+    // Valid keys with the id of each one, which is the principal of its requests
+    Static(HashMap<String, String>),
+    // Returns the id of the key, or None if it isn't valid
+    Validator(fn(&str) -> Option<String>),
+}
+
+/// Authentication with a static API key sent in the X-API-Key header by default. Authenticated
+/// requests get the id of their key as AuthResult::CustomAuthenticated
+pub struct ApiKeyConfiguration {
+    credential_source: CredentialSource,
+    keys: ApiKeys,
+}
+
+impl ApiKeyConfiguration {
+    pub fn new() -> Self {
+        ApiKeyConfiguration {
+            credential_source: default_credential_source(),
+            keys: ApiKeys::Static(HashMap::new()),
+        }
+    }
+
+    /// Validates keys with a function that returns the id of the key, or None to deny the request.
+    /// Useful when keys are stored elsewhere, like in a database
+    pub fn with_validator(validator: fn(&str) -> Option<String>) -> Self {
+        ApiKeyConfiguration {
+            credential_source: default_credential_source(),
+            keys: ApiKeys::Validator(validator),
+        }
+    }
+
+    pub fn header(mut self, header: HeaderName) -> Self {
+        self.credential_source = CredentialSource::Header(header);
+        self
+    }
+
+    /// Reads the key from a cookie or a query parameter instead of a header
+    pub fn credential_source(mut self, credential_source: CredentialSource) -> Self {
+        self.credential_source = credential_source;
+        self
+    }
+
+    pub fn get_credential_source(&self) -> &CredentialSource {
+        &self.credential_source
+    }
+
+    /// Adds a valid key. The id identifies who the key belongs to without exposing the key itself
+    pub fn add_key(mut self, key_id: &str, key: &str) -> Self {
+        match &mut self.keys {
+            ApiKeys::Static(keys) => {
+                keys.insert(key.to_string(), key_id.to_string());
+            }
+            ApiKeys::Validator(_) => {
+                debug!(
+                    "API keys are validated with a function, ignoring key {}",
+                    key_id
+                )
+            }
+        }
+        self
+    }
+
+    pub fn authenticate(&self, key: &str) -> AuthResult {
+        debug!("Using API key Authenticator");
+        let key_id = match &self.keys {
+            ApiKeys::Static(keys) => keys
+                .iter()
+                .find(|(valid_key, _)| constant_time_eq(valid_key.as_bytes(), key.as_bytes()))
+                .map(|(_, key_id)| key_id.clone()),
+            ApiKeys::Validator(validator) => validator(key),
+        };
+        match key_id {
+            Some(key_id) => {
+                debug!("Request allowed for API key {}", key_id);
+                AuthResult::CustomAuthenticated(key_id)
+            }
+            None => {
+                debug!("Unknown API key. Denying request");
+                AuthResult::Denied
+            }
+        }
+    }
+}
+
+impl Default for ApiKeyConfiguration {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn default_credential_source() -> CredentialSource {
+    CredentialSource::Header(HeaderName::from_static(DEFAULT_API_KEY_HEADER))
+}
+
+/// Compares every byte so the time taken doesn't tell how much of a guessed key is right
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn static_keys_test() {
+        let configuration = ApiKeyConfiguration::new()
+            .add_key("billing-service", "b1ll1ng-s3cr3t")
+            .add_key("reports-service", "r3p0rts-s3cr3t");
+
+        assert_eq!(
+            configuration.authenticate("r3p0rts-s3cr3t"),
+            AuthResult::CustomAuthenticated("reports-service".to_string())
+        );
+        assert_eq!(configuration.authenticate("wrong"), AuthResult::Denied);
+    }
+
+    #[test]
+    fn custom_header_validator_test() {
+        let configuration = ApiKeyConfiguration::with_validator(|key| {
+            key.strip_prefix("tenant-").map(|tenant| tenant.to_string())
+        })
+        .header(HeaderName::from_static("x-tenant-key"));
+
+        assert_eq!(
+            configuration.get_credential_source(),
+            &CredentialSource::Header(HeaderName::from_static("x-tenant-key"))
+        );
+        assert_eq!(
+            configuration.authenticate("tenant-acme"),
+            AuthResult::CustomAuthenticated("acme".to_string())
+        );
+        assert_eq!(configuration.authenticate("acme"), AuthResult::Denied);
+    }
+}
@@ -0,0 +1,116 @@
+use base64::Engine;
+use log::debug;
+
+use super::{credential_source::CredentialSource, security_configuration::AuthResult};
+
+/// HTTP Basic authentication. Credentials are checked with a verifier function, so passwords can
+/// be stored hashed wherever the application keeps them. Authenticated requests get the username
+/// as AuthResult::CustomAuthenticated
+pub struct BasicConfiguration {
+    realm: String,
+    verifier: fn(&str, &str) -> bool,
+    credential_source: CredentialSource,
+}
+
+impl BasicConfiguration {
+    pub fn new(realm: &str, verifier: fn(&str, &str) -> bool) -> Self {
+        BasicConfiguration {
+            realm: realm.to_string(),
+            verifier,
+            credential_source: CredentialSource::default(),
+        }
+    }
+
+    /// Reads the credentials from another header, like one set by a proxy in front of the
+    /// server, instead of Authorization. The value still has to use the Basic scheme
+    pub fn credential_source(mut self, credential_source: CredentialSource) -> Self {
+        self.credential_source = credential_source;
+        self
+    }
+
+    pub fn get_credential_source(&self) -> &CredentialSource {
+        &self.credential_source
+    }
+
+    pub fn authenticate(&self, authorization: &str) -> AuthResult {
+        debug!("Using Basic Authenticator");
+        let credentials = match authorization.split_once(' ') {
+            Some((scheme, credentials)) if scheme.eq_ignore_ascii_case("basic") => {
+                credentials.trim()
+            }
+            _ => {
+                debug!("Authorization header is not Basic. Denying request");
+                return AuthResult::Denied;
+            }
+        };
+
+        let decoded = base64::prelude::BASE64_STANDARD
+            .decode(credentials)
+            .ok()
+            .and_then(|decoded| String::from_utf8(decoded).ok());
+        let (username, password) = match decoded.as_deref().and_then(|d| d.split_once(':')) {
+            Some(user_and_password) => user_and_password,
+            None => {
+                debug!("Malformed Basic credentials. Denying request");
+                return AuthResult::Denied;
+            }
+        };
+
+        if (self.verifier)(username, password) {
+            debug!("Request allowed for user {}", username);
+            AuthResult::CustomAuthenticated(username.to_string())
+        } else {
+            debug!("Invalid credentials for user {}. Denying request", username);
+            AuthResult::Denied
+        }
+    }
+
+    /// Value of the WWW-Authenticate header sent with denied requests, so browsers prompt for
+    /// credentials
+    pub fn challenge(&self) -> String {
+        format!(
+            "Basic realm=\"{}\", charset=\"UTF-8\"",
+            self.realm.replace('"', "\\\"")
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn basic(credentials: &str) -> String {
+        format!(
+            "Basic {}",
+            base64::prelude::BASE64_STANDARD.encode(credentials)
+        )
+    }
+
+    #[test]
+    fn basic_authentication_test() {
+        let configuration = BasicConfiguration::new("Admin panel", |username, password| {
+            username == "admin" && password == "s3cr3t:with:colons"
+        });
+
+        assert_eq!(
+            configuration.authenticate(&basic("admin:s3cr3t:with:colons")),
+            AuthResult::CustomAuthenticated("admin".to_string())
+        );
+        assert_eq!(
+            configuration.authenticate(&basic("admin:wrong")),
+            AuthResult::Denied
+        );
+        assert_eq!(
+            configuration.authenticate("Bearer some.jwt.token"),
+            AuthResult::Denied
+        );
+        assert_eq!(
+            configuration.authenticate("Basic not-base64!"),
+            AuthResult::Denied
+        );
+        assert_eq!(
+            configuration.challenge(),
+            "Basic realm=\"Admin panel\", charset=\"UTF-8\""
+        );
+    }
+}
@@ -0,0 +1,102 @@
+use std::fmt::Display;
+
+use hyper::header::{HeaderName, AUTHORIZATION, COOKIE};
+
+use crate::{cookie, request::RequestMetadata};
+
+/// Where an authenticator reads the credentials of a request from
+#[derive(Debug, Clone, PartialEq)]
+pub enum CredentialSource {
+    /// Whole value of a header, like Authorization or X-API-Key
+    Header(HeaderName),
+    /// Cookie with the name, like an HttpOnly session cookie set by a browser app
+    Cookie(String),
+    /// Query parameter with the name, like the access_token some webhooks send
+    QueryParam(String),
+}
+
+impl Default for CredentialSource {
+    fn default() -> Self {
+        CredentialSource::Header(AUTHORIZATION)
+    }
+}
+
+impl CredentialSource {
+    pub(crate) fn extract(&self, request: &RequestMetadata) -> Option<String> {
+        let credentials = match self {
+            CredentialSource::Header(name) => request
+                .headers
+                .get(name)
+                .and_then(|value| value.to_str().ok())
+                .map(|value| value.to_string()),
+            CredentialSource::Cookie(name) => request
+                .headers
+                .get_all(COOKIE)
+                .iter()
+                .filter_map(|header| header.to_str().ok())
+                .find_map(|header| cookie::find_cookie(header, name)),
+            CredentialSource::QueryParam(name) => request.uri.query().and_then(|query| {
+                serde_html_form::from_str::<Vec<(String, String)>>(query)
+                    .ok()?
+                    .into_iter()
+                    .find(|(key, _)| key == name)
+                    .map(|(_, value)| value)
+            }),
+        };
+        credentials.filter(|credentials| !credentials.is_empty())
+    }
+}
+
+impl Display for CredentialSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Header(name) => write!(f, "header {}", name),
+            Self::Cookie(name) => write!(f, "cookie {}", name),
+            Self::QueryParam(name) => write!(f, "query parameter {}", name),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use hyper::{header::HeaderValue, HeaderMap, Method, Uri};
+
+    use super::*;
+
+    #[test]
+    fn credential_source_test() {
+        let mut headers = HeaderMap::new();
+        headers.insert(AUTHORIZATION, HeaderValue::from_static("Bearer header.jwt"));
+        headers.insert("x-api-key", HeaderValue::from_static("s3cr3t"));
+        headers.insert(
+            COOKIE,
+            HeaderValue::from_static("theme=dark; access_token=cookie.jwt"),
+        );
+        let request = RequestMetadata::new(
+            Method::GET,
+            Uri::from_static("/webhooks/payments?access_token=query.jwt&retry=1"),
+            headers,
+        );
+
+        assert_eq!(
+            CredentialSource::default().extract(&request),
+            Some("Bearer header.jwt".to_string())
+        );
+        assert_eq!(
+            CredentialSource::Header(HeaderName::from_static("x-api-key")).extract(&request),
+            Some("s3cr3t".to_string())
+        );
+        assert_eq!(
+            CredentialSource::Cookie("access_token".to_string()).extract(&request),
+            Some("cookie.jwt".to_string())
+        );
+        assert_eq!(
+            CredentialSource::QueryParam("access_token".to_string()).extract(&request),
+            Some("query.jwt".to_string())
+        );
+        assert_eq!(
+            CredentialSource::QueryParam("token".to_string()).extract(&request),
+            None
+        );
+    }
+}
@@ -1,3 +1,8 @@
 pub mod security_configuration;
+pub mod api_key;
+pub mod basic;
+pub mod credential_source;
+#[cfg(feature = "jwt")]
 pub mod simple_jwt;
+#[cfg(feature = "oidc")]
 pub mod oidc;
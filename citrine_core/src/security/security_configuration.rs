@@ -1,14 +1,26 @@
-use std::{collections::HashMap, fmt::Display};
+use std::{
+    collections::HashMap,
+    fmt::Display,
+    sync::{Arc, RwLock},
+};
 
-use hyper::header::{HeaderValue, AUTHORIZATION};
+use hyper::{header::HeaderValue, http::Extensions};
 use log::debug;
+use serde::de::DeserializeOwned;
 
 use crate::{
+    error::ServerError,
     request::RequestMetadata,
     request_matcher::{MethodMatcher, RequestMatcher},
 };
 
-use super::{oidc::OIDCConfiguration, simple_jwt::JWTConfiguration};
+use super::api_key::ApiKeyConfiguration;
+use super::basic::BasicConfiguration;
+use super::credential_source::CredentialSource;
+#[cfg(feature = "oidc")]
+use super::oidc::OIDCConfiguration;
+#[cfg(feature = "jwt")]
+use super::simple_jwt::JWTConfiguration;
 
 pub struct SecurityConfiguration {
     rules: Vec<SecurityRule>,
@@ -27,7 +39,7 @@ impl SecurityConfiguration {
         self
     }
 
-    pub fn authorize(&self, request: &RequestMetadata) -> AuthResult {
+    pub fn authorize(&self, request: &mut RequestMetadata) -> AuthResult {
         debug!("Authorizing request {} {}", request.method, request.uri);
         for rule in self.rules.iter() {
             if rule.matches(request) {
@@ -38,6 +50,39 @@ impl SecurityConfiguration {
         debug!("No matching rule, allowing request");
         AuthResult::Allowed
     }
+
+    /// Value of the WWW-Authenticate header for a denied request, if the authenticator of the
+    /// rule that matches it defines one
+    pub fn challenge(&self, request: &RequestMetadata) -> Option<String> {
+        self.rules
+            .iter()
+            .find(|rule| rule.matches(request))
+            .and_then(|rule| match &rule.action {
+                SecurityAction::Authenticate(authenticator) => authenticator.challenge(),
+                _ => None,
+            })
+    }
+
+    /// Whether the first rule that matches the request authenticates it
+    pub fn requires_authentication(&self, request: &RequestMetadata) -> bool {
+        self.rules
+            .iter()
+            .find(|rule| rule.matches(request))
+            .is_some_and(|rule| matches!(rule.action, SecurityAction::Authenticate(_)))
+    }
+
+    /// Description of every rule for diagnostics. Keys, secrets and validators are never included
+    pub fn summary(&self) -> Vec<String> {
+        self.rules.iter().map(SecurityRule::summary).collect()
+    }
+
+    pub fn validate(&self) -> Vec<String> {
+        self.rules
+            .iter()
+            .flat_map(|rule| rule.request_matchers.iter())
+            .filter_map(|request_matcher| request_matcher.validate().err())
+            .collect()
+    }
 }
 
 impl Default for SecurityConfiguration {
@@ -46,9 +91,60 @@ impl Default for SecurityConfiguration {
     }
 }
 
+/// Shared access to the security configuration used by the server. Replacing the configuration
+/// through any clone of the handle affects every request received afterwards, which allows
+/// rotating keys or changing rules without a restart
+#[derive(Clone, Default)]
+pub struct SecurityConfigurationHandle {
+    security_configuration: Arc<RwLock<Arc<SecurityConfiguration>>>,
+}
+
+impl SecurityConfigurationHandle {
+    pub fn new(security_configuration: SecurityConfiguration) -> Self {
+        SecurityConfigurationHandle {
+            security_configuration: Arc::new(RwLock::new(Arc::new(security_configuration))),
+        }
+    }
+
+    /// Configuration in use. Requests keep the one they started with even if it is replaced
+    /// while they are being authorized
+    pub fn current(&self) -> Arc<SecurityConfiguration> {
+        self.security_configuration
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone()
+    }
+
+    /// Atomically swaps the configuration in use. It is rejected if it has malformed matchers
+    pub fn replace(
+        &self,
+        security_configuration: SecurityConfiguration,
+    ) -> Result<(), ServerError> {
+        let problems = security_configuration.validate();
+        if !problems.is_empty() {
+            return Err(ServerError::from(format!(
+                "Invalid security configuration: {}",
+                problems.join(", ")
+            )));
+        }
+        self.set(security_configuration);
+        Ok(())
+    }
+
+    pub(crate) fn set(&self, security_configuration: SecurityConfiguration) {
+        *self
+            .security_configuration
+            .write()
+            .unwrap_or_else(|e| e.into_inner()) = Arc::new(security_configuration);
+    }
+}
+
 pub struct SecurityRule {
     request_matchers: Vec<RequestMatcher>,
     action: SecurityAction,
+    required_roles: Vec<String>,
+    required_scopes: Vec<String>,
+    on_authenticated: Option<fn(&AuthResult, &mut Extensions)>,
 }
 
 impl Default for SecurityRule {
@@ -56,6 +152,9 @@ impl Default for SecurityRule {
         SecurityRule {
             request_matchers: vec![],
             action: SecurityAction::Allow,
+            required_roles: vec![],
+            required_scopes: vec![],
+            on_authenticated: None,
         }
     }
 }
@@ -76,6 +175,28 @@ impl SecurityRule {
         self
     }
 
+    /// Roles the roles claim must contain, as an array or a space separated string. Authenticated
+    /// requests without all of them are forbidden, as are requests from authenticators without
+    /// claims like API keys
+    pub fn require_roles(mut self, roles: Vec<String>) -> Self {
+        self.required_roles = roles;
+        self
+    }
+
+    /// Scopes the scope or scp claim must contain, as an array or a space separated string.
+    /// Authenticated requests without all of them are forbidden
+    pub fn require_scopes(mut self, scopes: Vec<String>) -> Self {
+        self.required_scopes = scopes;
+        self
+    }
+
+    /// Runs after any authenticator of the rule accepts a request, to add typed data to the
+    /// extensions of the request, like the user or account that was resolved, for the handler to
+    /// read instead of looking it up again
+    pub fn on_authenticated(mut self, on_authenticated: fn(&AuthResult, &mut Extensions)) -> Self {
+        self.on_authenticated = Some(on_authenticated);
+        self
+    }
 
     pub fn matches(&self, request: &RequestMetadata) -> bool {
         for request_matcher in self.request_matchers.iter() {
@@ -90,8 +211,77 @@ impl SecurityRule {
         false
     }
 
-    pub fn get_auth_result(&self, request: &RequestMetadata) -> AuthResult {
-        self.action.apply(request)
+    pub fn get_auth_result(&self, request: &mut RequestMetadata) -> AuthResult {
+        let auth_result = self.action.apply(request);
+        let has_required_claims = match &auth_result {
+            AuthResult::Authenticated(claims) => self.has_required_claims(claims),
+            // Authenticators without claims, like API keys, can't prove any role or scope
+            AuthResult::CustomAuthenticated(_) => {
+                self.required_roles.is_empty() && self.required_scopes.is_empty()
+            }
+            _ => true,
+        };
+        if !has_required_claims {
+            debug!("Authenticated request lacks the required roles or scopes. Forbidding it");
+            return AuthResult::Forbidden;
+        }
+        if let Some(on_authenticated) = self.on_authenticated {
+            if matches!(
+                auth_result,
+                AuthResult::Authenticated(_) | AuthResult::CustomAuthenticated(_)
+            ) {
+                on_authenticated(&auth_result, &mut request.extensions);
+            }
+        }
+        auth_result
+    }
+
+    fn summary(&self) -> String {
+        let matchers = self
+            .request_matchers
+            .iter()
+            .map(|request_matcher| request_matcher.to_string())
+            .collect::<Vec<String>>()
+            .join(", ");
+        let mut summary = format!("{} | {}", matchers, self.action);
+        if let SecurityAction::Authenticate(authenticator) = &self.action {
+            summary.push_str(&format!(" from {}", authenticator.credential_source()));
+        }
+        if !self.required_roles.is_empty() {
+            summary.push_str(&format!(" | roles {}", self.required_roles.join(", ")));
+        }
+        if !self.required_scopes.is_empty() {
+            summary.push_str(&format!(" | scopes {}", self.required_scopes.join(", ")));
+        }
+        summary
+    }
+
+    fn has_required_claims(&self, claims: &AuthClaims) -> bool {
+        let roles = claim_values(claims, &["roles"]);
+        let scopes = claim_values(claims, &["scope", "scp"]);
+        self.required_roles.iter().all(|role| roles.contains(role))
+            && self
+                .required_scopes
+                .iter()
+                .all(|scope| scopes.contains(scope))
+    }
+}
+
+/// Values of the first present claim of the names, which can be a JSON array or a space separated
+/// string like the OAuth scope claim
+fn claim_values(claims: &AuthClaims, names: &[&str]) -> Vec<String> {
+    let claim = names.iter().find_map(|name| claims.get(*name));
+    match claim {
+        Some(serde_json::Value::Array(values)) => values
+            .iter()
+            .filter_map(|value| value.as_str())
+            .map(|value| value.to_string())
+            .collect(),
+        Some(serde_json::Value::String(values)) => values
+            .split_whitespace()
+            .map(|value| value.to_string())
+            .collect(),
+        _ => vec![],
     }
 }
 
@@ -129,6 +319,8 @@ pub enum AuthResult {
     Allowed,
     Authenticated(AuthClaims),
     CustomAuthenticated(String),
+    // Authenticated, but without the roles or scopes required by the rule
+    Forbidden,
 }
 
 impl AuthResult {
@@ -138,46 +330,461 @@ impl AuthResult {
             _ => None,
         }
     }
+
+    /// Claims deserialized into an application defined type. Returns None if the request has no
+    /// claims or they don't match the type
+    pub fn get_claims_as<C: DeserializeOwned>(&self) -> Option<C> {
+        let claims = self.get_claims()?;
+        let claims = serde_json::Value::Object(
+            claims
+                .iter()
+                .map(|(name, value)| (name.clone(), value.clone()))
+                .collect(),
+        );
+        match serde_json::from_value(claims) {
+            Ok(claims) => Some(claims),
+            Err(e) => {
+                debug!("Claims could not be deserialized: {}", e);
+                None
+            }
+        }
+    }
+
+    /// Who made the request: the sub claim of a token, or the user or key id of custom
+    /// authenticators like Basic or API keys
+    pub fn subject(&self) -> Option<&str> {
+        match self {
+            AuthResult::Authenticated(claims) => claims.get("sub")?.as_str(),
+            AuthResult::CustomAuthenticated(subject) => Some(subject),
+            _ => None,
+        }
+    }
 }
 
 pub enum Authenticator {
+    #[cfg(feature = "oidc")]
     OIDC(OIDCConfiguration),
     //todo add SAML
+    #[cfg(feature = "jwt")]
     JWT(JWTConfiguration),
     // This will receive a function that has the Authorization header as a param and returns
     // whether the request is allowed.
     Custom(fn(&HeaderValue) -> AuthResult),
+    // Like Custom, but the function returns the claims of the authenticated user, which will be
+    // available to handlers just like the ones from a JWT. Returning None denies the request.
+    CustomClaims(fn(&HeaderValue) -> Option<AuthClaims>),
+    // Custom and CustomClaims reading the credentials from another header, a cookie or a query
+    // parameter instead of Authorization
+    CustomFrom(CredentialSource, fn(&HeaderValue) -> AuthResult),
+    CustomClaimsFrom(CredentialSource, fn(&HeaderValue) -> Option<AuthClaims>),
+    // Static API key read from its own header, a cookie or a query parameter
+    ApiKey(Box<ApiKeyConfiguration>),
+    // Username and password in a Basic Authorization header
+    Basic(Box<BasicConfiguration>),
 }
 
 impl Authenticator {
-    pub fn authenticate(&self, request: &RequestMetadata) -> AuthResult {
-        let authorization_header = request.headers.get(AUTHORIZATION);
-        if authorization_header.is_none() {
-            debug!("No Authorization header provided. Denying request");
-            return AuthResult::Denied;
-        }
-        let authorization_header_str = authorization_header.unwrap().to_str();
-        if authorization_header_str.is_err() {
-            debug!("Invalid Authorization header provided. Denying request");
-            return AuthResult::Denied;
+    /// Where the authenticator reads the credentials of a request from. Custom and CustomClaims
+    /// always use the Authorization header
+    pub fn credential_source(&self) -> CredentialSource {
+        match self {
+            #[cfg(feature = "jwt")]
+            Authenticator::JWT(config) => config.get_credential_source().clone(),
+            #[cfg(feature = "oidc")]
+            Authenticator::OIDC(config) => config.get_credential_source().clone(),
+            Authenticator::ApiKey(config) => config.get_credential_source().clone(),
+            Authenticator::Basic(config) => config.get_credential_source().clone(),
+            Authenticator::Custom(_) | Authenticator::CustomClaims(_) => {
+                CredentialSource::default()
+            }
+            Authenticator::CustomFrom(credential_source, _)
+            | Authenticator::CustomClaimsFrom(credential_source, _) => credential_source.clone(),
         }
+    }
+
+    pub fn authenticate(&self, request: &RequestMetadata) -> AuthResult {
+        let credential_source = self.credential_source();
+        let credentials = match credential_source.extract(request) {
+            Some(credentials) => credentials,
+            None => {
+                debug!(
+                    "No credentials provided in {}. Denying request",
+                    credential_source
+                );
+                return AuthResult::Denied;
+            }
+        };
 
         match self {
-            Authenticator::JWT(config) => config.authenticate(authorization_header_str.unwrap()),
-            Authenticator::OIDC(config) => config.authenticate(authorization_header_str.unwrap()),
-            Authenticator::Custom(custom_auth_function) => {
-                custom_auth_function(authorization_header.unwrap())
+            #[cfg(feature = "jwt")]
+            Authenticator::JWT(config) => config.authenticate(&credentials),
+            #[cfg(feature = "oidc")]
+            Authenticator::OIDC(config) => config.authenticate(&credentials),
+            Authenticator::ApiKey(config) => config.authenticate(&credentials),
+            Authenticator::Basic(config) => config.authenticate(&credentials),
+            Authenticator::Custom(custom_auth_function)
+            | Authenticator::CustomFrom(_, custom_auth_function) => {
+                match HeaderValue::from_str(&credentials) {
+                    Ok(credentials) => custom_auth_function(&credentials),
+                    Err(_) => AuthResult::Denied,
+                }
+            }
+            Authenticator::CustomClaims(custom_auth_function)
+            | Authenticator::CustomClaimsFrom(_, custom_auth_function) => {
+                match HeaderValue::from_str(&credentials)
+                    .ok()
+                    .and_then(|credentials| custom_auth_function(&credentials))
+                {
+                    Some(claims) => AuthResult::Authenticated(claims),
+                    None => AuthResult::Denied,
+                }
             }
         }
     }
+
+    pub fn challenge(&self) -> Option<String> {
+        match self {
+            Authenticator::Basic(config) => Some(config.challenge()),
+            _ => None,
+        }
+    }
 }
 
 impl Display for Authenticator {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
+            #[cfg(feature = "jwt")]
             Self::JWT(_) => write!(f, "JWT"),
+            #[cfg(feature = "oidc")]
             Self::OIDC(_) => write!(f, "OIDC"),
-            Self::Custom(_) => write!(f, "Custom"),
+            Self::Custom(_) | Self::CustomFrom(..) => write!(f, "Custom"),
+            Self::CustomClaims(_) | Self::CustomClaimsFrom(..) => write!(f, "Custom with claims"),
+            Self::ApiKey(_) => write!(f, "API key"),
+            Self::Basic(_) => write!(f, "Basic"),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use hyper::{
+        header::{HeaderName, AUTHORIZATION, COOKIE},
+        HeaderMap, Method, Uri,
+    };
+
+    use crate::request::Request;
+
+    use super::*;
+
+    fn request_with_authorization(authorization: &'static str) -> RequestMetadata {
+        let mut headers = HeaderMap::new();
+        headers.insert(AUTHORIZATION, HeaderValue::from_static(authorization));
+        RequestMetadata::new(Method::GET, Uri::from_static("/api/users"), headers)
+    }
+
+    #[test]
+    fn replace_security_configuration_test() {
+        let handle = SecurityConfigurationHandle::new(
+            SecurityConfiguration::new().add_rule(
+                SecurityRule::new()
+                    .add_matcher(MethodMatcher::All, "/api/*")
+                    .execute_action(SecurityAction::Allow),
+            ),
+        );
+        let server_handle = handle.clone();
+        let mut request = RequestMetadata::new(
+            Method::GET,
+            Uri::from_static("/api/users"),
+            HeaderMap::new(),
+        );
+        assert_eq!(
+            server_handle.current().authorize(&mut request),
+            AuthResult::Allowed
+        );
+
+        handle
+            .replace(
+                SecurityConfiguration::new().add_rule(
+                    SecurityRule::new()
+                        .add_matcher(MethodMatcher::All, "/api/*")
+                        .execute_action(SecurityAction::Deny),
+                ),
+            )
+            .unwrap();
+        assert_eq!(
+            server_handle.current().authorize(&mut request),
+            AuthResult::Denied
+        );
+
+        let invalid = SecurityConfiguration::new().add_rule(
+            SecurityRule::new()
+                .add_matcher(MethodMatcher::All, "/api/(*")
+                .execute_action(SecurityAction::Allow),
+        );
+        assert!(handle.replace(invalid).is_err());
+        assert_eq!(
+            server_handle.current().authorize(&mut request),
+            AuthResult::Denied
+        );
+    }
+
+    #[test]
+    fn required_roles_and_scopes_test() {
+        let configuration = SecurityConfiguration::new().add_rule(
+            SecurityRule::new()
+                .add_matcher(MethodMatcher::All, "/api/*")
+                .execute_action(SecurityAction::Authenticate(Authenticator::CustomClaims(
+                    |authorization| {
+                        let mut claims = AuthClaims::new();
+                        match authorization.to_str().unwrap_or("") {
+                            "Session alice" => {
+                                claims.insert("roles".to_string(), serde_json::json!(["admin"]));
+                                claims.insert("scp".to_string(), serde_json::json!("users:read"));
+                            }
+                            "Session bob" => {
+                                claims.insert("roles".to_string(), serde_json::json!("viewer"));
+                                claims.insert(
+                                    "scope".to_string(),
+                                    serde_json::json!("users:read users:write"),
+                                );
+                            }
+                            _ => return None,
+                        }
+                        Some(claims)
+                    },
+                )))
+                .require_roles(vec!["admin".to_string()])
+                .require_scopes(vec!["users:read".to_string()]),
+        );
+
+        let auth_result = configuration.authorize(&mut request_with_authorization("Session alice"));
+        assert!(matches!(auth_result, AuthResult::Authenticated(_)));
+        assert_eq!(
+            configuration.authorize(&mut request_with_authorization("Session bob")),
+            AuthResult::Forbidden
+        );
+        assert_eq!(
+            configuration.authorize(&mut request_with_authorization("Session eve")),
+            AuthResult::Denied
+        );
+    }
+
+    #[test]
+    fn basic_challenge_test() {
+        let configuration = SecurityConfiguration::new().add_rule(
+            SecurityRule::new()
+                .add_matcher(MethodMatcher::All, "/admin/*")
+                .execute_action(SecurityAction::Authenticate(Authenticator::Basic(
+                    Box::new(BasicConfiguration::new("Admin", |username, password| {
+                        username == "admin" && password == "admin"
+                    })),
+                ))),
+        );
+        let mut request = RequestMetadata::new(
+            Method::GET,
+            Uri::from_static("/admin/users"),
+            HeaderMap::new(),
+        );
+
+        assert_eq!(configuration.authorize(&mut request), AuthResult::Denied);
+        assert_eq!(
+            configuration.challenge(&request).unwrap(),
+            "Basic realm=\"Admin\", charset=\"UTF-8\""
+        );
+
+        let request =
+            RequestMetadata::new(Method::GET, Uri::from_static("/other"), HeaderMap::new());
+        assert!(configuration.challenge(&request).is_none());
+    }
+
+    #[test]
+    fn required_roles_without_claims_test() {
+        let configuration = SecurityConfiguration::new().add_rule(
+            SecurityRule::new()
+                .add_matcher(MethodMatcher::All, "/admin/*")
+                .execute_action(SecurityAction::Authenticate(Authenticator::ApiKey(
+                    Box::new(
+                        ApiKeyConfiguration::new().add_key("reports-service", "r3p0rts-s3cr3t"),
+                    ),
+                )))
+                .require_roles(vec!["admin".to_string()]),
+        );
+        let mut headers = HeaderMap::new();
+        headers.insert("x-api-key", HeaderValue::from_static("r3p0rts-s3cr3t"));
+        let mut request =
+            RequestMetadata::new(Method::GET, Uri::from_static("/admin/users"), headers);
+
+        // A valid key has no roles to satisfy the rule
+        assert_eq!(configuration.authorize(&mut request), AuthResult::Forbidden);
+    }
+
+    #[derive(Clone, Debug, PartialEq)]
+    struct Account {
+        id: u64,
+        plan: &'static str,
+    }
+
+    #[test]
+    fn authenticator_extensions_test() {
+        let configuration = SecurityConfiguration::new().add_rule(
+            SecurityRule::new()
+                .add_matcher(MethodMatcher::All, "/api/*")
+                .execute_action(SecurityAction::Authenticate(Authenticator::ApiKey(
+                    Box::new(ApiKeyConfiguration::new().add_key("acme", "acme-key")),
+                )))
+                .on_authenticated(|auth_result, extensions| {
+                    if auth_result.subject() == Some("acme") {
+                        extensions.insert(Account {
+                            id: 42,
+                            plan: "enterprise",
+                        });
+                    }
+                }),
+        );
+        let mut headers = HeaderMap::new();
+        headers.insert("x-api-key", HeaderValue::from_static("acme-key"));
+        let mut metadata =
+            RequestMetadata::new(Method::GET, Uri::from_static("/api/invoices"), headers);
+
+        let auth_result = configuration.authorize(&mut metadata);
+        let request = Request::from_metadata_streaming(metadata, auth_result);
+
+        // The handler gets the account without looking it up again
+        assert_eq!(
+            request.extensions().get::<Account>(),
+            Some(&Account {
+                id: 42,
+                plan: "enterprise"
+            })
+        );
+        assert_eq!(
+            request.auth_result,
+            AuthResult::CustomAuthenticated("acme".to_string())
+        );
+    }
+
+    #[test]
+    fn credential_source_authenticators_test() {
+        let header_authenticator = Authenticator::ApiKey(Box::new(
+            ApiKeyConfiguration::new()
+                .header(HeaderName::from_static("x-service-token"))
+                .add_key("billing-service", "b1ll1ng-s3cr3t"),
+        ));
+        let cookie_authenticator = Authenticator::ApiKey(Box::new(
+            ApiKeyConfiguration::new()
+                .credential_source(CredentialSource::Cookie("session".to_string()))
+                .add_key("alice", "al1c3-s3ss10n"),
+        ));
+
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            "x-service-token",
+            HeaderValue::from_static("b1ll1ng-s3cr3t"),
+        );
+        headers.insert(
+            COOKIE,
+            HeaderValue::from_static("theme=dark; session=al1c3-s3ss10n"),
+        );
+        let request = RequestMetadata::new(Method::GET, Uri::from_static("/api/invoices"), headers);
+
+        assert_eq!(
+            header_authenticator.authenticate(&request),
+            AuthResult::CustomAuthenticated("billing-service".to_string())
+        );
+        assert_eq!(
+            cookie_authenticator.authenticate(&request),
+            AuthResult::CustomAuthenticated("alice".to_string())
+        );
+
+        // The default X-API-Key header is no longer read
+        let mut request = request_with_authorization("Bearer b1ll1ng-s3cr3t");
+        request
+            .headers
+            .insert("x-api-key", HeaderValue::from_static("b1ll1ng-s3cr3t"));
+        assert_eq!(
+            header_authenticator.authenticate(&request),
+            AuthResult::Denied
+        );
+    }
+
+    #[test]
+    fn custom_and_basic_credential_source_test() {
+        let custom_authenticator =
+            Authenticator::CustomFrom(CredentialSource::Cookie("session".to_string()), |session| {
+                match session.to_str() {
+                    Ok("al1c3-s3ss10n") => AuthResult::CustomAuthenticated("alice".to_string()),
+                    _ => AuthResult::Denied,
+                }
+            });
+        let basic_authenticator = Authenticator::Basic(Box::new(
+            BasicConfiguration::new("Admin", |username, password| {
+                username == "admin" && password == "s3cr3t"
+            })
+            .credential_source(CredentialSource::Header(HeaderName::from_static(
+                "x-forwarded-authorization",
+            ))),
+        ));
+
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            COOKIE,
+            HeaderValue::from_static("theme=dark; session=al1c3-s3ss10n"),
+        );
+        headers.insert(
+            "x-forwarded-authorization",
+            HeaderValue::from_static("Basic YWRtaW46czNjcjN0"),
+        );
+        let request = RequestMetadata::new(Method::GET, Uri::from_static("/admin"), headers);
+
+        assert_eq!(
+            custom_authenticator.authenticate(&request),
+            AuthResult::CustomAuthenticated("alice".to_string())
+        );
+        assert_eq!(
+            basic_authenticator.authenticate(&request),
+            AuthResult::CustomAuthenticated("admin".to_string())
+        );
+
+        // Neither reads the Authorization header anymore
+        let request = request_with_authorization("Basic YWRtaW46czNjcjN0");
+        assert_eq!(
+            custom_authenticator.authenticate(&request),
+            AuthResult::Denied
+        );
+        assert_eq!(
+            basic_authenticator.authenticate(&request),
+            AuthResult::Denied
+        );
+    }
+
+    #[test]
+    fn custom_claims_authenticator_test() {
+        let authenticator = Authenticator::CustomClaims(|authorization| {
+            if authorization.to_str().unwrap_or("") != "Session alice" {
+                return None;
+            }
+            let mut claims = AuthClaims::new();
+            claims.insert("sub".to_string(), serde_json::json!("alice"));
+            claims.insert("roles".to_string(), serde_json::json!(["admin"]));
+            Some(claims)
+        });
+
+        let metadata = request_with_authorization("Session alice");
+        let auth_result = authenticator.authenticate(&metadata);
+        let request = Request::new(
+            metadata.method,
+            metadata.uri,
+            String::new(),
+            metadata.headers,
+            auth_result,
+        );
+
+        // The handler reads the claims just like the ones of a JWT
+        let claims = request.auth_result.get_claims().unwrap();
+        assert_eq!(claims.get("sub").unwrap(), "alice");
+        assert_eq!(claims.get("roles").unwrap()[0], "admin");
+
+        let auth_result = authenticator.authenticate(&request_with_authorization("Session bob"));
+        assert_eq!(auth_result, AuthResult::Denied);
+    }
+}
@@ -1,10 +1,12 @@
+use std::collections::HashSet;
+
 use base64::Engine;
 use jsonwebtoken::{Algorithm, DecodingKey, Validation};
 use log::debug;
 
 use crate::security::security_configuration::AuthClaims;
 
-use super::security_configuration::AuthResult;
+use super::{credential_source::CredentialSource, security_configuration::AuthResult};
 
 pub enum JWTSecret {
     Plain(String),
@@ -21,10 +23,20 @@ impl JWTSecret {
     }
 }
 
-#[derive(Debug)]
 pub struct JWTConfiguration {
     secret: String,
-    algorithm: Algorithm,
+    validation: Box<Validation>,
+    credential_source: CredentialSource,
+}
+
+impl std::fmt::Debug for JWTConfiguration {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("JWTConfiguration")
+            .field("secret", &"[redacted]")
+            .field("validation", &self.validation)
+            .field("credential_source", &self.credential_source)
+            .finish()
+    }
 }
 
 impl JWTConfiguration {
@@ -44,27 +56,164 @@ impl JWTConfiguration {
             }
         };
 
-        JWTConfiguration { secret, algorithm }
+        JWTConfiguration {
+            secret,
+            validation: Box::new(Validation::new(algorithm)),
+            credential_source: CredentialSource::default(),
+        }
+    }
+
+    /// Where the token is read from. Defaults to the Authorization header, with or without the
+    /// Bearer scheme
+    pub fn credential_source(mut self, credential_source: CredentialSource) -> Self {
+        self.credential_source = credential_source;
+        self
+    }
+
+    pub fn get_credential_source(&self) -> &CredentialSource {
+        &self.credential_source
+    }
+
+    /// Seconds of clock skew tolerated when checking exp and nbf, for identity providers whose
+    /// clocks drift from the server one. Defaults to 60
+    pub fn leeway(mut self, leeway: u64) -> Self {
+        self.validation.leeway = leeway;
+        self
+    }
+
+    /// Whether tokens without an exp claim are rejected. Defaults to true. The exp claim is still
+    /// validated when present
+    pub fn require_exp(mut self, require_exp: bool) -> Self {
+        if require_exp {
+            self.validation
+                .required_spec_claims
+                .insert("exp".to_string());
+        } else {
+            self.validation.required_spec_claims.remove("exp");
+        }
+        self
+    }
+
+    /// Whether tokens used before their nbf claim are rejected. Defaults to false
+    pub fn validate_nbf(mut self, validate_nbf: bool) -> Self {
+        self.validation.validate_nbf = validate_nbf;
+        self
+    }
+
+    /// The aud claim must contain one of the audiences, or tokens are rejected
+    pub fn expected_audience(mut self, audience: HashSet<String>) -> Self {
+        self.validation.aud = Some(audience);
+        self
+    }
+
+    /// The iss claim must be one of the issuers, or tokens are rejected
+    pub fn expected_issuer(mut self, issuer: HashSet<String>) -> Self {
+        self.validation.iss = Some(issuer);
+        self
     }
 
     pub fn authenticate(&self, token: &str) -> AuthResult {
         debug!("Using JWT Authenticator");
-        let validation = Validation::new(self.algorithm);
         let split_token = token.split(" ");
         let token = split_token.last().unwrap_or("");
 
         let token_data = jsonwebtoken::decode::<AuthClaims>(
             token,
             &DecodingKey::from_secret(self.secret.as_ref()),
-            &validation,
+            &self.validation,
         );
 
-        if token_data.is_err() {
-            debug!("Error getting token data {:?}", token_data.err());
-            AuthResult::Denied
-        } else {
-            debug!("Request allowed");
-            AuthResult::Authenticated(token_data.unwrap().claims)
+        match token_data {
+            Ok(token_data) => {
+                debug!("Request allowed");
+                AuthResult::Authenticated(token_data.claims)
+            }
+            Err(e) => {
+                debug!("Error getting token data {:?}", e);
+                AuthResult::Denied
+            }
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use chrono::Utc;
+    use jsonwebtoken::{EncodingKey, Header};
+    use serde_json::json;
+
+    use super::*;
+
+    const SECRET: &str = "jwt-test-secret";
+
+    fn token(claims: serde_json::Value) -> String {
+        jsonwebtoken::encode(
+            &Header::new(Algorithm::HS256),
+            &claims,
+            &EncodingKey::from_secret(SECRET.as_ref()),
+        )
+        .unwrap()
+    }
+
+    fn is_authenticated(configuration: &JWTConfiguration, token: &str) -> bool {
+        matches!(
+            configuration.authenticate(token),
+            AuthResult::Authenticated(_)
+        )
+    }
+
+    #[test]
+    fn leeway_and_required_claims_test() {
+        let now = Utc::now().timestamp();
+        let expired = token(json!({ "sub": "alice", "exp": now - 90 }));
+        let without_exp = token(json!({ "sub": "alice" }));
+
+        // Defaults keep the previous behavior: 60 seconds of leeway and exp is required
+        let configuration = JWTConfiguration::new(JWTSecret::plain(SECRET), Algorithm::HS256);
+        assert!(!is_authenticated(&configuration, &expired));
+        assert!(!is_authenticated(&configuration, &without_exp));
+
+        let configuration = JWTConfiguration::new(JWTSecret::plain(SECRET), Algorithm::HS256)
+            .leeway(120)
+            .require_exp(false);
+        assert!(is_authenticated(&configuration, &expired));
+        assert!(is_authenticated(&configuration, &without_exp));
+
+        let configuration =
+            JWTConfiguration::new(JWTSecret::plain(SECRET), Algorithm::HS256).validate_nbf(true);
+        let not_yet_valid = token(json!({ "sub": "alice", "exp": now + 600, "nbf": now + 300 }));
+        assert!(!is_authenticated(&configuration, &not_yet_valid));
+    }
+
+    #[test]
+    fn expected_audience_and_issuer_test() {
+        let configuration = JWTConfiguration::new(JWTSecret::plain(SECRET), Algorithm::HS256)
+            .expected_audience(HashSet::from(["orders-api".to_string()]))
+            .expected_issuer(HashSet::from(["https://id.example.com".to_string()]));
+        let exp = Utc::now().timestamp() + 600;
+
+        let valid = token(json!({
+            "sub": "alice",
+            "exp": exp,
+            "aud": "orders-api",
+            "iss": "https://id.example.com"
+        }));
+        assert!(is_authenticated(&configuration, &valid));
+
+        let wrong_audience = token(json!({
+            "sub": "alice",
+            "exp": exp,
+            "aud": "billing-api",
+            "iss": "https://id.example.com"
+        }));
+        assert!(!is_authenticated(&configuration, &wrong_audience));
+
+        let wrong_issuer = token(json!({
+            "sub": "alice",
+            "exp": exp,
+            "aud": "orders-api",
+            "iss": "https://evil.example.com"
+        }));
+        assert!(!is_authenticated(&configuration, &wrong_issuer));
+    }
+}
@@ -0,0 +1,121 @@
+use std::{
+    any::{Any, TypeId},
+    collections::HashMap,
+};
+
+use serde_json::Value;
+
+type ErasedSerializer = Box<dyn Fn(&dyn Any) -> Value + Send + Sync>;
+
+/// Custom JSON representations for specific types. When the body passed to
+/// Response::json_registered is of a registered type, its serializer is used instead of the
+/// Serialize implementation of the type
+#[derive(Default)]
+pub struct ResponseSerializers {
+    serializers: HashMap<TypeId, ErasedSerializer>,
+}
+
+impl ResponseSerializers {
+    pub fn new() -> Self {
+        ResponseSerializers::default()
+    }
+
+    pub fn register<S: Any>(mut self, serializer: fn(&S) -> Value) -> Self {
+        self.serializers.insert(
+            TypeId::of::<S>(),
+            Box::new(move |value| serializer(value.downcast_ref::<S>().unwrap())),
+        );
+        self
+    }
+
+    pub fn serialize(&self, value: &dyn Any) -> Option<Value> {
+        self.serializers
+            .get(&value.type_id())
+            .map(|serializer| serializer(value))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use hyper::{HeaderMap, Method, StatusCode, Uri};
+    use serde_json::json;
+
+    use crate::{
+        request::Request,
+        response::{Response, ResponseBody},
+        router::{InternalRouter, Router},
+        security::security_configuration::AuthResult,
+    };
+
+    use super::*;
+
+    struct Money {
+        cents: i64,
+        currency: &'static str,
+    }
+
+    #[test]
+    fn registered_serializer_test() {
+        let serializers = ResponseSerializers::new().register(|money: &Money| {
+            json!(format!(
+                "{}.{:02} {}",
+                money.cents / 100,
+                money.cents % 100,
+                money.currency
+            ))
+        });
+
+        let price = Money {
+            cents: 1999,
+            currency: "EUR",
+        };
+        assert_eq!(serializers.serialize(&price), Some(json!("19.99 EUR")));
+        assert_eq!(serializers.serialize(&"not money"), None);
+    }
+
+    #[derive(serde::Serialize)]
+    struct Temperature {
+        celsius: f64,
+    }
+
+    fn json_body(response: &Response) -> Value {
+        match response.get_body() {
+            Some(ResponseBody::Full(body)) => serde_json::from_slice(body).unwrap(),
+            _ => panic!("Expected a JSON body"),
+        }
+    }
+
+    #[test]
+    fn json_response_serializer_test() {
+        let mut router = InternalRouter::from(Router::<()>::new().get("/temperature", |_, req| {
+            Response::new(StatusCode::OK).json_registered(&req, Temperature { celsius: 21.5 })
+        }))
+        .unwrap();
+        let request = || {
+            Request::new(
+                Method::GET,
+                Uri::from_static("/temperature"),
+                String::new(),
+                HeaderMap::new(),
+                AuthResult::Allowed,
+            )
+        };
+
+        // Without registered serializers the Serialize implementation is used
+        let (_, response) = router.run(request(), Arc::new(()));
+        assert_eq!(json_body(&response), json!({ "celsius": 21.5 }));
+
+        router.set_response_serializers(Some(ResponseSerializers::new().register(
+            |temperature: &Temperature| json!({ "value": temperature.celsius, "unit": "C" }),
+        )));
+        let (_, response) = router.run(request(), Arc::new(()));
+        assert_eq!(json_body(&response), json!({ "value": 21.5, "unit": "C" }));
+
+        // json keeps the Serialize implementation and takes borrowed values
+        let temperature = Temperature { celsius: 21.5 };
+        let response = Response::new(StatusCode::OK).json(&temperature);
+        assert_eq!(json_body(&response), json!({ "celsius": 21.5 }));
+    }
+}
@@ -1,29 +1,69 @@
-use http_body_util::Full;
+use chrono::Utc;
+use hyper::body::Body;
+use hyper::header::{ORIGIN, REFERER, USER_AGENT, WWW_AUTHENTICATE};
+use hyper::server::conn::http1;
 use hyper::service::service_fn;
-use hyper::{body::Bytes, server::conn::http1};
+#[cfg(feature = "templates")]
+use hyper::StatusCode;
+use hyper::{HeaderMap, Version};
 use hyper_util::rt::TokioIo;
 use hyper_util::server::graceful::GracefulShutdown;
 use log::{error, info};
+#[cfg(feature = "templates")]
+use std::collections::HashMap;
+use std::future::Future;
 use std::net::SocketAddr;
+use std::pin::Pin;
 use std::process::exit;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 use tokio::net::TcpListener;
+use tokio::sync::mpsc;
+use tokio_rustls::TlsAcceptor;
 
-use crate::error::{ErrorType, RequestError, ServerError};
-use crate::middleware::RequestMiddleware;
-use crate::request::{Request, RequestMetadata};
-use crate::response::Response;
+use crate::access_log::{AccessLogEntry, AccessLogFormat, AuthenticatedSubject};
+use crate::cors::CorsConfiguration;
+use crate::drain::{self, DEFAULT_DRAIN_TIMEOUT, DRAIN_REPORT_INTERVAL};
+use crate::error::{
+    self, ErrorConfiguration, ErrorFormat, ErrorHandler, ErrorType, RequestError, ServerError,
+};
+use crate::middleware::{RequestMiddleware, ResponseMiddleware};
+use crate::request::{Request, RequestMetadata, ShutdownState};
+use crate::resolution::{ResolutionOrder, Resolver};
+use crate::response::{BoxedBody, Response};
 use crate::router::InternalRouter;
-use crate::security::security_configuration::{AuthResult, SecurityConfiguration};
-use crate::static_file_server::StaticFileServer;
+use crate::security::security_configuration::{
+    AuthResult, SecurityConfiguration, SecurityConfigurationHandle,
+};
+use crate::slow_request_log::{RequestMeasurement, SlowRequestLog};
+#[cfg(feature = "static-files")]
+use crate::static_file_server::{self, StaticFileServer};
+use crate::tls::TLS_HANDSHAKE_TIMEOUT;
+
+/// Address the server listens on
+pub(crate) const HOST: [u8; 4] = [127, 0, 0, 1];
+
+pub type ReadinessGate = Pin<Box<dyn Future<Output = ()> + Send>>;
 
 pub struct RequestPipelineConfiguration<T: 'static + Send + Sync> {
     response_interceptor: fn(&Request, &Response),
     router: InternalRouter<T>,
-    security_configuration: SecurityConfiguration,
+    security_configuration: SecurityConfigurationHandle,
+    #[cfg(feature = "static-files")]
     static_file_server: StaticFileServer,
+    resolution_order: ResolutionOrder,
     request_middleware: RequestMiddleware,
+    response_middleware: ResponseMiddleware,
     context: Arc<T>,
+    ready: AtomicBool,
+    shutdown_state: ShutdownState,
+    cors: Option<CorsConfiguration>,
+    drain_timeout: Duration,
+    error_configuration: ErrorConfiguration,
+    slow_request_log: Option<SlowRequestLog>,
+    default_headers: HeaderMap,
+    access_log: Option<AccessLogFormat>,
 }
 
 impl<T> RequestPipelineConfiguration<T>
@@ -33,35 +73,106 @@ where
     pub fn new(
         response_interceptor: fn(&Request, &Response),
         router: InternalRouter<T>,
-        security_configuration: SecurityConfiguration,
-        static_file_server: StaticFileServer,
+        security_configuration: SecurityConfigurationHandle,
         request_middleware: RequestMiddleware,
+        response_middleware: ResponseMiddleware,
         context: T,
     ) -> Self {
         RequestPipelineConfiguration {
             response_interceptor,
             router,
             security_configuration,
-            static_file_server,
+            #[cfg(feature = "static-files")]
+            static_file_server: StaticFileServer::default(),
+            resolution_order: ResolutionOrder::default(),
             request_middleware,
+            response_middleware,
             context: Arc::new(context),
+            ready: AtomicBool::new(true),
+            shutdown_state: ShutdownState::default(),
+            cors: None,
+            drain_timeout: DEFAULT_DRAIN_TIMEOUT,
+            error_configuration: ErrorConfiguration::default(),
+            slow_request_log: None,
+            default_headers: HeaderMap::new(),
+            access_log: None,
         }
     }
+
+    #[cfg(feature = "static-files")]
+    pub fn with_static_file_server(mut self, static_file_server: StaticFileServer) -> Self {
+        self.static_file_server = static_file_server;
+        self
+    }
+
+    pub fn with_resolution_order(mut self, resolution_order: ResolutionOrder) -> Self {
+        self.resolution_order = resolution_order;
+        self
+    }
+
+    pub fn with_drain_timeout(mut self, drain_timeout: Duration) -> Self {
+        self.drain_timeout = drain_timeout;
+        self
+    }
+
+    pub fn with_cors(mut self, cors: Option<CorsConfiguration>) -> Self {
+        self.cors = cors;
+        self
+    }
+
+    pub fn with_error_handler(mut self, error_handler: Option<ErrorHandler>) -> Self {
+        self.error_configuration.handler = error_handler;
+        self
+    }
+
+    pub fn with_error_format(mut self, error_format: ErrorFormat) -> Self {
+        self.error_configuration.format = error_format;
+        self
+    }
+
+    #[cfg(feature = "templates")]
+    pub fn with_error_templates(mut self, error_templates: HashMap<StatusCode, String>) -> Self {
+        self.error_configuration.templates = Arc::new(error_templates);
+        self
+    }
+
+    pub fn with_slow_request_log(mut self, slow_request_log: Option<SlowRequestLog>) -> Self {
+        self.slow_request_log = slow_request_log;
+        self
+    }
+
+    pub fn with_default_headers(mut self, default_headers: HeaderMap) -> Self {
+        self.default_headers = default_headers;
+        self
+    }
+
+    pub fn with_access_log(mut self, access_log: Option<AccessLogFormat>) -> Self {
+        self.access_log = access_log;
+        self
+    }
 }
 
-pub async fn start<T>(port: u16, config: RequestPipelineConfiguration<T>)
-where
+pub async fn start<T>(
+    port: u16,
+    config: RequestPipelineConfiguration<T>,
+    readiness_gate: Option<ReadinessGate>,
+    tls_acceptor: Option<TlsAcceptor>,
+) where
     T: 'static + Sync + Send,
 {
     let listener: TcpListener;
-    match TcpListener::bind(SocketAddr::from(([127, 0, 0, 1], port))).await {
+    match TcpListener::bind(SocketAddr::from((HOST, port))).await {
         Ok(tcp_listener) => listener = tcp_listener,
         Err(_) => {
             error!("Error binding port {}", port);
             exit(1)
         }
     }
-    info!("Listening in port {}", port);
+    if tls_acceptor.is_some() {
+        info!("Listening in port {} (TLS)", port);
+    } else {
+        info!("Listening in port {}", port);
+    }
 
     let http = http1::Builder::new();
 
@@ -69,44 +180,105 @@ where
 
     let config = Arc::new(config);
 
+    if let Some(readiness_gate) = readiness_gate {
+        gate_readiness(&config, readiness_gate);
+    }
+
     let mut signal = std::pin::pin!(shutdown_signal());
 
+    // TLS handshakes are done in their own task so a slow client can't block the accept loop.
+    // Finished handshakes are sent back here to be watched by the graceful shutdown
+    let (tls_stream_tx, mut tls_stream_rx) = mpsc::unbounded_channel();
+
     loop {
         tokio::select! {
-            Ok((stream, _addr)) = listener.accept() => {
-                let io = TokioIo::new(stream);
-
-                //Check if we can avoid the double cloning
-                let request_config = config.clone();
-                let svc = service_fn(move |request| {
-                    handle_request(request, request_config.clone())
-                });
-
-                let conn = http.serve_connection(io, svc);
-
-                let fut = graceful_shutdown.watch(conn);
-
-                tokio::spawn(async move {
-                    if let Err(e) = fut.await {
-                        error!("Error handling request {:?}", e);
+            Ok((stream, remote_addr)) = listener.accept() => {
+                match tls_acceptor.as_ref() {
+                    Some(tls_acceptor) => {
+                        let handshake = tls_acceptor.accept(stream);
+                        let tls_stream_tx = tls_stream_tx.clone();
+                        tokio::spawn(async move {
+                            match tokio::time::timeout(TLS_HANDSHAKE_TIMEOUT, handshake).await {
+                                Ok(Ok(tls_stream)) => {
+                                    let _ = tls_stream_tx.send((tls_stream, remote_addr));
+                                }
+                                Ok(Err(e)) => error!("TLS handshake failed {:?}", e),
+                                Err(_) => error!("TLS handshake with {} timed out", remote_addr),
+                            }
+                        });
                     }
-                });
+                    None => {
+                        let io = TokioIo::new(stream);
+                        serve_connection(
+                            &http,
+                            &graceful_shutdown,
+                            io,
+                            remote_addr,
+                            config.clone(),
+                        );
+                    }
+                }
+            },
+
+            Some((tls_stream, remote_addr)) = tls_stream_rx.recv() => {
+                let io = TokioIo::new(tls_stream);
+                serve_connection(&http, &graceful_shutdown, io, remote_addr, config.clone());
             },
 
             _ = &mut signal => {
                 info!("Shutting down gracefully");
+                config.shutdown_state.begin_shutdown();
                 break;
             }
         }
     }
 
-    tokio::select! {
-        _ = graceful_shutdown.shutdown() => {
-        },
-        _ = tokio::time::sleep(std::time::Duration::from_secs(10)) => {
-            eprintln!("Timed out wait for all connections to close");
+    drain::drain(
+        graceful_shutdown.shutdown(),
+        &config.shutdown_state,
+        config.drain_timeout,
+        DRAIN_REPORT_INTERVAL,
+    )
+    .await;
+}
+
+fn serve_connection<T, I>(
+    http: &http1::Builder,
+    graceful_shutdown: &GracefulShutdown,
+    io: I,
+    remote_addr: SocketAddr,
+    config: Arc<RequestPipelineConfiguration<T>>,
+) where
+    T: 'static + Sync + Send,
+    I: hyper::rt::Read + hyper::rt::Write + Unpin + Send + 'static,
+{
+    let connection = config.shutdown_state.track_connection();
+    let svc = service_fn(move |request| handle_request(request, remote_addr, config.clone()));
+
+    let conn = http.serve_connection(io, svc);
+
+    let fut = graceful_shutdown.watch(conn);
+
+    tokio::spawn(async move {
+        if let Err(e) = fut.await {
+            error!("Error handling request {:?}", e);
         }
-    }
+        drop(connection);
+    });
+}
+
+/// Requests are answered with a 503 until the gate resolves
+fn gate_readiness<T: Send + Sync + 'static>(
+    config: &Arc<RequestPipelineConfiguration<T>>,
+    readiness_gate: ReadinessGate,
+) {
+    config.ready.store(false, Ordering::Release);
+    let gate_config = config.clone();
+    tokio::spawn(async move {
+        readiness_gate.await;
+        gate_config.ready.store(true, Ordering::Release);
+        info!("Application ready to serve requests");
+    });
 }
 
 async fn shutdown_signal() {
@@ -122,45 +294,686 @@ async fn shutdown_signal() {
 
 async fn handle_request<T: Send + Sync + 'static>(
     request: hyper::Request<hyper::body::Incoming>,
+    remote_addr: SocketAddr,
+    config: Arc<RequestPipelineConfiguration<T>>,
+) -> Result<hyper::Response<BoxedBody>, ServerError> {
+    let version = request.version();
+    handle_metadata(request.into(), version, remote_addr, config).await
+}
+
+/// Rest of the pipeline once the request is split into its metadata and its unread body, so it
+/// can also be driven with requests built inside the framework
+async fn handle_metadata<T: Send + Sync + 'static>(
+    request_metadata: RequestMetadata,
+    version: Version,
+    remote_addr: SocketAddr,
+    config: Arc<RequestPipelineConfiguration<T>>,
+) -> Result<hyper::Response<BoxedBody>, ServerError> {
+    let mut response = if config.slow_request_log.is_some() || config.access_log.is_some() {
+        measure(request_metadata, version, remote_addr, config.clone()).await?
+    } else {
+        handle_cors(request_metadata, config.clone()).await?
+    };
+    add_default_headers(&config.default_headers, response.headers_mut());
+    Ok(response)
+}
+
+/// Times the whole pipeline for the slow request log and the access log, which are written once
+/// the response is finalized
+async fn measure<T: Send + Sync + 'static>(
+    request_metadata: RequestMetadata,
+    version: Version,
+    remote_addr: SocketAddr,
+    config: Arc<RequestPipelineConfiguration<T>>,
+) -> Result<hyper::Response<BoxedBody>, ServerError> {
+    let started = Instant::now();
+    let method = request_metadata.method.clone();
+    let uri = request_metadata.uri.clone();
+    let referer = request_metadata.headers.get(REFERER).cloned();
+    let user_agent = request_metadata.headers.get(USER_AGENT).cloned();
+    let request_size = request_metadata
+        .body
+        .as_ref()
+        .and_then(|body| body.size_hint().exact());
+    let response = handle_cors(request_metadata, config.clone()).await?;
+    let latency = started.elapsed();
+    let response_size = response.body().size_hint().exact();
+
+    if let Some(slow_request_log) = config.slow_request_log.as_ref() {
+        slow_request_log.log(&RequestMeasurement {
+            method: &method,
+            path: uri.path(),
+            latency,
+            request_size,
+            response_size,
+        });
+    }
+    if let Some(access_log) = config.access_log {
+        let subject = response.extensions().get::<AuthenticatedSubject>();
+        access_log.log(&AccessLogEntry {
+            time: Utc::now(),
+            client_ip: remote_addr.ip(),
+            method: &method,
+            uri: &uri,
+            version,
+            status: response.status(),
+            latency,
+            subject: subject.map(|subject| subject.0.as_str()),
+            response_size,
+            referer: referer.as_ref().and_then(|value| value.to_str().ok()),
+            user_agent: user_agent.as_ref().and_then(|value| value.to_str().ok()),
+        });
+    }
+    Ok(response)
+}
+
+/// Headers already in the response, set by the handler, the middlewares or CORS, are kept
+fn add_default_headers(default_headers: &HeaderMap, headers: &mut HeaderMap) {
+    for name in default_headers.keys() {
+        if !headers.contains_key(name) {
+            for value in default_headers.get_all(name) {
+                headers.append(name, value.clone());
+            }
+        }
+    }
+}
+
+async fn handle_cors<T: Send + Sync + 'static>(
+    request_metadata: RequestMetadata,
     config: Arc<RequestPipelineConfiguration<T>>,
-) -> Result<hyper::Response<Full<Bytes>>, ServerError> {
-    let request_metadata: RequestMetadata = request.into();
+) -> Result<hyper::Response<BoxedBody>, ServerError> {
+    let cors = match config.cors.as_ref() {
+        Some(cors) => cors,
+        None => return process_request(request_metadata, config).await,
+    };
+
+    // Preflight requests are answered before reaching security, as browsers send them without
+    // credentials
+    if cors.is_preflight(&request_metadata.method, &request_metadata.headers) {
+        return cors
+            .preflight_response(&request_metadata.headers)
+            .try_into();
+    }
+
+    let origin = request_metadata.headers.get(ORIGIN).cloned();
+    let mut response = process_request(request_metadata, config.clone()).await?;
+    if let Some(origin) = origin {
+        cors.add_response_headers(&origin, response.headers_mut());
+    }
+    Ok(response)
+}
+
+async fn process_request<T: Send + Sync + 'static>(
+    mut request_metadata: RequestMetadata,
+    config: Arc<RequestPipelineConfiguration<T>>,
+) -> Result<hyper::Response<BoxedBody>, ServerError> {
+    if !config.ready.load(Ordering::Acquire) {
+        let error = RequestError::with_message(
+            ErrorType::ServiceUnavailable,
+            "Application is not ready yet",
+        );
+        let response = error::error_response(&config.error_configuration, error, &request_metadata);
+        return response.try_into();
+    }
 
     // First, we check if the request is authorized
-    let auth_result = config.security_configuration.authorize(&request_metadata);
-    if auth_result == AuthResult::Denied {
-        let response: Response =
-            RequestError::with_message(ErrorType::Unauthorized, request_metadata.uri.path()).into();
+    let security_configuration = config.security_configuration.current();
+    let auth_result = authorize(
+        &config.router,
+        &security_configuration,
+        &mut request_metadata,
+    );
+    let auth_error = match auth_result {
+        AuthResult::Denied => Some(ErrorType::Unauthorized),
+        AuthResult::Forbidden => Some(ErrorType::Forbidden),
+        _ => None,
+    };
+    if let Some(auth_error) = auth_error {
+        let error = RequestError::with_message(auth_error, request_metadata.uri.path());
+        let mut response =
+            error::error_response(&config.error_configuration, error, &request_metadata);
+        // Tells the client how to authenticate, like browsers prompting for Basic credentials
+        if auth_result == AuthResult::Denied {
+            if let Some(challenge) = security_configuration.challenge(&request_metadata) {
+                response = response.add_header(WWW_AUTHENTICATE, &challenge);
+            }
+        }
         return response.try_into();
     }
 
-    // Second, we try to serve the request as a static file request
-    // If that fails, we go on normally to fulfill the request with our router
-    // Consider adding support for logging this types of requests
-    if let Some(response) = config.static_file_server.try_serve(&request_metadata).await {
-        return Ok(response);
+    // The auth result is consumed by the handler, so the subject is kept in the response for the
+    // access log
+    let subject = match config.access_log {
+        Some(_) => auth_result.subject().map(|subject| subject.to_string()),
+        None => None,
+    };
+
+    // Second, we try the sources of the resolution order for the path until one of them answers
+    // the request. By default static files are tried first and then the router
+    let mut response = resolve(request_metadata, auth_result, &config).await?;
+    if let Some(subject) = subject {
+        response
+            .extensions_mut()
+            .insert(AuthenticatedSubject(subject));
+    }
+    Ok(response)
+}
+
+async fn resolve<T: Send + Sync + 'static>(
+    request_metadata: RequestMetadata,
+    auth_result: AuthResult,
+    config: &RequestPipelineConfiguration<T>,
+) -> Result<hyper::Response<BoxedBody>, ServerError> {
+    let path = request_metadata.uri.path().to_owned();
+    let resolvers = config.resolution_order.resolvers(&path);
+    for resolver in resolvers {
+        match resolver {
+            #[cfg(feature = "static-files")]
+            Resolver::StaticFiles => {
+                if let Some(response) = config.static_file_server.try_serve(&request_metadata).await
+                {
+                    return Ok(response);
+                }
+            }
+            #[cfg(feature = "static-files")]
+            Resolver::SpaIndex(index) => {
+                if let Some(response) =
+                    static_file_server::try_serve_index(index, &request_metadata).await
+                {
+                    return Ok(response);
+                }
+            }
+            Resolver::Router => {
+                if config.router.has_route(&path) {
+                    return route(request_metadata, auth_result, config).await;
+                }
+            }
+        }
+    }
+
+    // Deep links of single page applications get their index once nothing else answered, so it
+    // never shadows a route
+    #[cfg(feature = "static-files")]
+    if resolvers.contains(&Resolver::StaticFiles) {
+        if let Some(response) = config
+            .static_file_server
+            .try_serve_fallback(&request_metadata)
+            .await
+        {
+            return Ok(response);
+        }
+    }
+
+    // None of the sources answered. The router answers with its own 404 when it is one of them, so
+    // the middlewares and the interceptor see it
+    if resolvers.contains(&Resolver::Router) {
+        return route(request_metadata, auth_result, config).await;
     }
+    let error = RequestError::with_message(ErrorType::NotFound, &path);
+    error::error_response(&config.error_configuration, error, &request_metadata).try_into()
+}
+
+async fn route<T: Send + Sync + 'static>(
+    request_metadata: RequestMetadata,
+    auth_result: AuthResult,
+    config: &RequestPipelineConfiguration<T>,
+) -> Result<hyper::Response<BoxedBody>, ServerError> {
+    // Reading the body consumes the metadata, so errors are answered from a copy without the body
+    let error_metadata = RequestMetadata::new(
+        request_metadata.method.clone(),
+        request_metadata.uri.clone(),
+        request_metadata.headers.clone(),
+    );
 
-    // Third, map the request_metadata into the request object that will be user visible
-    let internal_request_res = Request::from_metadata_and_auth(request_metadata, auth_result).await;
+    // First, map the request_metadata into the request object that will be user visible
+    let internal_request_res = if config
+        .router
+        .streams_body(&request_metadata.method, request_metadata.uri.path())
+    {
+        Ok(Request::from_metadata_streaming(request_metadata, auth_result))
+    } else {
+        let max_body_size = config
+            .router
+            .max_body_size(&request_metadata.method, request_metadata.uri.path());
+        Request::from_metadata_and_auth(request_metadata, auth_result, max_body_size).await
+    };
     if let Err(e) = internal_request_res {
-        let response: Response = RequestError::with_message(ErrorType::RequestBodyUnreadable, &e.to_string())
-            .into();
-        return response
-            .try_into();
+        let response = error::error_response(&config.error_configuration, e, &error_metadata);
+        return response.try_into();
     }
-    // Fourth, we execute the defined middlewares before reaching the router to get the request
-    let internal_request = config
-        .request_middleware
-        .process(internal_request_res.unwrap());
+    // Second, we execute the defined middlewares before reaching the router to get the request
+    let mut internal_request = internal_request_res.unwrap();
+    internal_request.set_shutdown_state(config.shutdown_state.clone());
+    let (internal_request, short_circuit) = config.request_middleware.process(internal_request);
+
+    // Third, use the router to get the REST request result, unless a middleware already answered
+    // it. We return the request from the run function because it will be different from the one
+    // we input, as the path variables are matched inside.
+    let (internal_request, response) = match short_circuit {
+        Some(response) => (internal_request, response),
+        None => config.router.run(internal_request, config.context.clone()),
+    };
 
-    // Fifth, use the router to get the REST request result
-    // We return the request from the run function because it will be different from the one we
-    // input, as the path variables are matched inside.
-    let (internal_request, response) = config.router.run(internal_request, config.context.clone());
+    // Fourth, let the response middlewares modify the response
+    let response = config
+        .response_middleware
+        .process(&internal_request, response);
 
     // Lastly, execute the configured response interceptor
-    (config.response_interceptor)(&internal_request, &response);
+    intercept(config.response_interceptor, &internal_request, &response);
+
+    // The middlewares and interceptor see the body uncompressed
+    #[cfg(feature = "compression")]
+    let response = response.compress_for(&internal_request.headers);
 
     response.try_into()
 }
+
+/// Public routes skip the security rules, whatever rules match them
+fn authorize<T: Send + Sync + 'static>(
+    router: &InternalRouter<T>,
+    security_configuration: &SecurityConfiguration,
+    request_metadata: &mut RequestMetadata,
+) -> AuthResult {
+    if router.is_public(&request_metadata.method, request_metadata.uri.path()) {
+        return AuthResult::Allowed;
+    }
+    security_configuration.authorize(request_metadata)
+}
+
+fn intercept(interceptor: fn(&Request, &Response), request: &Request, response: &Response) {
+    if !response.is_interceptor_skipped() {
+        interceptor(request, response);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::{Mutex, Once};
+
+    use hyper::{HeaderMap, Method, StatusCode, Uri};
+    use log::{LevelFilter, Log, Metadata, Record};
+
+    use crate::application::{self, ReadinessCheck};
+    use crate::request_matcher::MethodMatcher;
+    use crate::router::Router;
+    use crate::security::api_key::ApiKeyConfiguration;
+    use crate::security::security_configuration::{Authenticator, SecurityAction, SecurityRule};
+
+    use super::*;
+
+    static INTERCEPTED: AtomicUsize = AtomicUsize::new(0);
+
+    /// Logger that keeps the messages of the whole test binary, so tests can check what the
+    /// pipeline logs. Tests look for the lines of their own paths, as they run in parallel
+    struct CapturedLogs(Mutex<Vec<String>>);
+
+    impl Log for CapturedLogs {
+        fn enabled(&self, _: &Metadata) -> bool {
+            true
+        }
+
+        fn log(&self, record: &Record) {
+            self.0.lock().unwrap().push(record.args().to_string());
+        }
+
+        fn flush(&self) {}
+    }
+
+    static CAPTURED_LOGS: CapturedLogs = CapturedLogs(Mutex::new(Vec::new()));
+
+    fn captured_logs(path: &str) -> Vec<String> {
+        static INSTALL_LOGGER: Once = Once::new();
+        INSTALL_LOGGER.call_once(|| {
+            log::set_logger(&CAPTURED_LOGS).unwrap();
+            log::set_max_level(LevelFilter::Info);
+        });
+        CAPTURED_LOGS
+            .0
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|line| line.contains(path))
+            .cloned()
+            .collect()
+    }
+
+    fn pipeline<T: Send + Sync + 'static>(
+        router: Router<T>,
+        context: T,
+    ) -> RequestPipelineConfiguration<T> {
+        RequestPipelineConfiguration::new(
+            |_, _| {},
+            InternalRouter::from(router).unwrap(),
+            SecurityConfigurationHandle::default(),
+            RequestMiddleware::default(),
+            ResponseMiddleware::default(),
+            context,
+        )
+    }
+
+    /// Sends a GET request without a body through the whole pipeline
+    async fn get<T: Send + Sync + 'static>(
+        config: &Arc<RequestPipelineConfiguration<T>>,
+        path: &'static str,
+        headers: HeaderMap,
+    ) -> hyper::Response<BoxedBody> {
+        let metadata = RequestMetadata::new(Method::GET, Uri::from_static(path), headers);
+        let remote_addr = SocketAddr::from(([10, 0, 0, 7], 52000));
+        handle_metadata(metadata, Version::HTTP_11, remote_addr, config.clone())
+            .await
+            .unwrap()
+    }
+
+    #[test]
+    fn skip_interceptor_test() {
+        let request = Request::new(
+            Method::GET,
+            Uri::from_static("/health"),
+            String::new(),
+            HeaderMap::new(),
+            AuthResult::Allowed,
+        );
+        let interceptor: fn(&Request, &Response) = |_, _| {
+            INTERCEPTED.fetch_add(1, Ordering::SeqCst);
+        };
+
+        intercept(interceptor, &request, &Response::new(StatusCode::OK));
+        assert_eq!(INTERCEPTED.load(Ordering::SeqCst), 1);
+
+        intercept(
+            interceptor,
+            &request,
+            &Response::new(StatusCode::OK).skip_interceptor(),
+        );
+        assert_eq!(INTERCEPTED.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn public_route_test() {
+        let router = InternalRouter::from(
+            Router::<()>::base_path("/api")
+                .get("/health", |_, _| Response::new(StatusCode::OK))
+                .public()
+                .get("/health/details", |_, _| Response::new(StatusCode::OK))
+                .get("/orders", |_, _| Response::new(StatusCode::OK)),
+        )
+        .unwrap();
+        let security_configuration = SecurityConfiguration::new().add_rule(
+            SecurityRule::new()
+                .add_matcher(MethodMatcher::All, "/api/*")
+                .execute_action(SecurityAction::Deny),
+        );
+        let metadata = |path: &'static str| {
+            RequestMetadata::new(Method::GET, Uri::from_static(path), HeaderMap::new())
+        };
+
+        assert_eq!(
+            authorize(
+                &router,
+                &security_configuration,
+                &mut metadata("/api/health")
+            ),
+            AuthResult::Allowed
+        );
+        // Only the marked route is public, not the ones under it or next to it
+        assert_eq!(
+            authorize(
+                &router,
+                &security_configuration,
+                &mut metadata("/api/health/details")
+            ),
+            AuthResult::Denied
+        );
+        assert_eq!(
+            authorize(
+                &router,
+                &security_configuration,
+                &mut metadata("/api/orders")
+            ),
+            AuthResult::Denied
+        );
+    }
+
+    #[tokio::test]
+    #[cfg(feature = "static-files")]
+    async fn resolution_order_test() {
+        use std::fs;
+
+        use http_body_util::BodyExt;
+        use hyper::header::{HeaderValue, ACCEPT};
+
+        let public_folder = std::env::temp_dir().join("citrine_resolution_order_test");
+        fs::create_dir_all(&public_folder).unwrap();
+        fs::write(public_folder.join("about"), "static").unwrap();
+        fs::write(public_folder.join("index.html"), "spa").unwrap();
+
+        let config = |resolution_order: ResolutionOrder| {
+            let router = Router::<()>::new().get("/about", |_, _| {
+                Response::new(StatusCode::OK).text("router")
+            });
+            RequestPipelineConfiguration::new(
+                |_, _| {},
+                InternalRouter::from(router).unwrap(),
+                SecurityConfigurationHandle::default(),
+                RequestMiddleware::default(),
+                ResponseMiddleware::default(),
+                (),
+            )
+            .with_static_file_server(
+                StaticFileServer::new().serve_folder("/", public_folder.clone()),
+            )
+            .with_resolution_order(resolution_order)
+        };
+        let resolve_body = |config: RequestPipelineConfiguration<()>, path: &'static str| async move {
+            let mut headers = HeaderMap::new();
+            headers.insert(ACCEPT, HeaderValue::from_static("text/html"));
+            let metadata = RequestMetadata::new(Method::GET, Uri::from_static(path), headers);
+            let response = resolve(metadata, AuthResult::Allowed, &config)
+                .await
+                .unwrap();
+            let status = response.status();
+            let body = response.into_body().collect().await.unwrap().to_bytes();
+            (status, String::from_utf8(body.to_vec()).unwrap())
+        };
+        let spa_index = Resolver::SpaIndex(public_folder.join("index.html"));
+
+        let static_first = config(ResolutionOrder::default());
+        assert_eq!(
+            resolve_body(static_first, "/about").await,
+            (StatusCode::OK, "static".to_string())
+        );
+        let router_first = config(ResolutionOrder::new(vec![
+            Resolver::Router,
+            Resolver::StaticFiles,
+        ]));
+        assert_eq!(
+            resolve_body(router_first, "/about").await,
+            (StatusCode::OK, "router".to_string())
+        );
+
+        let without_spa = config(ResolutionOrder::default());
+        let (status, _) = resolve_body(without_spa, "/dashboard/settings").await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        let with_spa = config(ResolutionOrder::new(vec![
+            Resolver::StaticFiles,
+            Resolver::Router,
+            spa_index.clone(),
+        ]));
+        assert_eq!(
+            resolve_body(with_spa, "/dashboard/settings").await,
+            (StatusCode::OK, "spa".to_string())
+        );
+
+        // The SPA index is left out of the paths under /api, which get the router's 404
+        let per_prefix = config(
+            ResolutionOrder::new(vec![Resolver::StaticFiles, spa_index])
+                .for_prefix("/api", vec![Resolver::Router]),
+        );
+        let (status, _) = resolve_body(per_prefix, "/api/settings").await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+
+        fs::remove_dir_all(&public_folder).unwrap();
+    }
+
+    #[test]
+    fn default_headers_test() {
+        let mut default_headers = HeaderMap::new();
+        default_headers.insert("x-app-version", "1.2.0".parse().unwrap());
+        default_headers.insert("x-frame-options", "DENY".parse().unwrap());
+
+        let success: hyper::Response<BoxedBody> = Response::new(StatusCode::OK)
+            .add_header(hyper::header::X_FRAME_OPTIONS, "SAMEORIGIN")
+            .try_into()
+            .unwrap();
+        let error: hyper::Response<BoxedBody> =
+            Response::from(RequestError::with_message(ErrorType::NotFound, "/missing"))
+                .try_into()
+                .unwrap();
+        for (mut response, frame_options) in [(success, "SAMEORIGIN"), (error, "DENY")] {
+            add_default_headers(&default_headers, response.headers_mut());
+            assert_eq!(response.headers().get("x-app-version").unwrap(), "1.2.0");
+            // Headers set by the handler win over the default ones
+            assert_eq!(
+                response.headers().get("x-frame-options").unwrap(),
+                frame_options
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn probes_skip_security_test() {
+        let readiness_check: ReadinessCheck<AtomicBool> = |ready| ready.load(Ordering::Acquire);
+        let router = application::add_probes(
+            Router::new().get("/orders", |_, _| Response::new(StatusCode::OK)),
+            Some("/health"),
+            Some(("/readyz".to_string(), readiness_check)),
+        );
+        // Every request has to be authenticated with an API key
+        let security_configuration = SecurityConfiguration::new().add_rule(
+            SecurityRule::new()
+                .add_matcher(MethodMatcher::All, "/*")
+                .execute_action(SecurityAction::Authenticate(Authenticator::ApiKey(
+                    Box::new(ApiKeyConfiguration::new().add_key("ops", "0ps-s3cr3t")),
+                ))),
+        );
+        let config = Arc::new(RequestPipelineConfiguration::new(
+            |_, _| {},
+            InternalRouter::from(router).unwrap(),
+            SecurityConfigurationHandle::new(security_configuration),
+            RequestMiddleware::default(),
+            ResponseMiddleware::default(),
+            AtomicBool::new(false),
+        ));
+
+        let response = get(&config, "/orders", HeaderMap::new()).await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+        // Probes are answered without credentials
+        let response = get(&config, "/health", HeaderMap::new()).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let response = get(&config, "/readyz", HeaderMap::new()).await;
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        config.context.store(true, Ordering::Release);
+        let response = get(&config, "/readyz", HeaderMap::new()).await;
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn slow_request_log_test() {
+        // Installed before the request, so its entry is captured
+        captured_logs("/reports/slow");
+        let router = Router::<()>::new().get("/reports/slow", |_, _| {
+            std::thread::sleep(Duration::from_millis(50));
+            Response::new(StatusCode::OK)
+        });
+        let config = Arc::new(pipeline(router, ()).with_slow_request_log(Some(
+            SlowRequestLog::new().latency(Duration::from_millis(20)),
+        )));
+
+        let response = get(&config, "/reports/slow", HeaderMap::new()).await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let entries = captured_logs("/reports/slow");
+        assert_eq!(entries.len(), 1);
+        assert!(entries[0].starts_with("Slow request GET /reports/slow took"));
+        assert!(entries[0].ends_with("over the threshold of 20 ms"));
+    }
+
+    #[tokio::test]
+    async fn access_log_test() {
+        use hyper::header::HeaderValue;
+
+        // Installed before the requests, so their entries are captured
+        captured_logs("/audit/entries");
+        let router = Router::<()>::new().get("/audit/entries", |_, _| {
+            std::thread::sleep(Duration::from_millis(10));
+            Response::new(StatusCode::OK)
+        });
+        let security_configuration = SecurityConfiguration::new().add_rule(
+            SecurityRule::new()
+                .add_matcher(MethodMatcher::All, "/audit/*")
+                .execute_action(SecurityAction::Authenticate(Authenticator::ApiKey(
+                    Box::new(ApiKeyConfiguration::new().add_key("auditor", "4ud1t-k3y")),
+                ))),
+        );
+        let config = Arc::new(
+            RequestPipelineConfiguration::new(
+                |_, _| {},
+                InternalRouter::from(router).unwrap(),
+                SecurityConfigurationHandle::new(security_configuration),
+                RequestMiddleware::default(),
+                ResponseMiddleware::default(),
+                (),
+            )
+            .with_access_log(Some(AccessLogFormat::Json)),
+        );
+
+        let response = get(&config, "/audit/entries", HeaderMap::new()).await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        let mut headers = HeaderMap::new();
+        headers.insert("x-api-key", HeaderValue::from_static("4ud1t-k3y"));
+        let response = get(&config, "/audit/entries", headers).await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let entries: Vec<serde_json::Value> = captured_logs("/audit/entries")
+            .iter()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0]["status"], 401);
+        assert!(entries[0]["subject"].is_null());
+        assert_eq!(entries[1]["status"], 200);
+        assert_eq!(entries[1]["subject"], "auditor");
+        assert_eq!(entries[1]["client_ip"], "10.0.0.7");
+        assert!(entries[1]["latency_ms"].as_f64().unwrap() >= 10.0);
+    }
+
+    #[tokio::test]
+    async fn readiness_gate_test() {
+        let router = Router::<()>::new().get("/hello", |_, _| Response::new(StatusCode::OK));
+        let config = Arc::new(pipeline(router, ()));
+        let (ready_tx, ready_rx) = tokio::sync::oneshot::channel::<()>();
+        gate_readiness(
+            &config,
+            Box::pin(async move {
+                let _ = ready_rx.await;
+            }),
+        );
+
+        let response = get(&config, "/hello", HeaderMap::new()).await;
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+
+        ready_tx.send(()).unwrap();
+        tokio::time::timeout(Duration::from_secs(1), async {
+            while !config.ready.load(Ordering::Acquire) {
+                tokio::task::yield_now().await;
+            }
+        })
+        .await
+        .unwrap();
+        let response = get(&config, "/hello", HeaderMap::new()).await;
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+}
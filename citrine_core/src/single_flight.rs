@@ -0,0 +1,176 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Condvar, Mutex},
+};
+
+use tokio::runtime::{Handle, RuntimeFlavor};
+
+use crate::{request::Request, response::Response};
+
+/// Key that identifies identical requests for single-flight routes
+pub type SingleFlightKey = fn(&Request) -> String;
+
+/// Default key, requests with the same method, path and query are identical
+pub fn method_and_path_key(request: &Request) -> String {
+    format!("{} {}", request.method, request.full_path_and_query())
+}
+
+/// Handler executions in progress for a single-flight route. Requests with the same key that
+/// arrive while one is running wait for it and get a copy of its response instead of running the
+/// handler again
+#[derive(Default)]
+pub(crate) struct SingleFlight {
+    in_flight: Mutex<HashMap<String, Arc<Call>>>,
+}
+
+#[derive(Default)]
+struct Call {
+    // None while the handler runs. Then the response to share, or None if it can't be shared
+    result: Mutex<Option<Option<Response>>>,
+    done: Condvar,
+}
+
+impl SingleFlight {
+    pub(crate) fn run(&self, key: String, handler: impl FnOnce() -> Response) -> Response {
+        let mut in_flight = self.in_flight.lock().unwrap();
+        if let Some(call) = in_flight.get(&key).cloned() {
+            drop(in_flight);
+            let shared = call.wait();
+            return match shared {
+                Some(response) => response,
+                // Streamed responses can't be copied, so the handler is run again
+                None => handler(),
+            };
+        }
+
+        let call = Arc::new(Call::default());
+        in_flight.insert(key.clone(), call.clone());
+        drop(in_flight);
+
+        // Waiters are released even if the handler panics
+        let mut finish = FinishGuard {
+            single_flight: self,
+            key,
+            call,
+            shared: None,
+        };
+        let response = handler();
+        finish.shared = response.try_clone();
+        response
+    }
+}
+
+impl Call {
+    /// Handlers are synchronous, so waiting blocks the thread. On a multi-threaded runtime the
+    /// worker hands its other tasks over to another thread before blocking
+    fn wait(&self) -> Option<Response> {
+        match Handle::try_current() {
+            Ok(handle) if handle.runtime_flavor() == RuntimeFlavor::MultiThread => {
+                tokio::task::block_in_place(|| self.wait_blocking())
+            }
+            _ => self.wait_blocking(),
+        }
+    }
+
+    fn wait_blocking(&self) -> Option<Response> {
+        let mut result = self.result.lock().unwrap();
+        while result.is_none() {
+            result = self.done.wait(result).unwrap();
+        }
+        result
+            .as_ref()
+            .unwrap()
+            .as_ref()
+            .and_then(|response| response.try_clone())
+    }
+}
+
+struct FinishGuard<'a> {
+    single_flight: &'a SingleFlight,
+    key: String,
+    call: Arc<Call>,
+    shared: Option<Response>,
+}
+
+impl Drop for FinishGuard<'_> {
+    fn drop(&mut self) {
+        if let Ok(mut in_flight) = self.single_flight.in_flight.lock() {
+            in_flight.remove(&self.key);
+        }
+        if let Ok(mut result) = self.call.result.lock() {
+            *result = Some(self.shared.take());
+        }
+        self.call.done.notify_all();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            mpsc,
+        },
+        thread,
+    };
+
+    use hyper::StatusCode;
+
+    use crate::response::ResponseBody;
+
+    use super::*;
+
+    const KEY: &str = "GET /reports/7";
+
+    #[test]
+    fn identical_calls_share_response_test() {
+        let single_flight = Arc::new(SingleFlight::default());
+        let executions = Arc::new(AtomicUsize::new(0));
+        let (started_tx, started_rx) = mpsc::channel();
+        let (release_tx, release_rx) = mpsc::channel::<()>();
+
+        let leader = {
+            let single_flight = single_flight.clone();
+            let executions = executions.clone();
+            thread::spawn(move || {
+                single_flight.run(KEY.to_string(), || {
+                    executions.fetch_add(1, Ordering::SeqCst);
+                    started_tx.send(()).unwrap();
+                    release_rx.recv().unwrap();
+                    Response::new(StatusCode::OK).json("7")
+                })
+            })
+        };
+        started_rx.recv().unwrap();
+        let call = single_flight.in_flight.lock().unwrap()[KEY].clone();
+
+        let waiters: Vec<_> = (0..7)
+            .map(|_| {
+                let single_flight = single_flight.clone();
+                let executions = executions.clone();
+                thread::spawn(move || {
+                    single_flight.run(KEY.to_string(), || {
+                        executions.fetch_add(1, Ordering::SeqCst);
+                        Response::new(StatusCode::OK)
+                    })
+                })
+            })
+            .collect();
+        // The in-flight map, the running call and this test hold a reference each, and every
+        // waiter holds one more until it gets the response
+        while Arc::strong_count(&call) < 3 + waiters.len() {
+            thread::yield_now();
+        }
+        release_tx.send(()).unwrap();
+
+        for handle in std::iter::once(leader).chain(waiters) {
+            let response = handle.join().unwrap();
+            assert_eq!(response.get_status(), StatusCode::OK);
+            assert!(
+                matches!(response.get_body(), Some(ResponseBody::Full(body)) if body == "\"7\"")
+            );
+        }
+        assert_eq!(executions.load(Ordering::SeqCst), 1);
+        assert!(single_flight.in_flight.lock().unwrap().is_empty());
+    }
+}
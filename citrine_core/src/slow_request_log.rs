@@ -0,0 +1,116 @@
+use std::time::Duration;
+
+use hyper::Method;
+use log::warn;
+
+/// Thresholds over which requests are logged as a warning, to find the slow or heavy endpoints of
+/// an application in production. Every threshold is optional
+#[derive(Debug, Clone, Default)]
+pub struct SlowRequestLog {
+    latency: Option<Duration>,
+    request_size: Option<u64>,
+    response_size: Option<u64>,
+}
+
+/// What was measured of a request once its response is ready
+pub(crate) struct RequestMeasurement<'a> {
+    pub method: &'a Method,
+    pub path: &'a str,
+    pub latency: Duration,
+    /// Declared size of the request body, if known
+    pub request_size: Option<u64>,
+    /// Size of the response body, unknown for streamed bodies
+    pub response_size: Option<u64>,
+}
+
+impl SlowRequestLog {
+    pub fn new() -> Self {
+        SlowRequestLog::default()
+    }
+
+    /// Time from receiving the request to having its response ready
+    pub fn latency(mut self, threshold: Duration) -> Self {
+        self.latency = Some(threshold);
+        self
+    }
+
+    /// Bytes of the request body
+    pub fn request_size(mut self, threshold: u64) -> Self {
+        self.request_size = Some(threshold);
+        self
+    }
+
+    /// Bytes of the response body. Streamed responses are not checked, as their size is not known
+    /// until they are sent
+    pub fn response_size(mut self, threshold: u64) -> Self {
+        self.response_size = Some(threshold);
+        self
+    }
+
+    pub(crate) fn log(&self, measurement: &RequestMeasurement) {
+        for entry in self.entries(measurement) {
+            warn!("{}", entry);
+        }
+    }
+
+    fn entries(&self, measurement: &RequestMeasurement) -> Vec<String> {
+        let mut entries = vec![];
+        if let Some(threshold) = self.latency {
+            if measurement.latency > threshold {
+                entries.push(format!(
+                    "Slow request {} {} took {} ms, over the threshold of {} ms",
+                    measurement.method,
+                    measurement.path,
+                    measurement.latency.as_millis(),
+                    threshold.as_millis()
+                ));
+            }
+        }
+        if let (Some(threshold), Some(size)) = (self.request_size, measurement.request_size) {
+            if size > threshold {
+                entries.push(format!(
+                    "Large request {} {} with a body of {} bytes, over the threshold of {} bytes",
+                    measurement.method, measurement.path, size, threshold
+                ));
+            }
+        }
+        if let (Some(threshold), Some(size)) = (self.response_size, measurement.response_size) {
+            if size > threshold {
+                entries.push(format!(
+                    "Large response to {} {} with a body of {} bytes, over the threshold of {} bytes",
+                    measurement.method, measurement.path, size, threshold
+                ));
+            }
+        }
+        entries
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn slow_request_entries_test() {
+        let slow_request_log = SlowRequestLog::new()
+            .latency(Duration::from_millis(10))
+            .response_size(1024);
+        let measurement = RequestMeasurement {
+            method: &Method::GET,
+            path: "/reports",
+            latency: Duration::from_millis(30),
+            request_size: None,
+            response_size: Some(512),
+        };
+        let entries = slow_request_log.entries(&measurement);
+        assert_eq!(entries.len(), 1);
+        assert!(entries[0].starts_with("Slow request GET /reports took"));
+
+        // Requests under the thresholds are not logged
+        let measurement = RequestMeasurement {
+            latency: Duration::from_millis(5),
+            ..measurement
+        };
+        assert!(slow_request_log.entries(&measurement).is_empty());
+    }
+}
@@ -1,10 +1,39 @@
-use std::path::PathBuf;
+use std::path::{Component, Path, PathBuf};
 
-use http_body_util::{BodyExt, Full};
-use hyper::{body::Bytes, Method, StatusCode};
+use chrono::{DateTime, FixedOffset};
+use http_body_util::BodyExt;
+use hyper::body::Bytes;
+use hyper::header::{
+    HeaderName, HeaderValue, ACCEPT_ENCODING, CACHE_CONTROL, CONTENT_ENCODING, CONTENT_TYPE, ETAG,
+    IF_MODIFIED_SINCE, IF_NONE_MATCH, IF_RANGE, LAST_MODIFIED, RANGE, VARY,
+};
+use hyper::{HeaderMap, Method, StatusCode};
 use hyper_staticfile::Static;
+use log::error;
 
+use crate::error::{ErrorType, RequestError};
 use crate::request::RequestMetadata;
+use crate::response::{self, BoxedBody, Response};
+use crate::util;
+
+/// Request headers forwarded to the file server, which answers them with a 206 Partial Content
+/// with the requested range. Conditional requests are answered here instead, as the 304 of the
+/// file server has neither the ETag nor the Last-Modified of the file
+const FORWARDED_HEADERS: [HeaderName; 2] = [RANGE, IF_RANGE];
+
+/// Extensions of the precompressed siblings of a file, like app.js.br, with their Content-Encoding
+/// in order of preference
+const PRECOMPRESSED: [(&str, &str); 2] = [("br", "br"), ("gz", "gzip")];
+
+/// Precompressed siblings of a requested file
+#[derive(Default)]
+struct PrecompressedSiblings {
+    /// Whether the file has any, so all of its responses vary with the Accept-Encoding of the
+    /// request, the plain one included
+    exist: bool,
+    /// Extension and encoding of the first one the client accepts
+    accepted: Option<(&'static str, &'static str)>,
+}
 
 /// Contains a map of folders, with the key being the base_url and 
 #[derive(Default, Clone)]
@@ -22,7 +51,43 @@ impl StaticFileServer {
         self
     }
 
-    pub async fn try_serve(&self, request: &RequestMetadata) -> Option<hyper::Response<Full<Bytes>>> {
+    /// Serves a folder configured beforehand, for mounts that need their own settings like custom
+    /// headers
+    pub fn add_folder(mut self, folder: ServedFolder) -> Self {
+        self.folders.push(folder);
+        self
+    }
+
+    /// Index file of a single page application for the last served folder, see
+    /// ServedFolder::fallback
+    pub fn fallback(mut self, index: PathBuf) -> Self {
+        if let Some(folder) = self.folders.pop() {
+            self.folders.push(folder.fallback(index));
+        }
+        self
+    }
+
+    /// Serves the folder under /.well-known (RFC 8615), for files like ACME challenges or
+    /// security.txt
+    pub fn serve_well_known(self, folder: PathBuf) -> Self {
+        self.serve_folder("/.well-known", folder)
+    }
+
+    pub fn validate(&self) -> Vec<String> {
+        self.folders
+            .iter()
+            .filter(|folder| !folder.folder.is_dir())
+            .map(|folder| {
+                format!(
+                    "Static folder {} served in {} does not exist or is not a directory",
+                    folder.folder.display(),
+                    folder.url_base_path
+                )
+            })
+            .collect()
+    }
+
+    pub async fn try_serve(&self, request: &RequestMetadata) -> Option<hyper::Response<BoxedBody>> {
         if request.method != Method::GET {
             return None;
         }
@@ -37,63 +102,825 @@ impl StaticFileServer {
 
         None
     }
+
+    /// Index of the first folder with a fallback whose URL prefix contains the path. Only tried
+    /// once the files and the routes didn't answer the request, so it never shadows a route
+    pub(crate) async fn try_serve_fallback(
+        &self,
+        request: &RequestMetadata,
+    ) -> Option<hyper::Response<BoxedBody>> {
+        let path = request.uri.path();
+        let folder = self
+            .folders
+            .iter()
+            .find(|folder| folder.fallback.is_some() && folder.contains(path))?;
+        try_serve_index(folder.fallback.as_ref()?, request).await
+    }
+}
+
+/// How long browsers and proxies can cache the files of a folder
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CacheControl {
+    /// Revalidated on every use, for files like index.html that change without changing their name
+    NoCache,
+    /// Cached for the seconds before being revalidated
+    MaxAge(u64),
+    /// Cached for the seconds and never revalidated, for bundles with a hash of their content in
+    /// their name
+    Immutable(u64),
+}
+
+impl CacheControl {
+    fn header_value(&self) -> HeaderValue {
+        match self {
+            CacheControl::NoCache => HeaderValue::from_static("no-cache"),
+            CacheControl::MaxAge(seconds) => {
+                HeaderValue::from_str(&format!("public, max-age={}", seconds)).unwrap()
+            }
+            CacheControl::Immutable(seconds) => {
+                HeaderValue::from_str(&format!("public, max-age={}, immutable", seconds)).unwrap()
+            }
+        }
+    }
 }
 
 #[derive(Clone)]
 pub struct ServedFolder {
     url_base_path: String,
-    server: Static
+    folder: PathBuf,
+    server: Static,
+    headers: HeaderMap,
+    fallback: Option<PathBuf>,
+    directory_listing: bool,
 }
 
 impl ServedFolder {
     pub fn new(url_base_path: &str, folder: PathBuf) -> Self {
-        ServedFolder { url_base_path: url_base_path.to_string(), server: Static::new(folder) }
+        ServedFolder {
+            url_base_path: url_base_path.to_string(),
+            server: Static::new(folder.clone()),
+            folder,
+            headers: HeaderMap::new(),
+            fallback: None,
+            directory_listing: false,
+        }
     }
 
-    pub async fn try_serve(&self, request: &RequestMetadata) -> Option<hyper::Response<Full<Bytes>>> {
-        let new_uri = hyper::Uri::builder()
-            .path_and_query(
-                request
-                    .uri
-                    .path()
-                    .strip_prefix(&self.url_base_path)
-                    .unwrap_or(""),
-            )
-            .build();
+    /// Index file of a single page application, served with a 200 to the GET requests that accept
+    /// HTML for paths under the folder that have no file and no extension, so deep links like
+    /// /dashboard/settings reach the client side router
+    pub fn fallback(mut self, index: PathBuf) -> Self {
+        self.fallback = Some(index);
+        self
+    }
+
+    /// Prefixes match whole segments, so /app doesn't contain /apps
+    fn contains(&self, path: &str) -> bool {
+        let base_path = self.url_base_path.trim_end_matches('/');
+        match path.strip_prefix(base_path) {
+            Some(rest) => rest.is_empty() || rest.starts_with('/'),
+            None => false,
+        }
+    }
+
+    /// Adds a header to every file served from the folder, like Cross-Origin-Resource-Policy or
+    /// Access-Control-Allow-Origin for fonts loaded from other origins
+    pub fn with_header(mut self, name: HeaderName, value: &str) -> Self {
+        let value = HeaderValue::from_str(value).unwrap();
+        self.headers.insert(name, value);
+        self
+    }
+
+    /// Cache-Control of every file served from the folder, including its 304 Not Modified
+    /// responses
+    pub fn cache_control(mut self, cache_control: CacheControl) -> Self {
+        self.headers
+            .insert(CACHE_CONTROL, cache_control.header_value());
+        self
+    }
+
+    /// Answers the requests for directories without an index.html with an HTML page listing their
+    /// entries. Off by default, as it exposes every file of the folder
+    pub fn with_directory_listing(mut self, directory_listing: bool) -> Self {
+        self.directory_listing = directory_listing;
+        self
+    }
+
+    /// Returns None when there is no file to serve for the request, so it can fall through to the
+    /// router. Errors opening a file that does exist are logged and answered with a 500 instead.
+    /// A precompressed sibling of the file, like app.js.br or app.js.gz, is served instead when the
+    /// client accepts its encoding, with the Content-Type of the original file. Every response of
+    /// a file with precompressed siblings has a Vary: accept-encoding header for caches
+    pub async fn try_serve(&self, request: &RequestMetadata) -> Option<hyper::Response<BoxedBody>> {
+        let path = request
+            .uri
+            .path()
+            .strip_prefix(&self.url_base_path)
+            .unwrap_or("");
+        let siblings = self.precompressed_siblings(path, &request.headers).await;
+        let served_path = match siblings.accepted {
+            Some((extension, _)) => format!("{}.{}", path, extension),
+            None => path.to_string(),
+        };
+        let new_uri = hyper::Uri::builder().path_and_query(served_path).build();
         if new_uri.is_err() {
             return None;
         }
 
-        let static_file_request = hyper::Request::builder()
+        let mut static_file_request = hyper::Request::builder()
             .method(Method::GET)
-            .uri(new_uri.unwrap())
-            .body(());
+            .uri(new_uri.unwrap());
+        for name in FORWARDED_HEADERS {
+            for value in request.headers.get_all(&name) {
+                static_file_request = static_file_request.header(&name, value);
+            }
+        }
+        let static_file_request = static_file_request.body(());
         if static_file_request.is_err() {
             return None;
         }
 
         let static_file_result = self.server.clone().serve(static_file_request.unwrap()).await;
-        if static_file_result.is_err() {
-            return None;
+        if let Err(e) = static_file_result {
+            error!("Error serving static file {}: {}", request.uri.path(), e);
+            return Self::internal_error(request);
         }
         let static_file_response = static_file_result.unwrap();
-        let (parts, body) = static_file_response.into_parts();
+        let (mut parts, body) = static_file_response.into_parts();
 
-        if parts.status != StatusCode::OK {
-            return None;
+        if parts.status == StatusCode::FORBIDDEN {
+            error!(
+                "Permission denied reading static file {}",
+                request.uri.path()
+            );
+            return Self::internal_error(request);
+        }
+
+        for (name, value) in self.headers.iter() {
+            parts.headers.insert(name, value.clone());
+        }
+        if siblings.exist {
+            parts
+                .headers
+                .insert(VARY, HeaderValue::from_static("accept-encoding"));
+        }
+
+        if parts.status == StatusCode::OK && is_not_modified(&request.headers, &parts.headers) {
+            parts.status = StatusCode::NOT_MODIFIED;
+        }
+
+        match parts.status {
+            // Partial Content carries the Content-Range of the part
+            StatusCode::OK | StatusCode::PARTIAL_CONTENT | StatusCode::RANGE_NOT_SATISFIABLE => {}
+            // The client already has the current version of the file, so it is answered with the
+            // ETag and Last-Modified of the file and no body
+            StatusCode::NOT_MODIFIED => {
+                return Some(hyper::Response::from_parts(
+                    parts,
+                    response::full_body(Bytes::new()),
+                ));
+            }
+            _ if self.directory_listing => return self.try_list_directory(request).await,
+            _ => return None,
+        }
+
+        if let Some((_, encoding)) = siblings.accepted {
+            let content_type = mime_guess::from_path(path).first_or_octet_stream();
+            if let Ok(content_type) = HeaderValue::from_str(content_type.as_ref()) {
+                parts.headers.insert(CONTENT_TYPE, content_type);
+            }
+            parts
+                .headers
+                .insert(CONTENT_ENCODING, HeaderValue::from_static(encoding));
+        }
+
+        // Files are streamed, so large downloads and ranges of videos are not held in memory
+        Some(hyper::Response::from_parts(
+            parts,
+            body.map_err(Into::into).boxed_unsync(),
+        ))
+    }
+
+    /// Precompressed siblings of the file, and the first of them that the client accepts
+    async fn precompressed_siblings(
+        &self,
+        path: &str,
+        request_headers: &HeaderMap,
+    ) -> PrecompressedSiblings {
+        let mut siblings = PrecompressedSiblings::default();
+        let file = match self.file_path(path) {
+            Some(file) if !path.ends_with('/') => file,
+            _ => return siblings,
+        };
+        for (extension, encoding) in PRECOMPRESSED {
+            let mut sibling = file.clone().into_os_string();
+            sibling.push(format!(".{}", extension));
+            if !tokio::fs::metadata(&sibling)
+                .await
+                .is_ok_and(|metadata| metadata.is_file())
+            {
+                continue;
+            }
+            siblings.exist = true;
+            if siblings.accepted.is_none() && accepts_encoding(request_headers, encoding) {
+                siblings.accepted = Some((extension, encoding));
+            }
         }
+        siblings
+    }
+
+    /// Path in the folder of a path relative to the URL base path. None if any of its segments is
+    /// not a plain name, like .. or an absolute path, so it can't leave the folder
+    fn file_path(&self, relative_path: &str) -> Option<PathBuf> {
+        let mut file_path = self.folder.clone();
+        for segment in relative_path.split('/') {
+            if segment.is_empty() {
+                continue;
+            }
+            let segment = percent_decode(segment)?;
+            let mut components = Path::new(&segment).components();
+            match (components.next(), components.next()) {
+                (Some(Component::Normal(_)), None) => file_path.push(segment),
+                _ => return None,
+            }
+        }
+        Some(file_path)
+    }
 
-        // Convert the body to Bytes
-        let body_bytes_res = body.collect().await;
-        if body_bytes_res.is_err() {
+    /// HTML page with links to the entries of the requested directory. Paths with components other
+    /// than plain names, or that resolve outside the folder through a symlink, are not listed
+    async fn try_list_directory(
+        &self,
+        request: &RequestMetadata,
+    ) -> Option<hyper::Response<BoxedBody>> {
+        let path = request.uri.path();
+        let directory = self.file_path(path.strip_prefix(&self.url_base_path)?)?;
+
+        let root = tokio::fs::canonicalize(&self.folder).await.ok()?;
+        let directory = tokio::fs::canonicalize(&directory).await.ok()?;
+        if !directory.starts_with(&root) || !directory.is_dir() {
             return None;
         }
-        let body_bytes = body_bytes_res.unwrap();
 
-        // Convert the Bytes into a Full<Bytes>
-        let full_body = Full::from(body_bytes.to_bytes());
+        let mut read_dir = match tokio::fs::read_dir(&directory).await {
+            Ok(read_dir) => read_dir,
+            Err(e) => {
+                error!("Error listing directory {}: {}", directory.display(), e);
+                return Self::internal_error(request);
+            }
+        };
+        let mut entries = vec![];
+        while let Ok(Some(entry)) = read_dir.next_entry().await {
+            let is_dir = entry
+                .file_type()
+                .await
+                .map(|file_type| file_type.is_dir())
+                .unwrap_or(false);
+            entries.push((entry.file_name().to_string_lossy().into_owned(), is_dir));
+        }
+        entries.sort();
 
-        Some(hyper::Response::from_parts(parts, full_body))
+        let html = directory_listing_html(path, directory == root, &entries);
+        let mut response = hyper::Response::new(response::full_body(html.into()));
+        response.headers_mut().insert(
+            CONTENT_TYPE,
+            HeaderValue::from_static(mime::TEXT_HTML_UTF_8.as_ref()),
+        );
+        for (name, value) in self.headers.iter() {
+            response.headers_mut().insert(name, value.clone());
+        }
+        Some(response)
+    }
+
+    fn internal_error(request: &RequestMetadata) -> Option<hyper::Response<BoxedBody>> {
+        let response: Response =
+            RequestError::with_message(ErrorType::Internal, request.uri.path()).into();
+        response.try_into().ok()
+    }
+}
+
+/// Whether the client already has the current version of the file. If-Modified-Since is ignored
+/// when the request has an If-None-Match, as RFC 9110 asks
+fn is_not_modified(request_headers: &HeaderMap, file_headers: &HeaderMap) -> bool {
+    if request_headers.contains_key(IF_NONE_MATCH) {
+        return matches_etag(request_headers, file_headers);
+    }
+    match (
+        http_date_header(file_headers, LAST_MODIFIED),
+        http_date_header(request_headers, IF_MODIFIED_SINCE),
+    ) {
+        (Some(last_modified), Some(if_modified_since)) => last_modified <= if_modified_since,
+        _ => false,
     }
 }
 
+fn http_date_header(headers: &HeaderMap, name: HeaderName) -> Option<DateTime<FixedOffset>> {
+    let value = headers.get(name)?.to_str().ok()?;
+    DateTime::parse_from_rfc2822(value).ok()
+}
+
+/// Whether any of the If-None-Match tags matches the ETag of the file, using the weak comparison
+fn matches_etag(request_headers: &HeaderMap, file_headers: &HeaderMap) -> bool {
+    let Some(etag) = file_headers.get(ETAG).and_then(|etag| etag.to_str().ok()) else {
+        return false;
+    };
+    let etag = etag.trim_start_matches("W/");
+    request_headers
+        .get_all(IF_NONE_MATCH)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(|tag| tag.trim())
+        .any(|tag| tag == "*" || tag.trim_start_matches("W/") == etag)
+}
+
+/// Index file of a single page application, for GET requests that accept HTML and whose path has
+/// no extension. Requests for missing assets, like /app.js, are left to get a 404
+pub(crate) async fn try_serve_index(
+    index: &Path,
+    request: &RequestMetadata,
+) -> Option<hyper::Response<BoxedBody>> {
+    if request.method != Method::GET
+        || !util::accepts_html(&request.headers)
+        || Path::new(request.uri.path()).extension().is_some()
+    {
+        return None;
+    }
+
+    match tokio::fs::read(index).await {
+        Ok(html) => {
+            let mut response = hyper::Response::new(response::full_body(html.into()));
+            response.headers_mut().insert(
+                CONTENT_TYPE,
+                HeaderValue::from_static(mime::TEXT_HTML_UTF_8.as_ref()),
+            );
+            Some(response)
+        }
+        Err(e) => {
+            error!("Error reading index file {}: {}", index.display(), e);
+            ServedFolder::internal_error(request)
+        }
+    }
+}
+
+/// Links are absolute, built from the requested path, so they work with or without a trailing
+/// slash and keep the URL base path of the folder
+fn directory_listing_html(path: &str, is_root: bool, entries: &[(String, bool)]) -> String {
+    let directory_path = format!("{}/", path.trim_end_matches('/'));
+    let mut items = String::new();
+    if !is_root {
+        let parent = directory_path.trim_end_matches('/');
+        let parent = &parent[..=parent.rfind('/').unwrap_or(0)];
+        items.push_str(&format!(
+            "<li><a href=\"{}\">../</a></li>\n",
+            html_escape(parent)
+        ));
+    }
+    for (name, is_dir) in entries {
+        let suffix = if *is_dir { "/" } else { "" };
+        items.push_str(&format!(
+            "<li><a href=\"{}{}{}\">{}{}</a></li>\n",
+            html_escape(&directory_path),
+            percent_encode(name),
+            suffix,
+            html_escape(name),
+            suffix
+        ));
+    }
+
+    let title = html_escape(&directory_path);
+    format!(
+        r#"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<title>Index of {}</title>
+</head>
+<body>
+<h1>Index of {}</h1>
+<ul>
+{}</ul>
+</body>
+</html>
+"#,
+        title, title, items
+    )
+}
+
+/// Whether the Accept-Encoding headers accept the encoding with a quality over 0
+fn accepts_encoding(request_headers: &HeaderMap, encoding: &str) -> bool {
+    request_headers
+        .get_all(ACCEPT_ENCODING)
+        .iter()
+        .filter_map(|header| header.to_str().ok())
+        .flat_map(|header| header.split(','))
+        .any(|coding| {
+            let mut parts = coding.split(';');
+            let name = parts.next().unwrap_or_default().trim();
+            let quality = parts
+                .find_map(|parameter| parameter.trim().strip_prefix("q="))
+                .and_then(|quality| quality.parse::<f32>().ok())
+                .unwrap_or(1.0);
+            name.eq_ignore_ascii_case(encoding) && quality > 0.0
+        })
+}
+
+/// Decodes a segment of a URL path, None if it is not valid UTF-8 once decoded
+fn percent_decode(segment: &str) -> Option<String> {
+    let bytes = segment.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            let hex = std::str::from_utf8(bytes.get(i + 1..i + 3)?).ok()?;
+            decoded.push(u8::from_str_radix(hex, 16).ok()?);
+            i += 3;
+        } else {
+            decoded.push(bytes[i]);
+            i += 1;
+        }
+    }
+    String::from_utf8(decoded).ok()
+}
+
+/// Encodes every byte of a name but the unreserved characters of RFC 3986, so it can be used as a
+/// segment of a URL path
+fn percent_encode(name: &str) -> String {
+    name.bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                (byte as char).to_string()
+            }
+            _ => format!("%{:02X}", byte),
+        })
+        .collect()
+}
+
+fn html_escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&#39;")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use hyper::header::{
+        ACCEPT, ACCEPT_RANGES, ACCESS_CONTROL_ALLOW_ORIGIN, CONTENT_RANGE, LAST_MODIFIED,
+    };
+    use hyper::Uri;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn well_known_static_file_test() {
+        let public_folder = std::env::temp_dir().join("citrine_well_known_test");
+        let well_known_folder = public_folder.join(".well-known");
+        fs::create_dir_all(&well_known_folder).unwrap();
+        fs::write(
+            well_known_folder.join("openid-configuration"),
+            r#"{"issuer": "https://example.com"}"#,
+        )
+        .unwrap();
+
+        let static_file_servers = [
+            StaticFileServer::new().serve_folder("/", public_folder.clone()),
+            StaticFileServer::new().serve_well_known(well_known_folder.clone()),
+        ];
+        for static_file_server in static_file_servers {
+            let request = RequestMetadata::new(
+                Method::GET,
+                Uri::from_static("/.well-known/openid-configuration"),
+                HeaderMap::new(),
+            );
+            let response = static_file_server.try_serve(&request).await.unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+
+            let body = response.into_body().collect().await.unwrap().to_bytes();
+            assert_eq!(body, r#"{"issuer": "https://example.com"}"#);
+        }
+
+        fs::remove_dir_all(public_folder).unwrap();
+    }
+
+    #[tokio::test]
+    async fn custom_headers_test() {
+        let fonts_folder = std::env::temp_dir().join("citrine_custom_headers_test");
+        fs::create_dir_all(&fonts_folder).unwrap();
+        fs::write(fonts_folder.join("inter.woff2"), "font").unwrap();
+
+        let static_file_server = StaticFileServer::new()
+            .add_folder(
+                ServedFolder::new("/fonts", fonts_folder.clone())
+                    .with_header(ACCESS_CONTROL_ALLOW_ORIGIN, "*")
+                    .with_header(
+                        HeaderName::from_static("cross-origin-resource-policy"),
+                        "cross-origin",
+                    ),
+            )
+            .serve_folder("/assets", fonts_folder.clone());
+
+        let request = RequestMetadata::new(
+            Method::GET,
+            Uri::from_static("/fonts/inter.woff2"),
+            HeaderMap::new(),
+        );
+        let response = static_file_server.try_serve(&request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            response.headers().get(ACCESS_CONTROL_ALLOW_ORIGIN).unwrap(),
+            "*"
+        );
+        assert_eq!(
+            response
+                .headers()
+                .get("cross-origin-resource-policy")
+                .unwrap(),
+            "cross-origin"
+        );
+
+        // The headers only apply to the folder they were added to
+        let request = RequestMetadata::new(
+            Method::GET,
+            Uri::from_static("/assets/inter.woff2"),
+            HeaderMap::new(),
+        );
+        let response = static_file_server.try_serve(&request).await.unwrap();
+        assert!(response
+            .headers()
+            .get(ACCESS_CONTROL_ALLOW_ORIGIN)
+            .is_none());
+
+        fs::remove_dir_all(fonts_folder).unwrap();
+    }
+
+    #[tokio::test]
+    async fn fallback_test() {
+        let app_folder = std::env::temp_dir().join("citrine_fallback_test");
+        fs::create_dir_all(&app_folder).unwrap();
+        fs::write(app_folder.join("index.html"), "spa").unwrap();
+
+        let static_file_server = StaticFileServer::new()
+            .serve_folder("/app", app_folder.clone())
+            .fallback(app_folder.join("index.html"));
+        let request = |path: &'static str| {
+            let mut headers = HeaderMap::new();
+            headers.insert(ACCEPT, HeaderValue::from_static("text/html"));
+            RequestMetadata::new(Method::GET, Uri::from_static(path), headers)
+        };
+
+        let response = static_file_server
+            .try_serve_fallback(&request("/app/dashboard/settings"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        assert_eq!(body, "spa");
+
+        // Missing assets and paths outside the folder are not answered with the index
+        for path in ["/app/main.js", "/apps", "/api/users"] {
+            assert!(static_file_server
+                .try_serve_fallback(&request(path))
+                .await
+                .is_none());
+        }
+
+        fs::remove_dir_all(app_folder).unwrap();
+    }
+
+    #[tokio::test]
+    async fn not_modified_test() {
+        let public_folder = std::env::temp_dir().join("citrine_not_modified_test");
+        fs::create_dir_all(&public_folder).unwrap();
+        fs::write(public_folder.join("app.css"), "body {}").unwrap();
+
+        let static_file_server = StaticFileServer::new().serve_folder("/", public_folder.clone());
+        let request =
+            RequestMetadata::new(Method::GET, Uri::from_static("/app.css"), HeaderMap::new());
+        let response = static_file_server.try_serve(&request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let last_modified = response.headers().get(LAST_MODIFIED).unwrap().clone();
+        let etag = response.headers().get(ETAG).unwrap().clone();
+
+        let mut headers = HeaderMap::new();
+        headers.insert(IF_MODIFIED_SINCE, last_modified.clone());
+        let request = RequestMetadata::new(Method::GET, Uri::from_static("/app.css"), headers);
+        let response = static_file_server.try_serve(&request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+        assert_eq!(response.headers().get(LAST_MODIFIED).unwrap(), last_modified);
+        assert_eq!(response.headers().get(ETAG).unwrap(), etag);
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        assert!(body.is_empty());
+
+        let mut headers = HeaderMap::new();
+        headers.insert(IF_NONE_MATCH, etag.clone());
+        let request = RequestMetadata::new(Method::GET, Uri::from_static("/app.css"), headers);
+        let response = static_file_server.try_serve(&request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+        assert_eq!(response.headers().get(ETAG).unwrap(), etag);
+
+        // If-None-Match wins over If-Modified-Since, so an outdated ETag gets the file
+        let mut headers = HeaderMap::new();
+        headers.insert(IF_NONE_MATCH, HeaderValue::from_static("\"outdated\""));
+        headers.insert(IF_MODIFIED_SINCE, last_modified);
+        let request = RequestMetadata::new(Method::GET, Uri::from_static("/app.css"), headers);
+        let response = static_file_server.try_serve(&request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        fs::remove_dir_all(public_folder).unwrap();
+    }
+
+    #[tokio::test]
+    async fn range_test() {
+        let videos_folder = std::env::temp_dir().join("citrine_range_test");
+        fs::create_dir_all(&videos_folder).unwrap();
+        fs::write(videos_folder.join("intro.mp4"), "0123456789").unwrap();
+
+        let static_file_server =
+            StaticFileServer::new().serve_folder("/videos", videos_folder.clone());
+        let mut headers = HeaderMap::new();
+        headers.insert(RANGE, HeaderValue::from_static("bytes=2-5"));
+        let request =
+            RequestMetadata::new(Method::GET, Uri::from_static("/videos/intro.mp4"), headers);
+        let response = static_file_server.try_serve(&request).await.unwrap();
+
+        assert_eq!(response.status(), StatusCode::PARTIAL_CONTENT);
+        assert_eq!(
+            response.headers().get(CONTENT_RANGE).unwrap(),
+            "bytes 2-5/10"
+        );
+        assert_eq!(response.headers().get(ACCEPT_RANGES).unwrap(), "bytes");
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        assert_eq!(body, "2345");
+
+        fs::remove_dir_all(videos_folder).unwrap();
+    }
+
+    #[tokio::test]
+    async fn cache_control_test() {
+        let public_folder = std::env::temp_dir().join("citrine_cache_control_test");
+        fs::create_dir_all(&public_folder).unwrap();
+        fs::write(public_folder.join("app.3f2a.js"), "app").unwrap();
+        fs::write(public_folder.join("index.html"), "index").unwrap();
+
+        let static_file_server = StaticFileServer::new()
+            .add_folder(
+                ServedFolder::new("/assets", public_folder.clone())
+                    .cache_control(CacheControl::Immutable(86400)),
+            )
+            .add_folder(
+                ServedFolder::new("/", public_folder.clone()).cache_control(CacheControl::NoCache),
+            );
+        for (path, cache_control) in [
+            ("/assets/app.3f2a.js", "public, max-age=86400, immutable"),
+            ("/index.html", "no-cache"),
+        ] {
+            let request =
+                RequestMetadata::new(Method::GET, Uri::from_static(path), HeaderMap::new());
+            let response = static_file_server.try_serve(&request).await.unwrap();
+            assert_eq!(
+                response.headers().get(CACHE_CONTROL).unwrap(),
+                cache_control
+            );
+        }
+
+        fs::remove_dir_all(public_folder).unwrap();
+    }
+
+    #[tokio::test]
+    async fn directory_listing_test() {
+        let share_folder = std::env::temp_dir().join("citrine_directory_listing_test");
+        fs::create_dir_all(share_folder.join("reports")).unwrap();
+        fs::write(share_folder.join("reports").join("q1 <draft>.pdf"), "pdf").unwrap();
+
+        let static_file_server = StaticFileServer::new().add_folder(
+            ServedFolder::new("/share", share_folder.clone()).with_directory_listing(true),
+        );
+        let request = |path: &'static str| {
+            RequestMetadata::new(Method::GET, Uri::from_static(path), HeaderMap::new())
+        };
+
+        let response = static_file_server
+            .try_serve(&request("/share/reports/"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        let body = String::from_utf8(body.to_vec()).unwrap();
+        assert!(body.contains(r#"<a href="/share/">../</a>"#));
+        let link = r#"<a href="/share/reports/q1%20%3Cdraft%3E.pdf">q1 &lt;draft&gt;.pdf</a>"#;
+        assert!(body.contains(link));
+
+        // Paths leaving the folder are not listed, and listing is off by default
+        assert!(static_file_server
+            .try_serve(&request("/share/%2E%2E/"))
+            .await
+            .is_none());
+        let static_file_server =
+            StaticFileServer::new().serve_folder("/share", share_folder.clone());
+        assert!(static_file_server
+            .try_serve(&request("/share/reports/"))
+            .await
+            .is_none());
+
+        fs::remove_dir_all(share_folder).unwrap();
+    }
+
+    #[tokio::test]
+    async fn precompressed_test() {
+        let assets_folder = std::env::temp_dir().join("citrine_precompressed_test");
+        fs::create_dir_all(&assets_folder).unwrap();
+        fs::write(assets_folder.join("app.css"), "plain").unwrap();
+        fs::write(assets_folder.join("app.css.gz"), "gzipped").unwrap();
+        fs::write(assets_folder.join("theme.css"), "theme").unwrap();
+
+        let static_file_server =
+            StaticFileServer::new().serve_folder("/assets", assets_folder.clone());
+        let request = |accept_encoding: &'static str| {
+            let mut headers = HeaderMap::new();
+            headers.insert(ACCEPT_ENCODING, HeaderValue::from_static(accept_encoding));
+            RequestMetadata::new(Method::GET, Uri::from_static("/assets/app.css"), headers)
+        };
+
+        // There is no Brotli sibling, so the gzip one is served
+        let response = static_file_server
+            .try_serve(&request("br, gzip"))
+            .await
+            .unwrap();
+        assert_eq!(response.headers().get(CONTENT_ENCODING).unwrap(), "gzip");
+        assert_eq!(response.headers().get(CONTENT_TYPE).unwrap(), "text/css");
+        assert_eq!(response.headers().get(VARY).unwrap(), "accept-encoding");
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        assert_eq!(body, "gzipped");
+
+        // The plain file varies with Accept-Encoding too, so caches don't serve it to every client
+        let response = static_file_server
+            .try_serve(&request("br, gzip;q=0"))
+            .await
+            .unwrap();
+        assert!(response.headers().get(CONTENT_ENCODING).is_none());
+        assert_eq!(response.headers().get(VARY).unwrap(), "accept-encoding");
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        assert_eq!(body, "plain");
+        let response = static_file_server
+            .try_serve(&RequestMetadata::new(
+                Method::GET,
+                Uri::from_static("/assets/app.css"),
+                HeaderMap::new(),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.headers().get(VARY).unwrap(), "accept-encoding");
+
+        // Files without precompressed siblings don't vary
+        let response = static_file_server
+            .try_serve(&RequestMetadata::new(
+                Method::GET,
+                Uri::from_static("/assets/theme.css"),
+                HeaderMap::new(),
+            ))
+            .await
+            .unwrap();
+        assert!(response.headers().get(VARY).is_none());
+
+        fs::remove_dir_all(assets_folder).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn unreadable_file_test() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let public_folder = std::env::temp_dir().join("citrine_unreadable_file_test");
+        fs::create_dir_all(&public_folder).unwrap();
+        let file = public_folder.join("report.csv");
+        fs::write(&file, "id,total").unwrap();
+        fs::set_permissions(&file, fs::Permissions::from_mode(0o000)).unwrap();
+        // Root reads files whatever their permissions, so there is no unreadable file to serve
+        if fs::File::open(&file).is_ok() {
+            fs::remove_dir_all(public_folder).unwrap();
+            return;
+        }
+
+        let static_file_server = StaticFileServer::new().serve_folder("/", public_folder.clone());
+        let request = |path: &'static str| {
+            RequestMetadata::new(Method::GET, Uri::from_static(path), HeaderMap::new())
+        };
+        let response = static_file_server
+            .try_serve(&request("/report.csv"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+
+        // Missing files still fall through to the router
+        assert!(static_file_server
+            .try_serve(&request("/missing.csv"))
+            .await
+            .is_none());
+
+        fs::remove_dir_all(public_folder).unwrap();
+    }
+}
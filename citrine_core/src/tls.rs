@@ -0,0 +1,101 @@
+use std::{fs::File, io::BufReader, path::PathBuf, sync::Arc, time::Duration};
+
+use tokio_rustls::{rustls::ServerConfig, TlsAcceptor};
+
+use crate::error::ServerError;
+
+/// Time a client has to finish the TLS handshake before its connection is dropped, so idle
+/// connections don't pile up waiting for it
+pub(crate) const TLS_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Certificate chain and private key used to terminate TLS, read from PEM files
+#[derive(Clone, Debug)]
+pub struct TlsConfiguration {
+    cert_path: PathBuf,
+    key_path: PathBuf,
+}
+
+impl TlsConfiguration {
+    pub fn from_pem(cert_path: impl Into<PathBuf>, key_path: impl Into<PathBuf>) -> Self {
+        TlsConfiguration {
+            cert_path: cert_path.into(),
+            key_path: key_path.into(),
+        }
+    }
+
+    /// Loads the certificates and the key. Any problem reading them is returned as an error so
+    /// it is reported on startup
+    pub(crate) fn acceptor(&self) -> Result<TlsAcceptor, ServerError> {
+        let cert_file = File::open(&self.cert_path).map_err(|e| {
+            format!(
+                "Could not open TLS certificate {}: {}",
+                self.cert_path.display(),
+                e
+            )
+        })?;
+        let certs = rustls_pemfile::certs(&mut BufReader::new(cert_file))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| {
+                format!(
+                    "Could not read TLS certificate {}: {}",
+                    self.cert_path.display(),
+                    e
+                )
+            })?;
+        if certs.is_empty() {
+            return Err(ServerError::from(format!(
+                "No certificates found in {}",
+                self.cert_path.display()
+            )));
+        }
+
+        let key_file = File::open(&self.key_path).map_err(|e| {
+            format!(
+                "Could not open TLS private key {}: {}",
+                self.key_path.display(),
+                e
+            )
+        })?;
+        let key = rustls_pemfile::private_key(&mut BufReader::new(key_file))
+            .map_err(|e| {
+                format!(
+                    "Could not read TLS private key {}: {}",
+                    self.key_path.display(),
+                    e
+                )
+            })?
+            .ok_or_else(|| format!("No private key found in {}", self.key_path.display()))?;
+
+        let config = ServerConfig::builder()
+            .with_no_client_auth()
+            .with_single_cert(certs, key)
+            .map_err(|e| format!("Invalid TLS configuration: {}", e))?;
+
+        Ok(TlsAcceptor::from(Arc::new(config)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn missing_certificate_test() {
+        let tls = TlsConfiguration::from_pem("./missing_cert.pem", "./missing_key.pem");
+
+        let error = tls.acceptor().err().unwrap();
+        assert!(error.to_string().contains("missing_cert.pem"));
+    }
+
+    #[test]
+    fn certificate_without_pem_blocks_test() {
+        let cert_path = std::env::temp_dir().join("citrine_tls_test_cert.pem");
+        std::fs::write(&cert_path, "not a certificate").unwrap();
+        let tls = TlsConfiguration::from_pem(&cert_path, "./missing_key.pem");
+
+        let error = tls.acceptor().err().unwrap();
+        assert!(error.to_string().contains("No certificates found"));
+
+        std::fs::remove_file(cert_path).unwrap();
+    }
+}
@@ -1,7 +1,45 @@
+#[cfg(feature = "oidc")]
 use std::{sync::mpsc::{self, TryRecvError}, thread, time::Duration};
 
+use chrono::{DateTime, Utc};
+use hyper::{header::ACCEPT, HeaderMap};
+#[cfg(feature = "oidc")]
 use log::debug;
 
+/// Formats a date as an HTTP date (RFC 9110 IMF-fixdate), as used by headers like Retry-After
+pub fn http_date(date: &DateTime<Utc>) -> String {
+    date.format("%a, %d %b %Y %H:%M:%S GMT").to_string()
+}
+
+/// Whether the Accept header lists text/html, like the requests of browsers navigating
+pub fn accepts_html(headers: &HeaderMap) -> bool {
+    headers
+        .get_all(ACCEPT)
+        .iter()
+        .filter_map(|header| header.to_str().ok())
+        .flat_map(|header| header.split(','))
+        .any(|media_range| {
+            media_range.split(';').next().unwrap_or_default().trim()
+                == mime::TEXT_HTML.essence_str()
+        })
+}
+
+/// Percent-encodes the UTF-8 bytes of the value, keeping ASCII letters, digits and the characters
+/// in `keep` as they are
+pub fn percent_encode(value: &str, keep: &str) -> String {
+    value
+        .bytes()
+        .map(|byte| {
+            if byte.is_ascii_alphanumeric() || keep.as_bytes().contains(&byte) {
+                (byte as char).to_string()
+            } else {
+                format!("%{:02X}", byte)
+            }
+        })
+        .collect()
+}
+
+#[cfg(feature = "oidc")]
 pub fn get_max_age_from_reqwest(headers: &reqwest::header::HeaderMap) -> Option<Duration> {
     let cache_control_header = headers.get("Cache-Control");
     if cache_control_header.is_none() {
@@ -43,9 +81,12 @@ pub fn get_max_age_from_reqwest(headers: &reqwest::header::HeaderMap) -> Option<
     None
 }
 
+#[cfg(feature = "oidc")]
 type Delay = Duration;
+#[cfg(feature = "oidc")]
 type Cancel = Box<dyn Fn() + Send>;
 
+#[cfg(feature = "oidc")]
 pub fn use_repeating_job<F>(job: F) -> Cancel
 where
     F: Fn() -> Delay,
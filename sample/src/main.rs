@@ -64,7 +64,7 @@ async fn main() -> Result<(), ServerError> {
                 user,
                 request.method,
                 request.uri,
-                request.get_body_raw(),
+                request.get_body_for_logging(),
                 response.status,
             )
         })
@@ -79,6 +79,17 @@ async fn main() -> Result<(), ServerError> {
             tera.register_filter("url_encode", url_encode_filter);
             tera
         })
+        // Browsers get an HTML page for missing pages instead of the JSON error body
+        .error_template(StatusCode::NOT_FOUND, "not_found.html")
+        .health_check("/health")
+        // Readiness probes get a 503 when the database can't be reached
+        .readiness_check("/readyz", |context| {
+            context
+                .db
+                .get()
+                .map(|db| db.execute_batch("SELECT 1").is_ok())
+                .unwrap_or(false)
+        })
         .security_configuration(
             SecurityConfiguration::new()
                 // We protect writes in the /api subdomain but allow reads
@@ -111,7 +122,7 @@ async fn main() -> Result<(), ServerError> {
                 //        ).execute_action(SecurityAction::Authenticate(Authenticator::OIDC(OIDCConfiguration::new(
                 //                    HashSet::from([Uri::from_static("http://localhost:9000/realms/test_realm")]),
                 //                    Uri::from_static("http://localhost:9000/realms/test_realm/protocol/openid-connect/certs"),
-                //                    HashSet::from(["account".to_string()])).await)
+                //                    HashSet::from(["account".to_string()])).await?)
                 //)))
                 .add_rule(
                     SecurityRule::new()
@@ -268,7 +279,12 @@ impl Display for SampleError {
 fn base_path_controller(context: Arc<Context>, _: Request) -> Response {
     match find_all_users(&mut context.get_db_connection()) {
         Ok(users) => Response::template("index.html", &UserListResponse { users }).unwrap(),
-        Err(_) => Response::template("error.html", &json!({})).unwrap(),
+        Err(_) => Response::template_with_status(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "error.html",
+            &json!({}),
+        )
+        .unwrap(),
     }
 }
 
@@ -322,7 +338,7 @@ fn delete_by_id_controller(context: Arc<Context>, req: Request) -> Response {
     let id = path_variables.get("id").unwrap();
 
     match delete(id, &mut context.get_db_connection()) {
-        Ok(_) => Response::new(StatusCode::NO_CONTENT),
+        Ok(_) => Response::empty(StatusCode::NO_CONTENT),
         Err(e) => Response::default_error(&e),
     }
 }
@@ -331,7 +347,7 @@ fn create_user_controler(context: Arc<Context>, req: Request) -> Response {
     match req.get_body_validated::<CreateUser>() {
         Ok(create_user_request) => {
             match create(create_user_request.into(), &mut context.get_db_connection()) {
-                Ok(_) => Response::new(StatusCode::NO_CONTENT),
+                Ok(_) => Response::empty(StatusCode::NO_CONTENT),
                 Err(e) => Response::default_error(&e),
             }
         }
@@ -347,7 +363,7 @@ fn update_user_controler(context: Arc<Context>, req: Request) -> Response {
                 update_user_request,
                 &mut context.get_db_connection(),
             ) {
-                Ok(_) => Response::new(StatusCode::NO_CONTENT),
+                Ok(_) => Response::empty(StatusCode::NO_CONTENT),
                 Err(e) => Response::default_error(&e),
             }
         }